@@ -0,0 +1,162 @@
+use crate::config::Config;
+use crate::log;
+use crate::position_parser::{SimulationData, TimePoint, UavId};
+
+use plotters::prelude::*;
+use std::ops::Range;
+use std::path::Path;
+
+const FRAME_SIZE: (u32, u32) = (768, 768);
+
+/// The times to draw frames at, every `interval` seconds from `start` up to and including `end`
+fn frame_times(start: f32, end: f32, interval: f32) -> Vec<f32> {
+    let frames = ((end - start) / interval + 1e-3).floor().max(0.0) as usize + 1;
+    (0..frames).map(|i| start + i as f32 * interval).collect()
+}
+
+/// A square area around every logged position, so the view doesn't move between frames and
+/// distances look the same in both directions
+fn view_bounds(data: &SimulationData) -> (Range<f32>, Range<f32>) {
+    let (mut min_x, mut max_x) = (f32::INFINITY, f32::NEG_INFINITY);
+    let (mut min_y, mut max_y) = (f32::INFINITY, f32::NEG_INFINITY);
+    for (_, positions) in data.timesteps() {
+        for pos in positions.values() {
+            min_x = min_x.min(pos.x);
+            max_x = max_x.max(pos.x);
+            min_y = min_y.min(pos.y);
+            max_y = max_y.max(pos.y);
+        }
+    }
+    let center = ((min_x + max_x) / 2.0, (min_y + max_y) / 2.0);
+    let half_size = ((max_x - min_x).max(max_y - min_y) / 2.0 * 1.1).max(1.0);
+    (
+        (center.0 - half_size)..(center.0 + half_size),
+        (center.1 - half_size)..(center.1 + half_size),
+    )
+}
+
+/// Draws the UAVs at `positions` from above, with the central node in red
+fn draw_frame<DB: DrawingBackend>(
+    area: &DrawingArea<DB, plotters::coord::Shift>,
+    (x_range, y_range): &(Range<f32>, Range<f32>),
+    time: f32,
+    positions: &[(UavId, (f32, f32))],
+    central_node: Option<UavId>,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    DB::ErrorType: 'static,
+{
+    area.fill(&WHITE)?;
+    let mut chart = ChartBuilder::on(area)
+        .caption(format!("t = {:.1}s", time), ("sans-serif", 25))
+        .margin(10u32)
+        .x_label_area_size(40u32)
+        .y_label_area_size(50u32)
+        .build_cartesian_2d(x_range.clone(), y_range.clone())?;
+
+    chart
+        .configure_mesh()
+        .x_desc("X (m)")
+        .y_desc("Y (m)")
+        .label_style(("sans-serif", 15))
+        .draw()?;
+
+    chart.draw_series(positions.iter().map(|(uav, pos)| {
+        if Some(*uav) == central_node {
+            Circle::new(*pos, 7u32, RED.filled())
+        } else {
+            Circle::new(*pos, 5u32, BLUE.filled())
+        }
+    }))?;
+    Ok(())
+}
+
+/// Renders the positions in `positions_file` top-down, one frame every `frame_interval` seconds
+/// or every logged timestep if it isn't set. The frames are written to `output` as a GIF showing
+/// each frame for `frame_delay` milliseconds if it ends in `.gif`, and otherwise as numbered PNGs
+/// in the directory `output`
+pub fn animate(
+    positions_file: &str,
+    output: &str,
+    frame_interval: Option<f32>,
+    frame_delay: u32,
+    config: &Config,
+) -> Result<(), crate::Error> {
+    let mut data = SimulationData::load(positions_file, &config.parser, false)?;
+    let start = match data.timesteps().next() {
+        Some((time, _)) => time.0,
+        None => return Err(format!("{} has no positions", positions_file).into()),
+    };
+    let interval = frame_interval
+        .or_else(|| data.logging_interval())
+        .unwrap_or(1.0);
+    if interval <= 0.0 {
+        return Err("The frame interval must be positive".into());
+    }
+
+    let bounds = view_bounds(&data);
+    let central_node = config.fitness.central_node(&data);
+    let mut uavs: Vec<UavId> = data.uavs.iter().copied().collect();
+    uavs.sort();
+
+    let times = frame_times(start, data.simulation_length, interval);
+    let gif = output.ends_with(".gif");
+    let gif_root = if gif {
+        let backend = BitMapBackend::gif(output, FRAME_SIZE, frame_delay)
+            .map_err(|err| crate::Error::Other(err.into()))?;
+        Some(backend.into_drawing_area())
+    } else {
+        std::fs::create_dir_all(output)?;
+        None
+    };
+    for (frame, &time) in times.iter().enumerate() {
+        let positions: Vec<(UavId, (f32, f32))> = uavs
+            .iter()
+            .filter_map(|uav| {
+                let pos = data.pos_at_time(TimePoint(time), *uav)?;
+                Some((*uav, (pos.x, pos.y)))
+            })
+            .collect();
+        match &gif_root {
+            Some(root) => {
+                draw_frame(root, &bounds, time, &positions, central_node)?;
+                root.present()?;
+            }
+            None => {
+                let path = Path::new(output).join(format!("frame-{:05}.png", frame));
+                let root = BitMapBackend::new(&path, FRAME_SIZE).into_drawing_area();
+                draw_frame(&root, &bounds, time, &positions, central_node)?;
+                root.present()?;
+            }
+        }
+    }
+    log!("Wrote {} frames to {}", times.len(), output);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_timing() {
+        assert_eq!(frame_times(0.0, 1.0, 0.5), vec![0.0, 0.5, 1.0]);
+        assert_eq!(frame_times(0.0, 0.3, 0.1).len(), 4);
+        assert_eq!(frame_times(2.0, 2.0, 0.1), vec![2.0]);
+    }
+
+    #[test]
+    fn square_view() {
+        let csv = "Time (s),IP Address, X (m), Y (m), Z (m)\n\
+                   0,10.1.1.1,0,0,0,\n\
+                   0,10.1.1.2,10,2,0,\n\
+                   1,10.1.1.1,0,0,0,\n\
+                   1,10.1.1.2,10,-2,0,\n";
+        let data = SimulationData::parse(csv, &Default::default()).unwrap();
+        let (x, y) = view_bounds(&data);
+        assert!((x.end - x.start - 11.0).abs() < 1e-4);
+        assert!((y.end - y.start - 11.0).abs() < 1e-4);
+        assert!((x.start + x.end - 10.0).abs() < 1e-4);
+        assert!((y.start + y.end).abs() < 1e-4);
+    }
+}
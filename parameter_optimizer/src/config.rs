@@ -0,0 +1,1245 @@
+use crate::fitness::{FitnessConfig, Sampling, TargetDistance, VelocityMode};
+use crate::position_parser::ParseOptions;
+
+use std::path::{Path, PathBuf};
+
+/// Optimizer settings loaded from the json file passed with `--config`.
+/// Every field has a default, so a config file only needs to list the values it changes
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Prints extra progress messages, such as each runner exiting
+    pub verbose: bool,
+
+    /// Prints the full command line of every simulation in a form that can be pasted into a shell
+    pub log_commands: bool,
+
+    /// Writes one json object per completed sample to stdout, moving progress messages to stderr
+    pub ndjson: bool,
+
+    /// Reports how long was spent simulating, parsing and scoring once the optimizer stops
+    pub profile: bool,
+
+    /// How many of the best parameter sets are printed in a table once the optimizer stops.
+    /// 0 leaves the table out
+    pub summary_rows: usize,
+
+    pub fitness: FitnessConfig,
+    pub plot: PlotConfig,
+    pub parser: ParseOptions,
+    pub failure_monitor: FailureMonitorConfig,
+
+    /// How many seconds of flight each simulation is asked for with `--duration`. This is
+    /// separate from `fitness.fitness_window`, which picks the part of the flight that is scored
+    pub sim_duration: f64,
+
+    /// How many decimal places parameter values are given to the simulation with, and the best
+    /// positions files are named with. Sampled values are rounded before they are recorded, so the
+    /// results match what was simulated. Full precision by default
+    pub positions_precision: Option<usize>,
+
+    /// Simulations that run longer than this many seconds are killed
+    pub simulation_timeout: Option<f64>,
+
+    /// Caps the address space of each simulation at this many megabytes so a runaway simulation
+    /// can't run a shared machine out of memory. Simulations that hit it fail like timeouts.
+    /// Only enforced on Unix
+    pub simulation_memory_limit: Option<u64>,
+
+    /// A command the simulation is run through, followed by the simulation binary and its
+    /// arguments, such as `["nice", "-n", "19"]` or `["taskset", "-c", "0-3"]`. A timed out
+    /// simulation is killed through the wrapper, so wrappers should exec the binary rather than
+    /// forking it
+    pub simulation_wrapper: Vec<String>,
+
+    /// Stops the optimizer once the filesystem holding the simulation's working directory or the
+    /// exported results has less than this many megabytes free, instead of failing every sample
+    /// on a full disk. Only checked on Unix
+    pub min_free_disk_mb: Option<u64>,
+
+    /// Milliseconds to wait between starting each runner, so the simulations' startup load on the
+    /// filesystem and CPU ramps up instead of arriving all at once
+    pub runner_stagger_ms: u64,
+
+    /// Score whatever a killed simulation wrote to its positions file instead of discarding the
+    /// sample
+    pub score_timed_out: bool,
+
+    /// Captures each simulation's stdout and stderr in `<dir>/<positions file name>.log` instead
+    /// of passing them through. Logs of failed samples are kept, and the others are deleted
+    /// unless `keep_all_positions` is set
+    pub sim_log_dir: Option<PathBuf>,
+
+    /// Keeps the positions file and simulation log of every scored sample in the working
+    /// directory instead of deleting them
+    pub keep_all_positions: bool,
+
+    /// Compresses the positions files kept by `keep_all_positions` to `.csv.gz`, which can still
+    /// be given to `analyze` and the other commands that read positions files. Defaults to true
+    pub compress_positions: bool,
+
+    /// Stops the optimizer at the first sample with a NaN or infinite fitness, keeping its
+    /// positions file, instead of counting the sample as failed. Meant for debugging new
+    /// scenarios
+    pub fail_on_nan_fitness: bool,
+
+    /// What to do when a simulation exits successfully but leaves a positions file with no
+    /// positions in it, which usually means it stopped early
+    pub empty_positions: EmptyPositionsPolicy,
+
+    /// Stops the optimizer when the best error hasn't improved for this many evaluations
+    pub patience: Option<usize>,
+
+    /// A fitness to aim for. The status line then shows a rough estimate of how many more
+    /// evaluations it will take to reach it, extrapolated from the best error so far
+    pub target_fitness: Option<f64>,
+
+    /// A shell command run whenever a new best error is found. It gets the error in
+    /// `OPTIMIZER_ERROR`, the parameters as a json object in `OPTIMIZER_PARAMS` and one
+    /// `OPTIMIZER_PARAM_<NAME>` variable each, and the saved positions file in
+    /// `OPTIMIZER_POSITIONS_FILE`
+    pub on_new_best: Option<String>,
+
+    /// The parameters the optimizer searches for
+    pub parameters: Vec<ParameterConfig>,
+
+    /// Parameters that are passed to the simulation together in a single argument
+    pub parameter_groups: Vec<ParameterGroup>,
+
+    /// How the parameters of each simulation are picked
+    pub search: SearchStrategy,
+
+    /// When not empty, every parameter set is simulated once with each of these seeds and scored
+    /// by combining the errors with `seed_reducer`, so the optimizer looks for parameters that
+    /// work across scenarios. Otherwise each parameter set gets one random seed
+    pub scenario_seeds: Vec<usize>,
+
+    pub seed_reducer: SeedReducer,
+
+    /// Settings for the optimizer of every parameter
+    pub tpe: TpeConfig,
+
+    pub warm_start: WarmStartConfig,
+
+    /// A csv of hand picked parameter sets, in the format `replay` takes, that are evaluated and
+    /// told to the optimizers before they pick any parameters themselves. The order is: results
+    /// from `--warm-start` are told first, then the `default` parameters are evaluated, then these
+    /// sets in order, then the `warmup_samples`, and then the search continues as usual. The
+    /// `seed` column is ignored. Not used by grid sweeps
+    pub seed_points: Option<PathBuf>,
+
+    /// How many parameter sets spread over the whole search space by `warmup_sampler` are
+    /// evaluated after the `seed_points` and before the search picks any itself, so TPE and the
+    /// Gaussian process start from a model of the whole space. Not used by grid sweeps
+    pub warmup_samples: usize,
+
+    pub warmup_sampler: WarmupSampler,
+
+    pub results_file: ResultsFile,
+
+    /// The directory the plots, results, backup, manifest and best positions files of a run are
+    /// written to, created if needed. Defaults to a new `run-<unix time>` directory. The grid
+    /// progress file stays in the current directory so `--resume` can find it
+    pub out_dir: Option<PathBuf>,
+
+    /// When set, the optimizer uses this existing NS3 build instead of checking out and building
+    /// the repo itself
+    pub install: Option<InstallConfig>,
+
+    /// When set, simulations run on another machine over SSH instead of locally, and their
+    /// positions files are copied back with scp to be scored here. The local NS3 setup and
+    /// `install` are skipped. Needs the `remote` cargo feature
+    pub remote: Option<RemoteConfig>,
+
+    /// How many times `--use-git` retries cloning the NS3 repo after a network error, waiting
+    /// twice as long before each retry
+    pub git_retries: u32,
+}
+
+/// Locations inside an NS3 build that was made ahead of time, for systems where the optimizer
+/// can't clone or build. Paths may start with `~` and relative paths are relative to the
+/// directory the optimizer is started from
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct InstallConfig {
+    /// The directory the simulation runs in. Temporary positions files are written here too, so
+    /// it must be writable
+    pub working_dir: PathBuf,
+
+    /// The simulation binary to run
+    pub binary: PathBuf,
+
+    /// The directory containing the NS3 shared libraries, added to `LD_LIBRARY_PATH`
+    pub lib_dir: PathBuf,
+}
+
+/// A machine that runs the simulations for `Config::remote`. The remote paths are used as they
+/// are, so they should be absolute
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RemoteConfig {
+    /// The host to run on, as passed to ssh, such as `user@ns3-box` or a `Host` from
+    /// `~/.ssh/config`. It has to log in without a password prompt
+    pub host: String,
+
+    /// Options passed to both ssh and scp before the host, such as `["-i", "~/.ssh/ns3"]`. Use
+    /// `-o Port=...` rather than `-p`, which scp reads differently. Adding
+    /// `["-o", "ControlMaster=auto", "-o", "ControlPath=/tmp/ssh-%r@%h:%p", "-o", "ControlPersist=60"]`
+    /// saves logging in again for every command
+    #[serde(default)]
+    pub ssh_args: Vec<String>,
+
+    /// The directory on the remote host the simulation runs in and writes its positions files to
+    pub working_dir: PathBuf,
+
+    /// The simulation binary on the remote host
+    pub binary: PathBuf,
+
+    /// The directory containing the NS3 shared libraries on the remote host
+    pub lib_dir: PathBuf,
+
+    /// The local directory positions files are copied back to, created if needed. Defaults to
+    /// the directory the optimizer is started from
+    #[serde(default = "default_local_dir")]
+    pub local_dir: PathBuf,
+}
+
+fn default_local_dir() -> PathBuf {
+    PathBuf::from(".")
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            verbose: false,
+            log_commands: false,
+            ndjson: false,
+            profile: false,
+            summary_rows: 10,
+            fitness: FitnessConfig::default(),
+            plot: PlotConfig::default(),
+            parser: ParseOptions::default(),
+            failure_monitor: FailureMonitorConfig::default(),
+            sim_duration: 180.0,
+            positions_precision: None,
+            simulation_timeout: None,
+            simulation_memory_limit: None,
+            simulation_wrapper: Vec::new(),
+            min_free_disk_mb: None,
+            runner_stagger_ms: 0,
+            score_timed_out: false,
+            sim_log_dir: None,
+            keep_all_positions: false,
+            compress_positions: true,
+            fail_on_nan_fitness: false,
+            empty_positions: EmptyPositionsPolicy::Fail,
+            patience: None,
+            target_fitness: None,
+            on_new_best: None,
+            parameters: vec![ParameterConfig::new("a"), ParameterConfig::new("r")],
+            parameter_groups: Vec::new(),
+            search: SearchStrategy::Tpe,
+            scenario_seeds: Vec::new(),
+            seed_reducer: SeedReducer::Mean,
+            tpe: TpeConfig::default(),
+            warm_start: WarmStartConfig::default(),
+            seed_points: None,
+            warmup_samples: 0,
+            warmup_sampler: WarmupSampler::Sobol,
+            results_file: ResultsFile::Timestamped,
+            out_dir: None,
+            install: None,
+            remote: None,
+            git_retries: 3,
+        }
+    }
+}
+
+/// A value the optimizer searches for. It is passed to the simulation as `--{name}={value}`
+/// unless it belongs to a `ParameterGroup`
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ParameterConfig {
+    pub name: String,
+
+    /// Restricts the parameter to this (lowest, highest) part of the search space, such as to
+    /// split a grid sweep between machines. Must be inside the full range. Grid sweeps include
+    /// both ends, TPE only samples below the highest value
+    #[serde(default)]
+    pub range: Option<(f64, f64)>,
+
+    /// A known good value, such as from hand tuning. TPE is seeded with it instead of 1 and the
+    /// first sample of a TPE search uses it. Must be inside the range
+    #[serde(default)]
+    pub default: Option<f64>,
+}
+
+impl ParameterConfig {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            range: None,
+            default: None,
+        }
+    }
+
+    /// The values this parameter is searched over
+    pub fn search_range(&self) -> std::ops::Range<f64> {
+        match self.range {
+            Some((low, high)) => low..high,
+            None => crate::optimization::PARAM_MIN..crate::optimization::PARAM_MAX,
+        }
+    }
+}
+
+/// Several parameters that the simulation takes as one argument, formatted as
+/// `--{arg}={value1}{separator}{value2}...` in the order they are listed in `params`.
+/// Each value is still optimized and recorded on its own
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ParameterGroup {
+    pub arg: String,
+    pub params: Vec<String>,
+    #[serde(default = "default_separator")]
+    pub separator: String,
+}
+
+fn default_separator() -> String {
+    ",".to_owned()
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum SearchStrategy {
+    /// Each parameter's values are picked by its own TPE optimizer using the `tpe` settings
+    Tpe,
+    /// Every cell of an evenly spaced grid with this many values per parameter is evaluated once.
+    /// Progress is saved as cells finish, so an interrupted sweep can be continued with `--resume`
+    Grid { steps: usize },
+    /// A single Gaussian process over all the parameters picks the point with the highest
+    /// expected improvement. It models how parameters interact, which TPE doesn't, and tends to
+    /// need fewer evaluations on smooth objectives with a handful of parameters. Every pick refits
+    /// the process in time cubic in the number of results, so it slows down past several hundred
+    /// evaluations, and it struggles with more than about 10 parameters, where TPE scales better.
+    /// Needs the `gaussian-process` cargo feature
+    GaussianProcess(GaussianProcessConfig),
+}
+
+/// Settings for `SearchStrategy::GaussianProcess`
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct GaussianProcessConfig {
+    /// How many parameter sets are picked at random before the process is used. Defaults to 10
+    pub initial_samples: usize,
+
+    /// How far apart (as a fraction of each parameter's range) two parameter sets can be and
+    /// still have similar errors. Defaults to 0.2
+    pub length_scale: f64,
+
+    /// The noise in the errors as a fraction of their variance, which keeps the process from
+    /// chasing differences between seeds. Defaults to 0.01
+    pub noise: f64,
+
+    /// How many random candidates are scored by expected improvement for each pick. Defaults to
+    /// 1000
+    pub candidates: usize,
+}
+
+impl Default for GaussianProcessConfig {
+    fn default() -> Self {
+        Self {
+            initial_samples: 10,
+            length_scale: 0.2,
+            noise: 0.01,
+            candidates: 1000,
+        }
+    }
+}
+
+/// What happens to an existing `results.csv` when the optimizer finishes
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResultsFile {
+    /// Write `results-{unix time}.csv` so earlier results are never replaced
+    Timestamped,
+    /// Add this run's rows to `results.csv`. The optimizer refuses to start if the existing file
+    /// has different columns
+    Append,
+    /// Replace `results.csv`
+    Overwrite,
+}
+
+/// What to do with a positions file that is empty or only has a header
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmptyPositionsPolicy {
+    /// Count the sample as failed and delete the file, without telling the optimizers about it
+    Fail,
+    /// Stop the optimizer and keep the file, for debugging a scenario that keeps doing it
+    Stop,
+}
+
+/// How the `warmup_samples` are spread over the search space
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum WarmupSampler {
+    /// Each value is picked uniformly at random, which leaves gaps and clumps when there are few
+    /// samples
+    Random,
+    /// A randomly shifted Sobol sequence, which fills the space evenly in every dimension and
+    /// combination of dimensions. Works best with a power of two samples and supports up to 10
+    /// parameters
+    Sobol,
+    /// Each parameter's range is split into one slice per sample and every slice gets exactly one
+    /// sample, paired up at random between parameters
+    LatinHypercube,
+}
+
+/// How the errors of one parameter set over `scenario_seeds` are combined into a single error
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum SeedReducer {
+    Mean,
+    /// The worst error of any seed
+    Max,
+    /// The mean of the worst fraction (0 to 1) of the errors, rounded up to at least one seed
+    Cvar(f64),
+}
+
+impl SeedReducer {
+    /// The error of a sample whose seeds scored `errors`. A NaN from any seed makes the whole
+    /// sample NaN, so `Max` can't hide it
+    pub fn reduce(&self, errors: &[f64]) -> f64 {
+        if errors.iter().any(|error| error.is_nan()) {
+            return f64::NAN;
+        }
+        let mean = |errors: &[f64]| errors.iter().sum::<f64>() / errors.len() as f64;
+        match *self {
+            SeedReducer::Mean => mean(errors),
+            SeedReducer::Max => errors.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+            SeedReducer::Cvar(fraction) => {
+                let mut sorted = errors.to_vec();
+                sorted.sort_by(|a, b| b.total_cmp(a));
+                let count = ((fraction * errors.len() as f64).ceil() as usize).max(1);
+                mean(&sorted[..count.min(sorted.len())])
+            }
+        }
+    }
+}
+
+/// Hyperparameters for the tree-structured Parzen estimator that picks each parameter's values
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct TpeConfig {
+    /// The fraction (0 to 1) of past runs with the lowest error that are treated as "good" when
+    /// picking the next value. Lower values exploit the best region harder, higher values explore
+    /// more. Defaults to 0.1
+    pub gamma: f64,
+
+    /// How many candidate values are sampled from the good distribution before picking the most
+    /// promising one. More candidates means greedier choices. Defaults to 24
+    pub candidates: usize,
+
+    /// How the good and bad distributions are estimated. Defaults to `Parzen`
+    pub estimator: TpeEstimator,
+
+    /// The error each parameter's default value is seeded with. A low error makes TPE explore
+    /// around the defaults. Defaults to 10000, the starting best error
+    pub default_error: Option<f64>,
+}
+
+impl Default for TpeConfig {
+    fn default() -> Self {
+        Self {
+            gamma: 0.1,
+            candidates: 24,
+            estimator: TpeEstimator::Parzen,
+            default_error: None,
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TpeEstimator {
+    /// Kernel density estimation, a smooth estimate that suits continuous parameters
+    Parzen,
+    /// Histogram estimation, which suits parameters that only take a few distinct values. It
+    /// treats each parameter as a category index, so every search range must start at 0 and end
+    /// on a whole number
+    Histogram,
+}
+
+impl TpeConfig {
+    pub fn build_optimizer(
+        &self,
+        range: tpe::range::Range,
+    ) -> Result<tpe::TpeOptimizer, crate::Error> {
+        let estimator = match self.estimator {
+            TpeEstimator::Parzen => tpe::parzen_estimator(),
+            TpeEstimator::Histogram => tpe::histogram_estimator(),
+        };
+        Ok(tpe::TpeOptimizerBuilder::new()
+            .gamma(self.gamma)
+            .candidates(self.candidates)
+            .build(estimator, range)?)
+    }
+}
+
+/// Limits on which past results `--warm-start` feeds to the optimizers, so that results from
+/// before a change to the simulation don't dominate the estimator. The results are picked from
+/// the csv deterministically, so the same csv and settings always give the optimizers the same
+/// history. The settings are recorded in manifest.json with the rest of the config, and a run can
+/// only be reproduced with the same ones
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct WarmStartConfig {
+    /// Only the last this many results in the csv are used
+    pub recent: Option<usize>,
+
+    /// Only results whose error is at most this much above the lowest error among them are used.
+    /// Applied after `recent`
+    pub error_band: Option<f64>,
+}
+
+/// Settings for noticing when most simulations are failing, such as after a bad build
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct FailureMonitorConfig {
+    /// How many of the most recent samples the failure rate is measured over. At most 64
+    pub window: usize,
+
+    /// The failure rate (0 to 1) over the window above which a warning is printed
+    pub max_failure_rate: f64,
+
+    /// Stop the optimizer instead of only warning when the failure rate is exceeded
+    pub abort: bool,
+
+    /// Stop the optimizer once this many samples have panicked while being scored. Panicking
+    /// samples are otherwise skipped like failed ones
+    pub max_panics: Option<usize>,
+}
+
+impl Default for FailureMonitorConfig {
+    fn default() -> Self {
+        Self {
+            window: 20,
+            max_failure_rate: 0.5,
+            abort: false,
+            max_panics: None,
+        }
+    }
+}
+
+/// Settings for the exported graphs
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct PlotConfig {
+    /// Extra space added to each side of the parameter axes, as a fraction of the axis span.
+    /// Keeps points that lie exactly on the edge of the range from being clipped
+    pub axis_margin: f64,
+
+    /// Also draws the hot/cold graph colored by the distance, velocity and stability cost terms
+    pub per_term: bool,
+
+    /// Also draws `trajectory.png`, the path the samples took through the hot/cold graph's
+    /// parameters in the order they were picked
+    pub trajectory: bool,
+
+    /// Also writes the runs binned into a grid over the hot/cold graph's parameters to this csv,
+    /// so the landscape can be re-plotted with other tools
+    pub export_grid: Option<PathBuf>,
+
+    /// How many cells the exported grid has along each parameter
+    pub grid_cells: usize,
+
+    /// Adds each run to the `export_grid` file left by earlier runs instead of replacing it, so
+    /// the grid covers every session even after their results files are gone. The cells then
+    /// span each parameter's configured range rather than the sampled values so they line up
+    /// between sessions, which have to use the same ranges and `grid_cells`. The sessions that
+    /// were added are listed in `<export_grid>.sessions`, so re-exporting their backups doesn't
+    /// count them twice. Backups from before sessions were recorded are added every time
+    pub accumulate_grid: bool,
+
+    /// Colors the hot/cold graph linearly over this (lowest, highest) fitness range instead of by
+    /// the run's own quantiles, so graphs from different runs can be compared. Values outside it
+    /// get the color of the nearest end
+    pub fitness_color_range: Option<(f64, f64)>,
+}
+
+impl Default for PlotConfig {
+    fn default() -> Self {
+        Self {
+            axis_margin: 0.02,
+            per_term: false,
+            trajectory: false,
+            export_grid: None,
+            grid_cells: 32,
+            accumulate_grid: false,
+            fitness_color_range: None,
+        }
+    }
+}
+
+impl Config {
+    /// Loads a config file, which is read as YAML if it ends in `.yaml` or `.yml` and as json
+    /// otherwise
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, crate::Error> {
+        let path = crate::util::resolve_path(path);
+        let text = std::fs::read_to_string(&path)?;
+        let yaml = path
+            .extension()
+            .is_some_and(|extension| extension == "yaml" || extension == "yml");
+        Self::parse(&text, yaml)
+    }
+
+    fn parse(text: &str, yaml: bool) -> Result<Self, crate::Error> {
+        let config = if yaml {
+            serde_yaml::from_str(text).map_err(|err| err.to_string())
+        } else {
+            serde_json::from_str(text).map_err(|err| err.to_string())
+        };
+        config.map_err(crate::Error::Config)
+    }
+
+    /// Restricts the configured parameter `name` to `range`
+    pub fn restrict_parameter(
+        &mut self,
+        name: &str,
+        range: (f64, f64),
+    ) -> Result<(), crate::Error> {
+        let param = self
+            .parameters
+            .iter_mut()
+            .find(|param| param.name == name)
+            .ok_or_else(|| crate::Error::Config(format!("There is no parameter named {}", name)))?;
+        param.range = Some(range);
+        Ok(())
+    }
+
+    /// Overrides one setting from an assignment like `fitness.recovery_weight=200`. The value is
+    /// read as json when it parses as json and as a string otherwise, so `plot.per_term=true`
+    /// and `sim_log_dir=/tmp/logs` both work. Sections that are unset by default, like `install`,
+    /// have to be set as a whole with `install={...}`
+    pub fn set(&mut self, assignment: &str) -> Result<(), crate::Error> {
+        self.set_value(assignment).map_err(crate::Error::Config)
+    }
+
+    fn set_value(&mut self, assignment: &str) -> Result<(), String> {
+        let (key, value) = assignment
+            .split_once('=')
+            .ok_or_else(|| format!("Expected key=value but got {}", assignment))?;
+        let value = serde_json::from_str(value)
+            .unwrap_or_else(|_| serde_json::Value::String(value.to_owned()));
+
+        let mut root = serde_json::to_value(&*self).map_err(|err| err.to_string())?;
+        let mut field = &mut root;
+        for part in key.split('.') {
+            field = match field {
+                serde_json::Value::Object(map) => map.get_mut(part),
+                serde_json::Value::Array(items) => part
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(move |i| items.get_mut(i)),
+                _ => None,
+            }
+            .ok_or_else(|| format!("Unknown config key {}", key))?;
+        }
+        *field = value;
+
+        *self = serde_json::from_value(root)
+            .map_err(|err| format!("Invalid value for {}: {}", key, err))?;
+        Ok(())
+    }
+
+    /// Checks for settings that can't work together
+    pub fn validate(&self) -> Result<(), crate::Error> {
+        self.check_settings().map_err(crate::Error::Config)
+    }
+
+    fn check_settings(&self) -> Result<(), String> {
+        let range = tpe::range(0.0, 1.0).map_err(|err| err.to_string())?;
+        self.tpe
+            .build_optimizer(range)
+            .map_err(|err| err.to_string())?;
+        if self.tpe.estimator == TpeEstimator::Histogram {
+            if let Some(param) = self.parameters.iter().find(|param| {
+                let range = param.search_range();
+                range.start != 0.0 || range.end.fract() != 0.0
+            }) {
+                let range = param.search_range();
+                return Err(format!(
+                    "The histogram estimator needs search ranges from 0 to a whole number, but {} searches {}..{}",
+                    param.name, range.start, range.end
+                ));
+            }
+        }
+        if let Sampling::FixedStep(step) = self.fitness.sampling {
+            if step <= 0.0 {
+                return Err(format!(
+                    "The fitness sampling step must be positive, not {}",
+                    step
+                ));
+            }
+        }
+        if self
+            .simulation_wrapper
+            .first()
+            .is_some_and(|program| program.is_empty())
+        {
+            return Err("The simulation wrapper's program is empty".into());
+        }
+        if let TargetDistance::Schedule { points, .. } = &self.fitness.target_distance {
+            if points.is_empty() {
+                return Err("The target distance schedule has no points".into());
+            }
+            if points.windows(2).any(|pair| pair[0].0 >= pair[1].0) {
+                return Err("The target distance schedule must be sorted by time".into());
+            }
+        }
+        if let Some((low, high)) = self.plot.fitness_color_range {
+            check_color_range(low, high)?;
+        }
+        if self.fitness.stability_window == 0 {
+            return Err("The stability window must be at least 1 timestep".into());
+        }
+        if let Some((uav, weight)) = self
+            .fitness
+            .anchor_nodes
+            .iter()
+            .find(|(_, weight)| !(**weight > 0.0 && weight.is_finite()))
+        {
+            return Err(format!(
+                "Anchor node {} must have a positive weight, not {}",
+                uav, weight
+            ));
+        }
+        if self.fitness.anchor_nodes.len() > 1 && self.fitness.central_outlier_factor.is_some() {
+            return Err("Central outlier detection only works with a single anchor node".into());
+        }
+        if let VelocityMode::Window(window) = self.fitness.velocity_mode {
+            if window <= 0.0 {
+                return Err(format!(
+                    "The velocity window must be positive, not {}",
+                    window
+                ));
+            }
+        }
+        if !(self.sim_duration > 0.0 && self.sim_duration.is_finite()) {
+            return Err(format!(
+                "The simulation duration must be positive, not {}s",
+                self.sim_duration
+            ));
+        }
+        if let Some((start, end)) = self.fitness.fitness_window {
+            if !(start >= 0.0 && start < end) {
+                return Err(format!(
+                    "The fitness window must start at or after 0s and before it ends, not {}s to {}s",
+                    start, end
+                ));
+            }
+            if end > self.sim_duration {
+                return Err(format!(
+                    "The fitness window ends at {}s but simulations only run for {}s",
+                    end, self.sim_duration
+                ));
+            }
+        }
+        if let Some(time) = self.fitness.disturbance_time {
+            if !(time >= 0.0 && time < self.sim_duration) {
+                return Err(format!(
+                    "The disturbance time must be at or after 0s and before the {}s simulations end, not {}s",
+                    self.sim_duration, time
+                ));
+            }
+        }
+        if let Some(window) = self.fitness.steady_state_window {
+            if self.fitness.fitness_window.is_some() {
+                return Err("Only one of fitness_window and steady_state_window can be set".into());
+            }
+            if !(window > 0.0 && window <= self.sim_duration) {
+                return Err(format!(
+                    "The steady state window must be positive and no longer than the {}s simulations, not {}s",
+                    self.sim_duration, window
+                ));
+            }
+        }
+        if let Some(target) = self.target_fitness {
+            if !(target > 0.0 && target.is_finite()) {
+                return Err(format!(
+                    "The target fitness must be positive, not {}",
+                    target
+                ));
+            }
+        }
+        if let SearchStrategy::GaussianProcess(gp) = self.search {
+            if !cfg!(feature = "gaussian-process") {
+                return Err(
+                    "The Gaussian process search needs a build with the gaussian-process feature"
+                        .into(),
+                );
+            }
+            if !(gp.length_scale > 0.0 && gp.noise >= 0.0) {
+                return Err(format!(
+                    "The Gaussian process needs a positive length scale and a non-negative noise, not {} and {}",
+                    gp.length_scale, gp.noise
+                ));
+            }
+        }
+        if self.warmup_sampler == WarmupSampler::Sobol
+            && self.warmup_samples > 0
+            && self.parameters.len() > crate::warmup::SOBOL_MAX_DIMENSIONS
+        {
+            return Err(format!(
+                "The Sobol warmup sampler supports at most {} parameters, not {}",
+                crate::warmup::SOBOL_MAX_DIMENSIONS,
+                self.parameters.len()
+            ));
+        }
+        if let Some(remote) = &self.remote {
+            if !cfg!(feature = "remote") {
+                return Err(
+                    "Running simulations remotely needs a build with the remote feature".into(),
+                );
+            }
+            if remote.host.is_empty() {
+                return Err("The remote host is empty".into());
+            }
+            if self.simulation_memory_limit.is_some() {
+                return Err(
+                    "The simulation memory limit can't be enforced on a remote host".into(),
+                );
+            }
+        }
+        if let SearchStrategy::Grid { steps } = self.search {
+            if steps < 2 {
+                return Err("A grid search needs at least 2 steps".into());
+            }
+            if steps.checked_pow(self.parameters.len() as u32).is_none() {
+                return Err(format!(
+                    "A grid with {} steps over {} parameters has too many cells to count",
+                    steps,
+                    self.parameters.len()
+                ));
+            }
+        }
+
+        if let SeedReducer::Cvar(fraction) = self.seed_reducer {
+            if !(fraction > 0.0 && fraction <= 1.0) {
+                return Err(format!(
+                    "The Cvar seed reducer needs a fraction between 0 and 1, not {}",
+                    fraction
+                ));
+            }
+        }
+
+        // The plots put one parameter on each axis
+        if self.parameters.len() < 2 {
+            return Err(format!(
+                "At least 2 parameters are needed, not {}",
+                self.parameters.len()
+            ));
+        }
+        for (i, param) in self.parameters.iter().enumerate() {
+            if self.parameters[..i]
+                .iter()
+                .any(|other| other.name == param.name)
+            {
+                return Err(format!("Parameter {} is listed more than once", param.name));
+            }
+        }
+        for param in &self.parameters {
+            let full = ParameterConfig::new(&param.name).search_range();
+            let range = param.search_range();
+            if !(range.start < range.end && range.start >= full.start && range.end <= full.end) {
+                return Err(format!(
+                    "The range {}..{} of parameter {} must be non-empty and inside {}..{}",
+                    range.start, range.end, param.name, full.start, full.end
+                ));
+            }
+            if let Some(default) = param.default {
+                if !(range.start..=range.end).contains(&default) {
+                    return Err(format!(
+                        "The default {} of parameter {} is outside its range {}..{}",
+                        default, param.name, range.start, range.end
+                    ));
+                }
+            }
+        }
+
+        for name in self
+            .parameters
+            .iter()
+            .map(|param| &param.name)
+            .chain(self.parameter_groups.iter().map(|group| &group.arg))
+        {
+            if !is_flag_name(name) {
+                return Err(format!(
+                    "{:?} can't be passed to the simulation as --{}=VALUE. Names must start with a \
+                     letter and only contain letters, digits, _ and -",
+                    name, name
+                ));
+            }
+        }
+
+        let mut grouped = Vec::new();
+        for group in &self.parameter_groups {
+            for name in &group.params {
+                if !self.parameters.iter().any(|param| &param.name == name) {
+                    return Err(format!(
+                        "Parameter group {} contains unknown parameter {}",
+                        group.arg, name
+                    ));
+                }
+                if grouped.contains(&name) {
+                    return Err(format!("Parameter {} is in more than one group", name));
+                }
+                grouped.push(name);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn check_color_range(low: f64, high: f64) -> Result<(), String> {
+    if low < high {
+        Ok(())
+    } else {
+        Err(format!(
+            "The color range's lowest value {} must be below its highest value {}",
+            low, high
+        ))
+    }
+}
+
+/// Parses bounds written as `MIN:MAX`
+fn parse_bounds(text: &str) -> Result<(f64, f64), String> {
+    let (low, high) = text
+        .split_once(':')
+        .ok_or_else(|| format!("Expected MIN:MAX, not {:?}", text))?;
+    let parse = |value: &str| {
+        value
+            .trim()
+            .parse::<f64>()
+            .map_err(|err| format!("Invalid bound {:?}: {}", value, err))
+    };
+    Ok((parse(low)?, parse(high)?))
+}
+
+/// Parses a color range written as `MIN:MAX`, such as `0:5000`
+pub fn parse_color_range(text: &str) -> Result<(f64, f64), String> {
+    let (low, high) = parse_bounds(text)?;
+    check_color_range(low, high)?;
+    Ok((low, high))
+}
+
+/// Parses a parameter range override written as `NAME=MIN:MAX`, such as `a=0:9`. The range is
+/// checked against the full range by `Config::validate`
+pub fn parse_param_range(text: &str) -> Result<(String, (f64, f64)), String> {
+    let (name, bounds) = text
+        .split_once('=')
+        .ok_or_else(|| format!("Expected NAME=MIN:MAX, not {:?}", text))?;
+    Ok((name.trim().to_owned(), parse_bounds(bounds)?))
+}
+
+/// If `name` matches `[A-Za-z][A-Za-z0-9_-]*`, so `--{name}=value` is a well formed argument
+fn is_flag_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn group(arg: &str, params: &[&str]) -> ParameterGroup {
+        ParameterGroup {
+            arg: arg.to_owned(),
+            params: params.iter().map(|p| (*p).to_owned()).collect(),
+            separator: default_separator(),
+        }
+    }
+
+    #[test]
+    fn validate_groups() {
+        let mut config = Config::default();
+        assert!(config.validate().is_ok());
+
+        config.parameter_groups = vec![group("gains", &["a", "r"])];
+        assert!(config.validate().is_ok());
+
+        config.parameter_groups = vec![group("gains", &["a", "b"])];
+        assert!(config.validate().is_err());
+
+        config.parameter_groups = vec![group("gains", &["a"]), group("more", &["a", "r"])];
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn color_ranges() {
+        assert_eq!(parse_color_range("0:5000"), Ok((0.0, 5000.0)));
+        assert_eq!(parse_color_range("-1.5: 2e3"), Ok((-1.5, 2000.0)));
+        assert!(parse_color_range("5000").is_err());
+        assert!(parse_color_range("5:1").is_err());
+        assert!(parse_color_range("a:1").is_err());
+
+        let mut config = Config::default();
+        config.set("plot.fitness_color_range=[10, 0]").unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn parameter_ranges() {
+        assert_eq!(parse_param_range("a=0:9"), Ok(("a".to_owned(), (0.0, 9.0))));
+        assert!(parse_param_range("a").is_err());
+        assert!(parse_param_range("a=9").is_err());
+
+        let mut config = Config::default();
+        config.restrict_parameter("a", (9.0, 18.0)).unwrap();
+        assert_eq!(config.parameters[0].search_range(), 9.0..18.0);
+        assert_eq!(config.parameters[1].search_range(), 0.0..18.0);
+        assert!(config.validate().is_ok());
+        assert!(config.restrict_parameter("b", (0.0, 1.0)).is_err());
+
+        for range in [(5.0, 5.0), (6.0, 5.0), (-1.0, 5.0), (0.0, 20.0)] {
+            config.restrict_parameter("a", range).unwrap();
+            assert!(config.validate().is_err(), "{:?}", range);
+        }
+    }
+
+    #[test]
+    fn parameter_defaults() {
+        let mut config = Config::default();
+        config
+            .set(r#"parameters=[{"name":"a","default":4.5},{"name":"r"}]"#)
+            .unwrap();
+        assert_eq!(config.parameters[0].default, Some(4.5));
+        assert!(config.validate().is_ok());
+        config.restrict_parameter("a", (5.0, 9.0)).unwrap();
+        assert!(config.validate().is_err());
+        config.parameters[0].default = Some(9.0);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn validate_names() {
+        let mut config = Config::default();
+        config.parameters.push(ParameterConfig::new("max_speed-2"));
+        assert!(config.validate().is_ok());
+
+        for name in ["", "2a", "-a", "max speed", "a=1", "gain\n"] {
+            config.parameters[2].name = name.to_owned();
+            assert!(config.validate().is_err(), "{:?} was accepted", name);
+        }
+
+        config.parameters.pop();
+        config.parameter_groups = vec![group("gains list", &["a", "r"])];
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn validate_parameter_count() {
+        let mut config = Config::default();
+        config.parameters.push(ParameterConfig::new("a"));
+        assert!(config.validate().is_err());
+
+        config.parameters.truncate(1);
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn validate_tpe() {
+        let mut config = Config::default();
+        config.tpe.gamma = 1.5;
+        assert!(config.validate().is_err());
+
+        config.tpe.gamma = 0.25;
+        config.tpe.candidates = 0;
+        assert!(config.validate().is_err());
+
+        config.tpe.candidates = 8;
+        config.tpe.estimator = TpeEstimator::Histogram;
+        assert!(config.validate().is_ok());
+
+        config.parameters[1].range = Some((1.0, 5.0));
+        assert!(config.validate().is_err());
+        config.parameters[1].range = Some((0.0, 4.5));
+        assert!(config.validate().is_err());
+        config.parameters[1].range = Some((0.0, 4.0));
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn set_fields() {
+        let mut config = Config::default();
+        config.set("fitness.recovery_weight=200").unwrap();
+        assert_eq!(config.fitness.recovery_weight, 200.0);
+        config.set("plot.per_term=true").unwrap();
+        assert!(config.plot.per_term);
+        config.set("simulation_timeout=30").unwrap();
+        assert_eq!(config.simulation_timeout, Some(30.0));
+        config.set("parameters.1.name=gain").unwrap();
+        assert_eq!(config.parameters[1].name, "gain");
+        config.set(r#"search={"Grid":{"steps":5}}"#).unwrap();
+        assert_eq!(config.search, SearchStrategy::Grid { steps: 5 });
+        config.set("sim_log_dir=/tmp/logs").unwrap();
+        assert_eq!(config.sim_log_dir, Some(PathBuf::from("/tmp/logs")));
+        assert!(config.set("install.binary=/opt/sim").is_err());
+        config
+            .set(r#"install={"working_dir":".","binary":"/opt/sim","lib_dir":"lib"}"#)
+            .unwrap();
+        assert_eq!(
+            config.install.as_ref().unwrap().binary,
+            PathBuf::from("/opt/sim")
+        );
+
+        assert!(config.set("fitness.recovery_weigth=200").is_err());
+        assert!(config.set("parameters.5.name=b").is_err());
+        assert!(config.set("fitness.recovery_weight=heavy").is_err());
+        assert!(config.set("verbose").is_err());
+        assert_eq!(config.fitness.recovery_weight, 200.0);
+    }
+
+    #[test]
+    fn validate_sampling() {
+        let mut config = Config::default();
+        config.fitness.sampling = Sampling::FixedStep(0.0);
+        assert!(config.validate().is_err());
+        config.fitness.sampling = Sampling::FixedStep(0.05);
+        assert!(config.validate().is_ok());
+        config.fitness.sampling = Sampling::LoggingInterval;
+        assert!(config.validate().is_ok());
+        config.fitness.velocity_mode = VelocityMode::Window(0.0);
+        assert!(config.validate().is_err());
+        config.fitness.velocity_mode = VelocityMode::Window(0.5);
+        assert!(config.validate().is_ok());
+        config.fitness.stability_window = 0;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn yaml_config() {
+        let yaml = "
+verbose: true
+search:
+  Grid:
+    steps: 3
+fitness:
+  recovery_weight: 200
+  fitness_window: [5, 60]
+parameters:
+  - name: a
+    range: [0, 9]
+";
+        let json = r#"{
+            "verbose": true,
+            "search": { "Grid": { "steps": 3 } },
+            "fitness": { "recovery_weight": 200, "fitness_window": [5, 60] },
+            "parameters": [{ "name": "a", "range": [0, 9] }]
+        }"#;
+        let from_yaml = Config::parse(yaml, true).unwrap();
+        let from_json = Config::parse(json, false).unwrap();
+        assert_eq!(
+            serde_json::to_value(&from_yaml).unwrap(),
+            serde_json::to_value(&from_json).unwrap()
+        );
+        assert_eq!(from_yaml.fitness.recovery_weight, 200.0);
+        assert!(Config::parse("fitness:\n  recovery_weigth: 200\n", true).is_err());
+        assert!(Config::parse(yaml, false).is_err());
+    }
+
+    #[test]
+    fn validate_fitness_window() {
+        let mut config = Config::default();
+        config.set("fitness.fitness_window=[5, 60]").unwrap();
+        assert!(config.validate().is_ok());
+        config.set("fitness.fitness_window=[60, 5]").unwrap();
+        assert!(config.validate().is_err());
+        config.set("fitness.fitness_window=[-1, 5]").unwrap();
+        assert!(config.validate().is_err());
+
+        // The scored part has to fit in the simulation, which can run longer than it
+        config.set("fitness.fitness_window=[5, 200]").unwrap();
+        assert!(config.validate().is_err());
+        config.set("sim_duration=360").unwrap();
+        assert!(config.validate().is_ok());
+
+        config.set("fitness.steady_state_window=30").unwrap();
+        assert!(config.validate().is_err());
+        config.set("fitness.fitness_window=null").unwrap();
+        assert!(config.validate().is_ok());
+        config.set("fitness.steady_state_window=400").unwrap();
+        assert!(config.validate().is_err());
+        config.set("fitness.steady_state_window=null").unwrap();
+
+        config.set("fitness.disturbance_time=120").unwrap();
+        assert!(config.validate().is_ok());
+        config.set("fitness.disturbance_time=360").unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn validate_schedule() {
+        let mut config = Config::default();
+        config
+            .set(r#"fitness.target_distance={"Schedule":{"points":[[0,5],[60,10]],"interpolation":"Linear"}}"#)
+            .unwrap();
+        assert!(config.validate().is_ok());
+        config
+            .set(r#"fitness.target_distance={"Schedule":{"points":[[60,5],[0,10]],"interpolation":"Step"}}"#)
+            .unwrap();
+        assert!(config.validate().is_err());
+        config
+            .set(r#"fitness.target_distance={"Schedule":{"points":[],"interpolation":"Step"}}"#)
+            .unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn validate_grid() {
+        let mut config = Config {
+            search: SearchStrategy::Grid { steps: 1 },
+            ..Config::default()
+        };
+        assert!(config.validate().is_err());
+
+        config.search = SearchStrategy::Grid { steps: 10 };
+        assert!(config.validate().is_ok());
+
+        config.search = SearchStrategy::Grid { steps: 1 << 33 };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn validate_gaussian_process() {
+        let mut config = Config::default();
+        config
+            .set(r#"search={"GaussianProcess":{"length_scale":0.1}}"#)
+            .unwrap();
+        assert_eq!(
+            config.validate().is_ok(),
+            cfg!(feature = "gaussian-process")
+        );
+        config
+            .set(r#"search={"GaussianProcess":{"length_scale":0}}"#)
+            .unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn seed_reducers() {
+        let errors = [4.0, 1.0, 10.0, 5.0];
+        assert_eq!(SeedReducer::Mean.reduce(&errors), 5.0);
+        assert_eq!(SeedReducer::Max.reduce(&errors), 10.0);
+        assert_eq!(SeedReducer::Cvar(0.5).reduce(&errors), 7.5);
+        assert_eq!(SeedReducer::Cvar(0.1).reduce(&errors), 10.0);
+        assert_eq!(SeedReducer::Cvar(1.0).reduce(&errors), 5.0);
+        assert!(SeedReducer::Max.reduce(&[1.0, f64::NAN]).is_nan());
+        assert!(SeedReducer::Cvar(0.5).reduce(&[f64::NAN, 1.0]).is_nan());
+
+        let mut config = Config {
+            seed_reducer: SeedReducer::Cvar(0.0),
+            ..Config::default()
+        };
+        assert!(config.validate().is_err());
+        config.seed_reducer = SeedReducer::Cvar(0.25);
+        assert!(config.validate().is_ok());
+    }
+}
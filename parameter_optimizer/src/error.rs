@@ -0,0 +1,157 @@
+use crate::git::GitError;
+use crate::position_parser::ParseError;
+
+use std::fmt;
+
+/// Everything that can stop the optimizer, sorted by the phase it went wrong in so callers can
+/// tell a broken NS3 checkout from a bad positions file without matching on messages
+#[derive(Debug)]
+pub enum OptimizerError {
+    /// Cloning or checking out the NS3 repo failed
+    Setup(GitError),
+    /// Configuring or building NS3 failed
+    Build(String),
+    /// A positions file isn't valid
+    Parse(ParseError),
+    /// A simulation couldn't be run or didn't leave anything to score
+    Simulation(String),
+    /// The config file or the command line is invalid
+    Config(String),
+    /// Reading or writing a file failed
+    Io(std::io::Error),
+    /// Anything else, such as a plot that couldn't be drawn or a backup that couldn't be decoded
+    Other(Box<dyn std::error::Error>),
+}
+
+impl fmt::Display for OptimizerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OptimizerError::Setup(err) => err.fmt(f),
+            OptimizerError::Build(message)
+            | OptimizerError::Simulation(message)
+            | OptimizerError::Config(message) => f.write_str(message),
+            OptimizerError::Parse(err) => err.fmt(f),
+            OptimizerError::Io(err) => err.fmt(f),
+            OptimizerError::Other(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for OptimizerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            OptimizerError::Setup(err) => Some(err),
+            OptimizerError::Parse(err) => Some(err),
+            OptimizerError::Io(err) => Some(err),
+            OptimizerError::Other(err) => err.source(),
+            OptimizerError::Build(_)
+            | OptimizerError::Simulation(_)
+            | OptimizerError::Config(_) => None,
+        }
+    }
+}
+
+impl From<GitError> for OptimizerError {
+    fn from(err: GitError) -> Self {
+        OptimizerError::Setup(err)
+    }
+}
+
+impl From<ParseError> for OptimizerError {
+    fn from(err: ParseError) -> Self {
+        OptimizerError::Parse(err)
+    }
+}
+
+impl From<std::io::Error> for OptimizerError {
+    fn from(err: std::io::Error) -> Self {
+        OptimizerError::Io(err)
+    }
+}
+
+impl From<Box<dyn std::error::Error>> for OptimizerError {
+    fn from(err: Box<dyn std::error::Error>) -> Self {
+        OptimizerError::Other(err)
+    }
+}
+
+/// Errors from dependencies that don't belong to a particular phase end up in `Other`
+macro_rules! other_errors {
+    ($($error:ty),* $(,)?) => {
+        $(
+            impl From<$error> for OptimizerError {
+                fn from(err: $error) -> Self {
+                    OptimizerError::Other(Box::new(err))
+                }
+            }
+        )*
+    };
+}
+
+other_errors!(
+    serde_json::Error,
+    serde_yaml::Error,
+    bincode::Error,
+    std::num::ParseFloatError,
+    std::num::ParseIntError,
+    std::string::FromUtf8Error,
+    tpe::BuildError,
+    tpe::TellError,
+);
+
+impl<E: std::error::Error + Send + Sync + 'static> From<plotters::drawing::DrawingAreaErrorKind<E>>
+    for OptimizerError
+{
+    fn from(err: plotters::drawing::DrawingAreaErrorKind<E>) -> Self {
+        OptimizerError::Other(Box::new(err))
+    }
+}
+
+impl From<std::convert::Infallible> for OptimizerError {
+    fn from(err: std::convert::Infallible) -> Self {
+        match err {}
+    }
+}
+
+impl From<String> for OptimizerError {
+    fn from(message: String) -> Self {
+        OptimizerError::Other(message.into())
+    }
+}
+
+impl From<&str> for OptimizerError {
+    fn from(message: &str) -> Self {
+        OptimizerError::Other(message.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn phases() {
+        let err: OptimizerError = ParseError::NoPositions.into();
+        assert!(matches!(
+            err,
+            OptimizerError::Parse(ParseError::NoPositions)
+        ));
+        assert_eq!(err.to_string(), "No positions in file");
+
+        let err: OptimizerError = std::io::Error::from(std::io::ErrorKind::NotFound).into();
+        assert!(matches!(err, OptimizerError::Io(_)));
+
+        let mut config = crate::config::Config::default();
+        config.fitness.stability_window = 0;
+        let err = config.validate().unwrap_err();
+        assert!(matches!(err, OptimizerError::Config(_)));
+        assert_eq!(
+            err.to_string(),
+            "The stability window must be at least 1 timestep"
+        );
+
+        let err: OptimizerError = "anything else".into();
+        assert!(matches!(err, OptimizerError::Other(_)));
+        assert_eq!(err.to_string(), "anything else");
+    }
+}
@@ -0,0 +1,1957 @@
+use crate::log;
+use crate::position_parser::{SimulationData, TimePoint, UavId};
+
+use glam::Vec3A;
+use indexmap::IndexMap;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// Decides whether the spread of the peripheral distances at a single timestep counts as stable
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum StabilityCriterion {
+    /// Stable when the mean absolute deviation of the distances is below this many meters
+    AbsoluteMad(f64),
+    /// Stable when the mean absolute deviation of the distances is below this percentage of their
+    /// mean
+    PercentCv(f64),
+}
+
+impl StabilityCriterion {
+    /// NaN inputs (too few nodes for a distance) are never stable
+    fn is_stable(&self, mad: f64, mean: f64) -> bool {
+        match *self {
+            StabilityCriterion::AbsoluteMad(meters) => mad < meters,
+            StabilityCriterion::PercentCv(percent) => 100.0 * mad / mean < percent,
+        }
+    }
+}
+
+/// The distance the peripheral nodes should keep from the central node
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub enum TargetDistance {
+    /// A fixed distance in meters
+    Fixed(f64),
+    /// The mean distance to the central node at the start of the simulation, so the swarm is
+    /// rewarded for keeping the spacing it was spawned with
+    InitialSpacing,
+    /// A distance that changes during the simulation, given as `(seconds, meters)` points sorted
+    /// by time. The first and last distances hold before and after the points. Each timestep is
+    /// compared to the distance scheduled for it instead of comparing the run's mean distance
+    /// to a single target
+    Schedule {
+        points: Vec<(f64, f64)>,
+        interpolation: Interpolation,
+    },
+}
+
+/// How a `TargetDistance::Schedule` gets from one point to the next
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interpolation {
+    /// Each distance holds until the time of the next point
+    Step,
+    /// The distance changes linearly between points
+    Linear,
+}
+
+/// The distance scheduled at `time` by `points`, which must not be empty
+fn scheduled_distance(points: &[(f64, f64)], interpolation: Interpolation, time: f64) -> f64 {
+    let next = points.partition_point(|(point_time, _)| *point_time <= time);
+    if next == 0 {
+        return points[0].1;
+    }
+    let (start_time, start_distance) = points[next - 1];
+    match (interpolation, points.get(next)) {
+        (Interpolation::Linear, Some((end_time, end_distance))) => {
+            let fraction = (time - start_time) / (end_time - start_time);
+            start_distance + (end_distance - start_distance) * fraction
+        }
+        _ => start_distance,
+    }
+}
+
+/// How the cost terms are combined into the error score
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Normalization {
+    /// The terms are summed as they are
+    Raw,
+    /// Each term is scaled by the range of values it has taken so far in this run before being
+    /// weighted with `FitnessConfig::term_weights`, so equal weights mean equal importance.
+    /// Whenever a range grows, every earlier result is scored again against the new ranges and
+    /// the optimizers are told the new errors, so the errors of a run share one scale. They are
+    /// still only comparable within a single optimization run
+    PopulationRange,
+}
+
+/// How the per-timestep mean distances to the central node are combined into the distance that
+/// `central_distance_cost` compares to the target
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum DistanceAggregation {
+    Mean,
+    /// The most dispersed timestep
+    Max,
+    /// The 95th percentile of the timesteps, which targets the worst moments of dispersion
+    /// without depending on a single timestep
+    P95,
+}
+
+impl DistanceAggregation {
+    fn apply(&self, values: &[f64]) -> f64 {
+        match self {
+            DistanceAggregation::Mean => rgsl::statistics::mean(values, 1, values.len()),
+            DistanceAggregation::Max => values.iter().copied().fold(f64::NAN, f64::max),
+            DistanceAggregation::P95 => {
+                let mut sorted: Vec<f64> = values.iter().copied().filter(|v| !v.is_nan()).collect();
+                if sorted.is_empty() {
+                    return f64::NAN;
+                }
+                sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                let rank = (0.95 * sorted.len() as f64).ceil() as usize;
+                sorted[rank.clamp(1, sorted.len()) - 1]
+            }
+        }
+    }
+}
+
+/// What happens to a run whose positions file doesn't have `FitnessConfig::expected_uavs` UAVs
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum UavCountMismatch {
+    /// The sample is thrown away like a failed simulation
+    Reject,
+    /// The run is scored anyway, with this much added to `uav_count_cost` for each UAV too many
+    /// or too few
+    Penalty(f64),
+}
+
+/// What happens at timesteps where the central node is far from the rest of the swarm, see
+/// `FitnessConfig::central_outlier_factor`
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CentralOutlierAction {
+    /// The timestep is left out of the distances and the stability streaks, like a sparse one
+    Exclude,
+    /// The peripheral UAV closest to the swarm's centroid stands in as the central node for the
+    /// timestep, and the usual central node is scored as a peripheral one
+    Reselect,
+    /// The timestep is scored as usual. The run is only flagged with a warning and the count in
+    /// `CostBreakdown::central_outlier_timesteps`
+    Flag,
+}
+
+/// How each UAV's velocity is measured for `velocity_cost`
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum VelocityMode {
+    /// The distance moved since the previous scored timestep, divided by the time between them
+    PerStep,
+    /// The net distance moved over the last this many seconds, divided by the time it covers. Less
+    /// sensitive to the time step and to noise in the logged positions than `PerStep`. Until a
+    /// whole window has passed the time since the UAV was first scored is used
+    Window(f64),
+}
+
+/// Which points in time a simulation run is scored at
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Sampling {
+    /// Every UAV's position is interpolated at fixed steps of this many seconds
+    FixedStep(f64),
+    /// Like `FixedStep`, with the step set to the interval the positions file was logged at so
+    /// that no logged data is skipped. Falls back to 0.1 seconds when the file has a single
+    /// timestep
+    LoggingInterval,
+    /// The timesteps recorded in the positions file are used as they are. UAVs that weren't
+    /// logged at a timestep keep their last logged position, and only the logged ones contribute
+    /// a velocity
+    Recorded,
+}
+
+/// Settings that control how a simulation run is scored
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct FitnessConfig {
+    /// The time (in seconds) at which the scenario injects a disturbance, such as a node failure.
+    /// When set, a second stable time is measured using only the timesteps after this point
+    pub disturbance_time: Option<f64>,
+
+    pub target_distance: TargetDistance,
+
+    /// How the distance to the central node is combined over the run. For a
+    /// `TargetDistance::Schedule` it combines the per-timestep errors from the schedule instead.
+    /// Defaults to the mean
+    pub distance_aggregation: DistanceAggregation,
+
+    /// How much each meter the central distance is below `target_distance` costs. Setting it
+    /// above `above_target_weight` penalizes a swarm that packs too tightly, and risks collisions,
+    /// more than one that spreads too far. Defaults to 400, the same as `above_target_weight`
+    pub below_target_weight: f64,
+
+    /// How much each meter the central distance is above `target_distance` costs. Defaults to 400
+    pub above_target_weight: f64,
+
+    /// How much each second spent re-stabilizing after `disturbance_time` costs
+    pub recovery_weight: f64,
+
+    /// What counts as a stable timestep when measuring stable times
+    pub stability_criterion: StabilityCriterion,
+
+    /// How many scored timesteps, ending with the current one, the peripheral distances are pooled
+    /// over before `stability_criterion` is applied. Longer windows make the stable time less
+    /// sensitive to noise at single timesteps. Defaults to 1, which judges each timestep on its own
+    pub stability_window: usize,
+
+    pub normalization: Normalization,
+
+    /// Weights for each cost term by name when using `Normalization::PopulationRange`. Terms that
+    /// are not listed get a weight of 1.0
+    pub term_weights: IndexMap<String, f64>,
+
+    /// How much each UAV's distances count towards the distance means and MADs, keyed by IP
+    /// address. Distances between two peripheral UAVs are weighted by the product of their
+    /// weights. UAVs that are not listed get a weight of 1.0
+    pub uav_weights: IndexMap<UavId, f64>,
+
+    /// When not empty, only these UAVs are scored, keyed by IP address. The central node is the
+    /// lowest address that is scored
+    pub include_uavs: Vec<UavId>,
+
+    /// UAVs that are left out of every distance and velocity, such as one that is intentionally
+    /// uncontrolled. Applied after `include_uavs`
+    pub exclude_uavs: Vec<UavId>,
+
+    /// Velocities (in m/s) above this are clamped to it before averaging, so a UAV that jumps
+    /// because of a respawn or a logging glitch can't dominate `velocity_cost`. No cap by default
+    pub velocity_cap: Option<f64>,
+
+    /// Defaults to `PerStep`
+    pub velocity_mode: VelocityMode,
+
+    pub sampling: Sampling,
+
+    /// Only the part of the run from the first to the second time (in seconds) is scored, leaving
+    /// out the initial transient and anything odd at the end of the run. Every cost term, stable
+    /// time and dropout count is measured over just this part. Runs that end before it does are
+    /// rejected. It has to end by `sim_duration`. The whole run is scored by default
+    pub fitness_window: Option<(f64, f64)>,
+
+    /// Only the last this many seconds of each run are scored, for judging the steady state
+    /// alone. Unlike `fitness_window` it follows the length of each run. Can't be combined with
+    /// `fitness_window`
+    pub steady_state_window: Option<f64>,
+
+    /// Timesteps before this many seconds are left out of the mean velocity, so the UAVs
+    /// accelerating from rest at the start don't count towards `velocity_cost`. When no timesteps
+    /// are after it the velocity cost is 0
+    pub velocity_start_time: f64,
+
+    /// Whether the central node's own speed counts towards `velocity_cost`, and its acceleration
+    /// towards `control_effort_cost`, along with the peripheral nodes'. It is left out of every
+    /// distance either way. Defaults to true
+    pub include_central_in_velocity: bool,
+
+    /// How much losing every UAV costs. The cost is this times the fraction of the UAVs logged at
+    /// the start that are no longer logged at the end
+    pub dropout_weight: f64,
+
+    /// UAVs logged within this many seconds of the first or last timestep count as present at
+    /// the start or the end when measuring dropouts
+    pub survival_window: f64,
+
+    /// How much each m/s² of mean acceleration costs, as a proxy for control effort that favors
+    /// smooth controllers. Accelerations are measured from the same timesteps as velocities, after
+    /// `velocity_cap` is applied
+    pub control_effort_weight: f64,
+
+    /// The formation the UAVs should fly in, as each UAV's offset from the others keyed by IP
+    /// address. At every scored timestep the offsets are moved, and turned if
+    /// `formation_rotation` is set, to fit the UAVs' positions as closely as possible, and the
+    /// root mean square distance left between each UAV and its offset counts towards
+    /// `formation_cost`. UAVs without an offset are left out. Off when empty, which is the default
+    pub target_formation: IndexMap<UavId, Vec3A>,
+
+    /// Lets `target_formation` be turned about the vertical axis when fitting it, so only the
+    /// shape of the formation matters and not its heading. Defaults to false
+    pub formation_rotation: bool,
+
+    /// How much each meter of mean distance from `target_formation` costs. Defaults to 400, the
+    /// same as the distance terms
+    pub formation_weight: f64,
+
+    /// How many UAVs every positions file should have, counted before `include_uavs` and
+    /// `exclude_uavs` are applied. Catches truncated simulations, which otherwise tend to score
+    /// misleadingly well. Not checked by default
+    pub expected_uavs: Option<usize>,
+
+    /// What happens to runs that don't have `expected_uavs` UAVs. Defaults to rejecting them
+    pub uav_count_mismatch: UavCountMismatch,
+
+    /// Timesteps where fewer scored UAVs than this have a position, counting the central node,
+    /// are left out of the distances and the stability streaks, since a handful of UAVs gives
+    /// noisy statistics. Their velocities still count. Defaults to 2, the fewest that give a
+    /// distance
+    pub min_uavs_per_timestep: usize,
+
+    /// Since the central node is just the lowest address, it can be the UAV that drifts away,
+    /// which makes every distance to it meaningless. When set, the central node counts as an
+    /// outlier at timesteps where its distance to the centroid of all the UAVs is more than this
+    /// many mean absolute deviations above the peripheral UAVs' mean distance to it. Not checked
+    /// by default
+    pub central_outlier_factor: Option<f64>,
+
+    /// What happens at timesteps where the central node is an outlier. Defaults to excluding them
+    pub central_outlier_action: CentralOutlierAction,
+
+    /// The nodes distances are measured from, keyed by IP address, with how much each one's mean
+    /// distance to the other UAVs counts towards the distance compared to `target_distance`.
+    /// Anchors are left out of the peripheral distances, and anchors that aren't scored are
+    /// ignored. Defaults to the central node alone
+    pub anchor_nodes: IndexMap<UavId, f64>,
+}
+
+impl FitnessConfig {
+    fn uav_weight(&self, uav: &UavId) -> f64 {
+        self.uav_weights.get(uav).copied().unwrap_or(1.0)
+    }
+
+    /// If `uav` passes the `include_uavs` and `exclude_uavs` filters
+    fn scores_uav(&self, uav: &UavId) -> bool {
+        (self.include_uavs.is_empty() || self.include_uavs.contains(uav))
+            && !self.exclude_uavs.contains(uav)
+    }
+
+    /// How many UAVs `data` has more or fewer than `expected_uavs`
+    fn uav_count_difference(&self, data: &SimulationData) -> usize {
+        self.expected_uavs
+            .map_or(0, |expected| expected.abs_diff(data.uavs.len()))
+    }
+
+    /// Checks that `data` can be scored. It must have an anchor node, have `expected_uavs` UAVs if
+    /// mismatched runs are rejected, and last until the end of `fitness_window` or at least as
+    /// long as `steady_state_window`
+    pub fn check_data(&self, data: &SimulationData) -> Result<(), String> {
+        if self.anchors(data).is_empty() {
+            return Err(
+                "None of the anchor nodes are scored UAVs in the positions file".to_owned(),
+            );
+        }
+        if self.uav_count_mismatch == UavCountMismatch::Reject
+            && self.uav_count_difference(data) != 0
+        {
+            return Err(format!(
+                "Expected {} UAVs but the positions file has {}",
+                self.expected_uavs.unwrap(),
+                data.uavs.len()
+            ));
+        }
+        if let Some((_, end)) = self.fitness_window {
+            // Allows for the simulation length being stored as an f32
+            if end > data.simulation_length as f64 + 1e-3 {
+                return Err(format!(
+                    "The fitness window ends at {}s but the simulation is only {}s long",
+                    end, data.simulation_length
+                ));
+            }
+        }
+        if let Some(window) = self.steady_state_window {
+            if window > data.simulation_length as f64 + 1e-3 {
+                return Err(format!(
+                    "The steady state window is {}s but the simulation is only {}s long",
+                    window, data.simulation_length
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// The part of `data` that is scored, from `fitness_window` or `steady_state_window`. `None`
+    /// when the whole run is
+    fn scored_window(&self, data: &SimulationData) -> Option<(f64, f64)> {
+        let end = data.simulation_length as f64;
+        self.fitness_window.or_else(|| {
+            self.steady_state_window
+                .map(|window| ((end - window).max(0.0), end))
+        })
+    }
+
+    /// The node the others are scored against, which is the scored UAV with the lowest address.
+    /// `None` when the filters leave no UAVs
+    pub fn central_node(&self, data: &SimulationData) -> Option<UavId> {
+        data.uavs
+            .iter()
+            .copied()
+            .filter(|uav| self.scores_uav(uav))
+            .min()
+    }
+
+    /// The scored `anchor_nodes` in `data` with their weights, or the central node with a weight
+    /// of 1.0 when no anchors are configured
+    pub fn anchors(&self, data: &SimulationData) -> Vec<(UavId, f64)> {
+        if self.anchor_nodes.is_empty() {
+            return self
+                .central_node(data)
+                .map(|uav| vec![(uav, 1.0)])
+                .unwrap_or_default();
+        }
+        self.anchor_nodes
+            .iter()
+            .filter(|(uav, _)| data.uavs.contains(uav) && self.scores_uav(uav))
+            .map(|(uav, weight)| (*uav, *weight))
+            .collect()
+    }
+}
+
+/// Combines the mean distance to each anchor with the anchor's weight, given as
+/// `(mean, weight)` pairs. Anchors without a distance are left out, and NaN is returned when none
+/// have one
+fn combine_anchor_distances(means: &[(f64, f64)]) -> f64 {
+    let (sum, total_weight) = means
+        .iter()
+        .filter(|(mean, _)| !mean.is_nan())
+        .fold((0.0, 0.0), |(sum, total), (mean, weight)| {
+            (sum + mean * weight, total + weight)
+        });
+    sum / total_weight
+}
+
+impl Default for FitnessConfig {
+    fn default() -> Self {
+        Self {
+            disturbance_time: None,
+            target_distance: TargetDistance::Fixed(7.5),
+            distance_aggregation: DistanceAggregation::Mean,
+            below_target_weight: 400.0,
+            above_target_weight: 400.0,
+            recovery_weight: 400.0,
+            stability_criterion: StabilityCriterion::PercentCv(30.0),
+            stability_window: 1,
+            normalization: Normalization::Raw,
+            term_weights: IndexMap::new(),
+            uav_weights: IndexMap::new(),
+            include_uavs: Vec::new(),
+            exclude_uavs: Vec::new(),
+            velocity_cap: None,
+            velocity_mode: VelocityMode::PerStep,
+            sampling: Sampling::FixedStep(0.1),
+            fitness_window: None,
+            steady_state_window: None,
+            velocity_start_time: 0.0,
+            include_central_in_velocity: true,
+            dropout_weight: 0.0,
+            survival_window: 1.0,
+            control_effort_weight: 0.0,
+            target_formation: IndexMap::new(),
+            formation_rotation: false,
+            formation_weight: 400.0,
+            expected_uavs: None,
+            uav_count_mismatch: UavCountMismatch::Reject,
+            min_uavs_per_timestep: 2,
+            central_outlier_factor: None,
+            central_outlier_action: CentralOutlierAction::Exclude,
+            anchor_nodes: IndexMap::new(),
+        }
+    }
+}
+
+/// The individual terms that make up the error score of a run
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default)]
+pub struct CostBreakdown {
+    pub p_mad_cost: f64,
+    pub central_distance_cost: f64,
+    pub velocity_cost: f64,
+    pub recovery_cost: f64,
+    pub dropout_cost: f64,
+    pub control_effort_cost: f64,
+    pub uav_count_cost: f64,
+    pub formation_cost: f64,
+
+    /// The distance to the central node that `central_distance_cost` was measured against. For a
+    /// `TargetDistance::Schedule` this is the mean of the scheduled distances
+    pub target_distance: f64,
+
+    /// The time the swarm became stable and stayed that way until the end of the run
+    pub stable_time: f64,
+
+    /// How many seconds after the disturbance it took the swarm to become stable again.
+    /// `None` when no disturbance time is configured
+    pub recovery_stable_time: Option<f64>,
+
+    /// How many scored UAVs were logged near the start and the end of the run
+    pub initial_uavs: usize,
+    pub surviving_uavs: usize,
+
+    /// How many scored timesteps the central node was an outlier at, when
+    /// `FitnessConfig::central_outlier_factor` is set
+    pub central_outlier_timesteps: usize,
+}
+
+impl CostBreakdown {
+    /// The named cost terms that are summed into the total
+    pub fn terms(&self) -> Vec<(&'static str, f64)> {
+        vec![
+            ("p_mad_cost", self.p_mad_cost),
+            ("central_distance_cost", self.central_distance_cost),
+            ("velocity_cost", self.velocity_cost),
+            ("recovery_cost", self.recovery_cost),
+            ("dropout_cost", self.dropout_cost),
+            ("control_effort_cost", self.control_effort_cost),
+            ("uav_count_cost", self.uav_count_cost),
+            ("formation_cost", self.formation_cost),
+        ]
+    }
+
+    /// The error score that is given to the optimizer when using `Normalization::Raw`
+    pub fn total(&self) -> f64 {
+        self.terms().iter().map(|(_, cost)| cost).sum()
+    }
+}
+
+/// The smallest and largest value seen for each cost term so far
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default, Clone)]
+pub struct TermRanges {
+    ranges: IndexMap<String, (f64, f64)>,
+}
+
+impl TermRanges {
+    /// Widens the ranges to include the terms of `breakdown`, returning whether any of them grew
+    pub fn update(&mut self, breakdown: &CostBreakdown) -> bool {
+        let mut grew = false;
+        for (name, cost) in breakdown.terms() {
+            if !cost.is_finite() {
+                continue;
+            }
+            let range = self.ranges.entry(name.to_owned()).or_insert((cost, cost));
+            if cost < range.0 || cost > range.1 {
+                grew = true;
+            }
+            range.0 = range.0.min(cost);
+            range.1 = range.1.max(cost);
+        }
+        grew
+    }
+
+    /// Sums the terms of `breakdown` after mapping each one into 0..1 using its range.
+    /// Terms that haven't varied yet contribute nothing
+    pub fn normalized_total(
+        &self,
+        breakdown: &CostBreakdown,
+        weights: &IndexMap<String, f64>,
+    ) -> f64 {
+        breakdown
+            .terms()
+            .iter()
+            .map(|(name, cost)| {
+                let weight = weights.get(*name).copied().unwrap_or(1.0);
+                match self.ranges.get(*name) {
+                    Some((min, max)) if max > min => weight * (cost - min) / (max - min),
+                    _ => 0.0,
+                }
+            })
+            .sum()
+    }
+}
+
+/// The root mean square distance between each UAV's position and its target offset, given as
+/// `(position, offset)` pairs, once the offsets are moved to fit the positions as closely as
+/// possible. With `rotate` they are also turned about the vertical axis to fit, which for a
+/// rotation about a single axis has a closed form
+fn formation_error(formation: &[(Vec3A, Vec3A)], rotate: bool) -> f64 {
+    let count = formation.len() as f32;
+    let (position_sum, offset_sum) = formation.iter().fold(
+        (Vec3A::ZERO, Vec3A::ZERO),
+        |(positions, offsets), (position, offset)| (positions + *position, offsets + *offset),
+    );
+    let (position_center, offset_center) = (position_sum / count, offset_sum / count);
+    let centered: Vec<(Vec3A, Vec3A)> = formation
+        .iter()
+        .map(|(position, offset)| (*position - position_center, *offset - offset_center))
+        .collect();
+    let angle = if rotate {
+        let (cross, dot) = centered
+            .iter()
+            .fold((0.0, 0.0), |(cross, dot), (position, offset)| {
+                (
+                    cross + (offset.x * position.y - offset.y * position.x) as f64,
+                    dot + (offset.x * position.x + offset.y * position.y) as f64,
+                )
+            });
+        cross.atan2(dot)
+    } else {
+        0.0
+    };
+    let (sin, cos) = angle.sin_cos();
+    let squared_distances: f64 = centered
+        .iter()
+        .map(|(position, offset)| {
+            let (x, y) = (offset.x as f64, offset.y as f64);
+            let fitted = Vec3A::new(
+                (cos * x - sin * y) as f32,
+                (sin * x + cos * y) as f32,
+                offset.z,
+            );
+            (*position - fitted).length_squared() as f64
+        })
+        .sum();
+    (squared_distances / formation.len() as f64).sqrt()
+}
+
+/// If the timestep at `time` is inside the scored `window`
+fn in_window(window: Option<(f64, f64)>, time: f64) -> bool {
+    match window {
+        Some((start, end)) => start <= time && time <= end,
+        None => true,
+    }
+}
+
+/// Counts the `uavs` logged within `window` seconds of the first timestep and of the last one in
+/// the fitness window
+fn count_survivors(
+    data: &SimulationData,
+    uavs: &[UavId],
+    window: f64,
+    config: &FitnessConfig,
+) -> (usize, usize) {
+    let scored_window = config.scored_window(data);
+    let scored = || {
+        data.timesteps()
+            .map(|(time, recorded)| (time.0 as f64, recorded))
+            .filter(|(time, _)| in_window(scored_window, *time))
+    };
+    let start = match scored().next() {
+        Some((time, _)) => time,
+        None => return (0, 0),
+    };
+    let end = scored().last().unwrap().0;
+    let mut initial = HashSet::new();
+    let mut surviving = HashSet::new();
+    for (time, recorded) in scored() {
+        for uav in recorded.keys().filter(|uav| uavs.contains(uav)) {
+            if time <= start + window {
+                initial.insert(*uav);
+            }
+            if time >= end - window {
+                surviving.insert(*uav);
+            }
+        }
+    }
+    (initial.len(), surviving.len())
+}
+
+/// The mean of `points`, which must not be empty
+fn centroid(points: &[(UavId, Vec3A)]) -> Vec3A {
+    points.iter().fold(Vec3A::ZERO, |sum, (_, pos)| sum + *pos) / points.len() as f32
+}
+
+/// The smallest spread `is_central_outlier` measures against, as a fraction of the mean distance.
+/// Regular formations have no spread at all, and any central node would stand out from them
+const MIN_OUTLIER_SPREAD: f64 = 0.1;
+
+/// Whether `central` is more than `factor` mean absolute deviations further from the centroid of
+/// all the UAVs than the peripheral UAVs are on average. Never true with no peripheral UAVs
+fn is_central_outlier(central: Vec3A, peripheral: &[(UavId, Vec3A)], factor: f64) -> bool {
+    if peripheral.is_empty() {
+        return false;
+    }
+    let center =
+        (central + centroid(peripheral) * peripheral.len() as f32) / (peripheral.len() + 1) as f32;
+    let distances: Vec<f64> = peripheral
+        .iter()
+        .map(|(_, pos)| (*pos - center).length() as f64)
+        .collect();
+    let mean = rgsl::statistics::mean(&distances, 1, distances.len());
+    let mad = rgsl::statistics::absdev(&distances, 1, distances.len());
+    let spread = mad.max(MIN_OUTLIER_SPREAD * mean);
+    (central - center).length() as f64 > mean + factor * spread
+}
+
+/// Tracks the start of the current unbroken run of stable timesteps
+#[derive(Default)]
+struct StableStreak {
+    since: Option<f64>,
+}
+
+impl StableStreak {
+    fn update(&mut self, time: f64, stable: bool) {
+        if !stable {
+            self.since = None;
+        } else if self.since.is_none() {
+            self.since = Some(time);
+        }
+    }
+}
+
+/// Distances and the weight of each one, for means and MADs that respect `uav_weights`.
+/// Plain unweighted statistics are used when every weight is 1.0
+struct WeightedDistances {
+    values: Vec<f64>,
+    weights: Vec<f64>,
+    weighted: bool,
+}
+
+impl WeightedDistances {
+    fn new(weighted: bool) -> Self {
+        Self {
+            values: Vec::new(),
+            weights: Vec::new(),
+            weighted,
+        }
+    }
+
+    fn push(&mut self, value: f64, weight: f64) {
+        self.values.push(value);
+        self.weights.push(weight);
+    }
+
+    fn extend(&mut self, other: &WeightedDistances) {
+        self.values.extend(&other.values);
+        self.weights.extend(&other.weights);
+    }
+
+    fn mean(&self) -> f64 {
+        if self.weighted {
+            rgsl::statistics::wmean(&self.weights, 1, &self.values, 1, self.values.len())
+        } else {
+            rgsl::statistics::mean(&self.values, 1, self.values.len())
+        }
+    }
+
+    fn mad(&self) -> f64 {
+        if self.weighted {
+            rgsl::statistics::wabsdev(&self.weights, 1, &self.values, 1, self.values.len())
+        } else {
+            rgsl::statistics::absdev(&self.values, 1, self.values.len())
+        }
+    }
+}
+
+/// The per-timestep statistics that `get_error` collects over a run
+struct Timesteps<'a> {
+    config: &'a FitnessConfig,
+    /// Every scored UAV in a fixed order so that the sums are the same every time a file is scored
+    uavs: Vec<UavId>,
+    /// The nodes distances are measured from and their weights, see `FitnessConfig::anchors`
+    anchors: Vec<(UavId, f64)>,
+    weighted: bool,
+
+    /// Each UAV's recent positions, starting with the last one at or before the start of the
+    /// velocity window
+    last_poses: HashMap<UavId, VecDeque<(Vec3A, f32)>>,
+    last_velocities: HashMap<UavId, (Vec3A, f32)>,
+    all_central_distances: Vec<f64>,
+    /// The time of each entry in `all_central_distances`
+    all_times: Vec<f64>,
+    all_peripheral_distances: Vec<f64>,
+    all_velocities: Vec<f64>,
+    all_accelerations: Vec<f64>,
+    /// The distance from `target_formation` at each scored timestep
+    all_formation_errors: Vec<f64>,
+    /// The peripheral distances of the last `stability_window` scored timesteps
+    recent_peripheral_distances: VecDeque<WeightedDistances>,
+    streak: StableStreak,
+    recovery_streak: StableStreak,
+    clamped_velocities: usize,
+    /// Timesteps left out of the distances for having fewer than `min_uavs_per_timestep` UAVs
+    sparse_timesteps: usize,
+    /// Timesteps where the central node was an outlier, see `central_outlier_factor`
+    central_outlier_timesteps: usize,
+    /// The part of the run that is scored, see `FitnessConfig::scored_window`
+    window: Option<(f64, f64)>,
+}
+
+impl<'a> Timesteps<'a> {
+    fn new(data: &SimulationData, config: &'a FitnessConfig) -> Self {
+        let mut uavs: Vec<UavId> = data
+            .uavs
+            .iter()
+            .copied()
+            .filter(|uav| config.scores_uav(uav))
+            .collect();
+        uavs.sort();
+        Self {
+            config,
+            window: config.scored_window(data),
+            anchors: config.anchors(data),
+            uavs,
+            weighted: config.uav_weights.values().any(|weight| *weight != 1.0),
+            last_poses: HashMap::new(),
+            last_velocities: HashMap::new(),
+            all_central_distances: Vec::new(),
+            all_times: Vec::new(),
+            all_peripheral_distances: Vec::new(),
+            all_velocities: Vec::new(),
+            all_accelerations: Vec::new(),
+            all_formation_errors: Vec::new(),
+            recent_peripheral_distances: VecDeque::new(),
+            streak: StableStreak::default(),
+            recovery_streak: StableStreak::default(),
+            clamped_velocities: 0,
+            sparse_timesteps: 0,
+            central_outlier_timesteps: 0,
+        }
+    }
+
+    /// Adds the timestep at `time` where the UAVs are at `positions`. Velocities are only
+    /// measured for the UAVs in `moved`, which are the ones whose positions are new at this time
+    fn add(&mut self, time: f32, positions: &HashMap<UavId, Vec3A>, moved: &HashMap<UavId, Vec3A>) {
+        let config = self.config;
+        if !in_window(self.window, time as f64) {
+            return;
+        }
+        let mut anchors: Vec<(UavId, Vec3A, f64)> = self
+            .anchors
+            .iter()
+            .filter_map(|(uav, weight)| Some((*uav, *positions.get(uav)?, *weight)))
+            .collect();
+        if anchors.is_empty() {
+            return;
+        }
+        let mut peripheral_distances = WeightedDistances::new(self.weighted);
+        let mut velocities: Vec<f64> = Vec::new();
+        let mut accelerations: Vec<f64> = Vec::new();
+        let present = self
+            .uavs
+            .iter()
+            .filter(|uav| positions.contains_key(uav))
+            .count();
+        let sparse = present < config.min_uavs_per_timestep;
+        let mut excluded = false;
+        // Only a single central node is checked, `Config::validate` rejects several anchors
+        if let (Some(factor), false, [(central_node, central_pos, _)]) =
+            (config.central_outlier_factor, sparse, anchors.as_slice())
+        {
+            let peripheral: Vec<(UavId, Vec3A)> = self
+                .uavs
+                .iter()
+                .filter(|uav| *uav != central_node)
+                .filter_map(|uav| Some((*uav, *positions.get(uav)?)))
+                .collect();
+            if is_central_outlier(*central_pos, &peripheral, factor) {
+                self.central_outlier_timesteps += 1;
+                match config.central_outlier_action {
+                    CentralOutlierAction::Exclude => excluded = true,
+                    CentralOutlierAction::Reselect => {
+                        let center = centroid(&peripheral);
+                        let (uav, pos) = peripheral
+                            .iter()
+                            .copied()
+                            .min_by(|(_, a), (_, b)| {
+                                (*a - center).length().total_cmp(&(*b - center).length())
+                            })
+                            .unwrap();
+                        anchors[0] = (uav, pos, 1.0);
+                    }
+                    CentralOutlierAction::Flag => {}
+                }
+            }
+        }
+        let is_anchor = |uav: &UavId| anchors.iter().any(|(anchor, _, _)| anchor == uav);
+        let mut anchor_distances: Vec<WeightedDistances> = anchors
+            .iter()
+            .map(|_| WeightedDistances::new(self.weighted))
+            .collect();
+
+        for uav in &self.uavs {
+            if let Some(now_pos) = positions.get(uav).copied() {
+                let measure_velocity = config.include_central_in_velocity
+                    || !self.anchors.iter().any(|(anchor, _)| anchor == uav);
+                if measure_velocity && moved.contains_key(uav) {
+                    let window = match config.velocity_mode {
+                        VelocityMode::PerStep => 0.0,
+                        VelocityMode::Window(window) => window as f32,
+                    };
+                    let history = self.last_poses.entry(*uav).or_default();
+                    while history.len() > 1 && history[1].1 <= time - window {
+                        history.pop_front();
+                    }
+                    if let Some((last_pos, last_time)) = history.front() {
+                        let pos_delta = now_pos - *last_pos;
+                        let time_delta = time - last_time;
+                        let mut velocity: Vec3A = pos_delta / time_delta;
+                        let mut speed = velocity.length() as f64;
+                        if let Some(cap) = config.velocity_cap {
+                            if speed > cap {
+                                velocity *= (cap / speed) as f32;
+                                speed = cap;
+                                self.clamped_velocities += 1;
+                            }
+                        }
+                        velocities.push(speed);
+                        if let Some((last_velocity, last_time)) = self.last_velocities.get(uav) {
+                            let acceleration = (velocity - *last_velocity) / (time - last_time);
+                            accelerations.push(acceleration.length() as f64);
+                        }
+                        self.last_velocities.insert(*uav, (velocity, time));
+                    }
+                    history.push_back((now_pos, time));
+                }
+                if !sparse && !excluded && !is_anchor(uav) {
+                    let weight = config.uav_weight(uav);
+                    for ((_, anchor_pos, _), distances) in anchors.iter().zip(&mut anchor_distances)
+                    {
+                        distances.push((now_pos - *anchor_pos).length() as f64, weight);
+                    }
+                    for uav_2 in &self.uavs {
+                        if uav != uav_2 && !is_anchor(uav_2) {
+                            //Calculate the distance between this node and every other peripheral node
+                            if let Some(now_2_pos) = positions.get(uav_2) {
+                                peripheral_distances.push(
+                                    (*now_2_pos - now_pos).length() as f64,
+                                    weight * config.uav_weight(uav_2),
+                                );
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        let mean_velocity = rgsl::statistics::mean(&velocities, 1, velocities.len());
+        let mean_acceleration = rgsl::statistics::mean(&accelerations, 1, accelerations.len());
+        if time as f64 >= config.velocity_start_time {
+            self.all_velocities.push(mean_velocity);
+            if !accelerations.is_empty() {
+                self.all_accelerations.push(mean_acceleration);
+            }
+        }
+        if sparse {
+            self.sparse_timesteps += 1;
+            return;
+        }
+        if excluded {
+            return;
+        }
+        let formation: Vec<(Vec3A, Vec3A)> = config
+            .target_formation
+            .iter()
+            .filter(|(uav, _)| config.scores_uav(uav))
+            .filter_map(|(uav, offset)| Some((*positions.get(uav)?, *offset)))
+            .collect();
+        if !formation.is_empty() {
+            self.all_formation_errors
+                .push(formation_error(&formation, config.formation_rotation));
+        }
+
+        let anchor_means: Vec<(f64, f64)> = anchor_distances
+            .iter()
+            .zip(&anchors)
+            .map(|(distances, (_, _, weight))| (distances.mean(), *weight))
+            .collect();
+        let central_distances_mean = combine_anchor_distances(&anchor_means);
+        let peripheral_distances_mean = peripheral_distances.mean();
+
+        self.recent_peripheral_distances
+            .push_back(peripheral_distances);
+        if self.recent_peripheral_distances.len() > config.stability_window {
+            self.recent_peripheral_distances.pop_front();
+        }
+        let mut pooled = WeightedDistances::new(self.weighted);
+        for distances in &self.recent_peripheral_distances {
+            pooled.extend(distances);
+        }
+        let stable = config
+            .stability_criterion
+            .is_stable(pooled.mad(), pooled.mean());
+        self.streak.update(time as f64, stable);
+        if let Some(disturbance_time) = config.disturbance_time {
+            if time as f64 >= disturbance_time {
+                self.recovery_streak.update(time as f64, stable);
+            }
+        }
+
+        self.all_central_distances.push(central_distances_mean);
+        self.all_times.push(time as f64);
+        self.all_peripheral_distances
+            .push(peripheral_distances_mean);
+    }
+}
+
+pub fn get_error(data: &mut SimulationData, config: &FitnessConfig) -> CostBreakdown {
+    let mut timesteps = Timesteps::new(data, config);
+    let fixed_step = match config.sampling {
+        Sampling::FixedStep(step) => Some(step as f32),
+        Sampling::LoggingInterval => Some(data.logging_interval().unwrap_or(0.1)),
+        Sampling::Recorded => None,
+    };
+    match fixed_step {
+        Some(time_step) => {
+            let mut time = 0.0;
+            while time <= data.simulation_length {
+                let positions: HashMap<UavId, Vec3A> = timesteps
+                    .uavs
+                    .iter()
+                    .filter_map(|uav| Some((*uav, data.pos_at_time(TimePoint(time), *uav)?)))
+                    .collect();
+                timesteps.add(time, &positions, &positions);
+                time += time_step;
+            }
+        }
+        None => {
+            let mut positions = HashMap::new();
+            for (time, recorded) in data.timesteps() {
+                positions.extend(recorded.iter().map(|(uav, pos)| (*uav, *pos)));
+                timesteps.add(time.0, &positions, recorded);
+            }
+        }
+    }
+    let (initial_uavs, surviving_uavs) =
+        count_survivors(data, &timesteps.uavs, config.survival_window, config);
+    let uav_count_cost = match config.uav_count_mismatch {
+        UavCountMismatch::Penalty(penalty) => {
+            let difference = config.uav_count_difference(data);
+            if difference != 0 {
+                log!(
+                    "WARNING: Expected {} UAVs but the positions file has {}",
+                    config.expected_uavs.unwrap(),
+                    data.uavs.len()
+                );
+            }
+            penalty * difference as f64
+        }
+        UavCountMismatch::Reject => 0.0,
+    };
+    let Timesteps {
+        all_central_distances,
+        all_times,
+        all_peripheral_distances,
+        all_velocities,
+        all_accelerations,
+        all_formation_errors,
+        streak,
+        recovery_streak,
+        clamped_velocities,
+        sparse_timesteps,
+        central_outlier_timesteps,
+        ..
+    } = timesteps;
+
+    let mean_velocity: f64 = if all_velocities.is_empty() {
+        0.0
+    } else {
+        all_velocities.iter().sum::<f64>() / all_velocities.len() as f64
+    };
+
+    let mean_acceleration: f64 = if all_accelerations.is_empty() {
+        0.0
+    } else {
+        all_accelerations.iter().sum::<f64>() / all_accelerations.len() as f64
+    };
+
+    let mean_formation_error: f64 = if all_formation_errors.is_empty() {
+        0.0
+    } else {
+        all_formation_errors.iter().sum::<f64>() / all_formation_errors.len() as f64
+    };
+
+    let central_distance = config.distance_aggregation.apply(&all_central_distances);
+
+    let mad_of_peripheral_distance: f64 =
+        rgsl::statistics::absdev(&all_peripheral_distances, 1, all_peripheral_distances.len());
+
+    log!("central: {central_distance}, c mad: {mad_of_peripheral_distance}");
+    if clamped_velocities > 0 {
+        log!(
+            "Clamped {} velocities to the velocity cap",
+            clamped_velocities
+        );
+    }
+    if sparse_timesteps > 0 {
+        log!(
+            "Left {} timesteps with fewer than {} UAVs out of the distances",
+            sparse_timesteps,
+            config.min_uavs_per_timestep
+        );
+    }
+
+    if central_outlier_timesteps > 0 {
+        log!(
+            "WARNING: The central node was far from the swarm at {} timesteps ({:?})",
+            central_outlier_timesteps,
+            config.central_outlier_action
+        );
+    }
+
+    let distance_cost = |target: f64, distance: f64| {
+        let weight = if distance < target {
+            config.below_target_weight
+        } else {
+            config.above_target_weight
+        };
+        weight * (target - distance).abs()
+    };
+    let (target_distance, central_distance_cost) = match &config.target_distance {
+        TargetDistance::Fixed(distance) => (*distance, distance_cost(*distance, central_distance)),
+        TargetDistance::InitialSpacing => {
+            // NaN when every timestep was too sparse to measure
+            let distance = all_central_distances.first().copied().unwrap_or(f64::NAN);
+            (distance, distance_cost(distance, central_distance))
+        }
+        TargetDistance::Schedule {
+            points,
+            interpolation,
+        } => {
+            let targets: Vec<f64> = all_times
+                .iter()
+                .map(|time| scheduled_distance(points, *interpolation, *time))
+                .collect();
+            let costs: Vec<f64> = targets
+                .iter()
+                .zip(&all_central_distances)
+                .map(|(target, distance)| distance_cost(*target, *distance))
+                .collect();
+            (
+                rgsl::statistics::mean(&targets, 1, targets.len()),
+                config.distance_aggregation.apply(&costs),
+            )
+        }
+    };
+
+    let scored_end = timesteps
+        .window
+        .map_or(data.simulation_length as f64, |(_, end)| end);
+    let stable_time = streak.since.unwrap_or(scored_end);
+    // A run that ends before the disturbance, like one that timed out, hasn't recovered any
+    // faster than one that stabilized right at it
+    let recovery_stable_time = config.disturbance_time.map(|disturbance_time| {
+        (recovery_streak.since.unwrap_or(scored_end) - disturbance_time).max(0.0)
+    });
+
+    let dropout_fraction = if initial_uavs == 0 {
+        0.0
+    } else {
+        (1.0 - surviving_uavs as f64 / initial_uavs as f64).max(0.0)
+    };
+
+    CostBreakdown {
+        p_mad_cost: 400.0 * mad_of_peripheral_distance,
+        central_distance_cost,
+        velocity_cost: 250.0 * mean_velocity,
+        recovery_cost: config.recovery_weight * recovery_stable_time.unwrap_or(0.0),
+        dropout_cost: config.dropout_weight * dropout_fraction,
+        control_effort_cost: config.control_effort_weight * mean_acceleration,
+        uav_count_cost,
+        formation_cost: config.formation_weight * mean_formation_error,
+        target_distance,
+        stable_time,
+        recovery_stable_time,
+        initial_uavs,
+        surviving_uavs,
+        central_outlier_timesteps,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a positions file with a central node and three peripheral nodes in a triangle.
+    /// Between 5 and 7 seconds one of the peripheral nodes is knocked far away
+    fn disturbed_triangle() -> SimulationData {
+        let mut csv = String::from("Time (s),IP Address, X (m), Y (m), Z (m)\n");
+        for (time, far) in [
+            (0.0, false),
+            (4.9, false),
+            (5.0, true),
+            (6.9, true),
+            (7.0, false),
+            (10.0, false),
+        ] {
+            let x = if far { 100.0 } else { 0.0 };
+            csv.push_str(&format!("{},10.1.1.1,0,0,0,\n", time));
+            csv.push_str(&format!("{},10.1.1.2,1,0,0,\n", time));
+            csv.push_str(&format!("{},10.1.1.3,-0.5,0.866,0,\n", time));
+            csv.push_str(&format!("{},10.1.1.4,{},-0.866,0,\n", time, x - 0.5));
+        }
+        SimulationData::parse(&csv, &Default::default()).unwrap()
+    }
+
+    #[test]
+    fn recovery_stable_time() {
+        let breakdown = get_error(&mut disturbed_triangle(), &FitnessConfig::default());
+        assert_eq!(breakdown.recovery_stable_time, None);
+        assert_eq!(breakdown.recovery_cost, 0.0);
+        assert!((breakdown.stable_time - 7.0).abs() < 0.15);
+
+        let config = FitnessConfig {
+            disturbance_time: Some(5.0),
+            ..FitnessConfig::default()
+        };
+        let breakdown = get_error(&mut disturbed_triangle(), &config);
+        let recovery = breakdown.recovery_stable_time.unwrap();
+        assert!((recovery - 2.0).abs() < 0.15);
+        assert_eq!(breakdown.recovery_cost, config.recovery_weight * recovery);
+
+        let late = FitnessConfig {
+            disturbance_time: Some(20.0),
+            ..FitnessConfig::default()
+        };
+        let breakdown = get_error(&mut disturbed_triangle(), &late);
+        assert_eq!(breakdown.recovery_stable_time, Some(0.0));
+        assert_eq!(breakdown.recovery_cost, 0.0);
+    }
+
+    #[test]
+    fn asymmetric_distance_cost() {
+        let mut csv = String::from("Time (s),IP Address, X (m), Y (m), Z (m)\n");
+        for time in [0.0, 1.0] {
+            csv.push_str(&format!("{},10.1.1.1,0,0,0,\n", time));
+            csv.push_str(&format!("{},10.1.1.2,2,0,0,\n", time));
+            csv.push_str(&format!("{},10.1.1.3,0,2,0,\n", time));
+        }
+        let data = || SimulationData::parse(&csv, &Default::default()).unwrap();
+        let config = FitnessConfig {
+            below_target_weight: 1000.0,
+            above_target_weight: 100.0,
+            ..FitnessConfig::default()
+        };
+
+        // 2 m from the central node is 5.5 m closer than the default 7.5 m target
+        let below = get_error(&mut data(), &config);
+        assert!((below.central_distance_cost - 1000.0 * 5.5).abs() < 1e-3);
+
+        let config = FitnessConfig {
+            target_distance: TargetDistance::Fixed(1.5),
+            ..config
+        };
+        let above = get_error(&mut data(), &config);
+        assert!((above.central_distance_cost - 100.0 * 0.5).abs() < 1e-3);
+
+        let config = FitnessConfig {
+            target_distance: TargetDistance::Schedule {
+                points: vec![(0.0, 3.0)],
+                interpolation: Interpolation::Step,
+            },
+            ..config
+        };
+        let scheduled = get_error(&mut data(), &config);
+        assert!((scheduled.central_distance_cost - 1000.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn initial_spacing_target() {
+        let mut csv = String::from("Time (s),IP Address, X (m), Y (m), Z (m)\n");
+        for time in [0.0, 1.0] {
+            csv.push_str(&format!("{},10.1.1.1,0,0,0,\n", time));
+            csv.push_str(&format!("{},10.1.1.2,2,0,0,\n", time));
+            csv.push_str(&format!("{},10.1.1.3,0,2,0,\n", time));
+        }
+        let data = || SimulationData::parse(&csv, &Default::default()).unwrap();
+
+        let breakdown = get_error(&mut data(), &FitnessConfig::default());
+        assert_eq!(breakdown.target_distance, 7.5);
+        assert!((breakdown.central_distance_cost - 400.0 * 5.5).abs() < 1e-3);
+
+        let config = FitnessConfig {
+            target_distance: TargetDistance::InitialSpacing,
+            ..FitnessConfig::default()
+        };
+        let breakdown = get_error(&mut data(), &config);
+        assert!((breakdown.target_distance - 2.0).abs() < 1e-5);
+        assert!(breakdown.central_distance_cost < 1e-3);
+    }
+
+    #[test]
+    fn distance_schedule() {
+        let points = [(2.0, 4.0), (4.0, 8.0)];
+        assert_eq!(scheduled_distance(&points, Interpolation::Step, 0.0), 4.0);
+        assert_eq!(scheduled_distance(&points, Interpolation::Step, 3.0), 4.0);
+        assert_eq!(scheduled_distance(&points, Interpolation::Step, 4.0), 8.0);
+        assert_eq!(scheduled_distance(&points, Interpolation::Linear, 3.0), 6.0);
+        assert_eq!(scheduled_distance(&points, Interpolation::Linear, 9.0), 8.0);
+
+        // The peripheral nodes start 2m from the central node and are 6m away after 4.95 seconds,
+        // between two sampled timesteps
+        let mut csv = String::from("Time (s),IP Address, X (m), Y (m), Z (m)\n");
+        for (time, distance) in [(0.0, 2.0), (4.94, 2.0), (4.95, 6.0), (10.0, 6.0)] {
+            csv.push_str(&format!("{},10.1.1.1,0,0,0,\n", time));
+            csv.push_str(&format!("{},10.1.1.2,{},0,0,\n", time, distance));
+            csv.push_str(&format!("{},10.1.1.3,0,{},0,\n", time, distance));
+        }
+        let data = || SimulationData::parse(&csv, &Default::default()).unwrap();
+
+        // A fixed target at the run's mean distance can't tell the phases apart
+        let config = FitnessConfig {
+            target_distance: TargetDistance::Fixed(4.0),
+            ..FitnessConfig::default()
+        };
+        assert!(get_error(&mut data(), &config).central_distance_cost < 5.0);
+
+        let config = FitnessConfig {
+            target_distance: TargetDistance::Schedule {
+                points: vec![(0.0, 2.0), (4.95, 6.0)],
+                interpolation: Interpolation::Step,
+            },
+            ..FitnessConfig::default()
+        };
+        let breakdown = get_error(&mut data(), &config);
+        assert!(breakdown.central_distance_cost < 1e-3);
+        assert!((breakdown.target_distance - 4.0).abs() < 0.1);
+
+        let config = FitnessConfig {
+            target_distance: TargetDistance::Schedule {
+                points: vec![(0.0, 6.0), (4.95, 2.0)],
+                interpolation: Interpolation::Step,
+            },
+            ..FitnessConfig::default()
+        };
+        assert!((get_error(&mut data(), &config).central_distance_cost - 1600.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn uav_weights() {
+        let mut csv = String::from("Time (s),IP Address, X (m), Y (m), Z (m)\n");
+        for time in [0.0, 1.0] {
+            csv.push_str(&format!("{},10.1.1.1,0,0,0,\n", time));
+            csv.push_str(&format!("{},10.1.1.2,2,0,0,\n", time));
+            csv.push_str(&format!("{},10.1.1.3,0,4,0,\n", time));
+        }
+        let data = || SimulationData::parse(&csv, &Default::default()).unwrap();
+        let unweighted = get_error(&mut data(), &FitnessConfig::default());
+        assert!((unweighted.central_distance_cost - 400.0 * 4.5).abs() < 1e-3);
+
+        let mut config = FitnessConfig::default();
+        config.uav_weights.insert("10.1.1.2".parse().unwrap(), 1.0);
+        assert_eq!(get_error(&mut data(), &config), unweighted);
+
+        config.uav_weights = serde_json::from_str(r#"{"10.1.1.2": 3.0}"#).unwrap();
+        let weighted = get_error(&mut data(), &config);
+        //(3 * 2 + 4) / 4 = 2.5
+        assert!((weighted.central_distance_cost - 400.0 * 5.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn uav_filters() {
+        let mut csv = String::from("Time (s),IP Address, X (m), Y (m), Z (m)\n");
+        for time in [0.0, 1.0] {
+            csv.push_str(&format!("{},10.1.1.1,{},50,0,\n", time, time * 100.0));
+            csv.push_str(&format!("{},10.1.1.2,0,0,0,\n", time));
+            csv.push_str(&format!("{},10.1.1.3,2,0,0,\n", time));
+            csv.push_str(&format!("{},10.1.1.4,0,2,0,\n", time));
+        }
+        let data = || SimulationData::parse(&csv, &Default::default()).unwrap();
+        let uncontrolled = "10.1.1.1".parse::<UavId>().unwrap();
+
+        let all = get_error(&mut data(), &FitnessConfig::default());
+        let config = FitnessConfig {
+            exclude_uavs: vec![uncontrolled],
+            ..FitnessConfig::default()
+        };
+        let excluded = get_error(&mut data(), &config);
+        assert_ne!(all, excluded);
+        // 10.1.1.2 becomes the central node, with the others 2m away and not moving
+        assert!((excluded.central_distance_cost - 400.0 * 5.5).abs() < 1e-3);
+        assert_eq!(excluded.velocity_cost, 0.0);
+
+        let config = FitnessConfig {
+            include_uavs: ["10.1.1.2", "10.1.1.3", "10.1.1.4"]
+                .iter()
+                .map(|uav| uav.parse().unwrap())
+                .collect(),
+            ..FitnessConfig::default()
+        };
+        assert_eq!(get_error(&mut data(), &config), excluded);
+    }
+
+    #[test]
+    fn dropouts() {
+        let mut csv = String::from("Time (s),IP Address, X (m), Y (m), Z (m)\n");
+        for step in 0..=50 {
+            let time = step as f64 / 10.0;
+            csv.push_str(&format!("{},10.1.1.1,0,0,0,\n", time));
+            csv.push_str(&format!("{},10.1.1.2,2,0,0,\n", time));
+            csv.push_str(&format!("{},10.1.1.3,0,2,0,\n", time));
+            // Stops logging after 2 seconds
+            if time <= 2.0 {
+                csv.push_str(&format!("{},10.1.1.4,-2,0,0,\n", time));
+            }
+        }
+        let data = || SimulationData::parse(&csv, &Default::default()).unwrap();
+
+        let unweighted = get_error(&mut data(), &FitnessConfig::default());
+        assert_eq!((unweighted.initial_uavs, unweighted.surviving_uavs), (4, 3));
+        assert_eq!(unweighted.dropout_cost, 0.0);
+
+        let config = FitnessConfig {
+            dropout_weight: 100.0,
+            ..FitnessConfig::default()
+        };
+        let weighted = get_error(&mut data(), &config);
+        assert!((weighted.dropout_cost - 25.0).abs() < 1e-9);
+        assert!((weighted.total() - unweighted.total() - 25.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn expected_uavs() {
+        let unchecked = get_error(&mut disturbed_triangle(), &FitnessConfig::default());
+        let config = |expected_uavs, uav_count_mismatch| FitnessConfig {
+            expected_uavs: Some(expected_uavs),
+            uav_count_mismatch,
+            ..FitnessConfig::default()
+        };
+
+        let strict = config(4, UavCountMismatch::Reject);
+        assert!(strict.check_data(&disturbed_triangle()).is_ok());
+        let strict = config(6, UavCountMismatch::Reject);
+        assert!(strict.check_data(&disturbed_triangle()).is_err());
+        assert_eq!(get_error(&mut disturbed_triangle(), &strict), unchecked);
+
+        let lenient = config(6, UavCountMismatch::Penalty(50.0));
+        assert!(lenient.check_data(&disturbed_triangle()).is_ok());
+        let penalized = get_error(&mut disturbed_triangle(), &lenient);
+        assert_eq!(penalized.uav_count_cost, 100.0);
+        assert!((penalized.total() - unchecked.total() - 100.0).abs() < 1e-6);
+        let lenient = config(4, UavCountMismatch::Penalty(50.0));
+        assert_eq!(get_error(&mut disturbed_triangle(), &lenient), unchecked);
+    }
+
+    #[test]
+    fn fitness_window() {
+        // Leaving out the disturbance between 5 and 7 seconds scores a run that is stable throughout
+        let config = FitnessConfig {
+            fitness_window: Some((7.0, 10.0)),
+            ..FitnessConfig::default()
+        };
+        assert!(config.check_data(&disturbed_triangle()).is_ok());
+        let settled = get_error(&mut disturbed_triangle(), &config);
+        let whole = get_error(&mut disturbed_triangle(), &FitnessConfig::default());
+        assert!(settled.p_mad_cost < 1e-3);
+        assert!(whole.p_mad_cost > 1.0);
+        assert!((settled.stable_time - 7.0).abs() < 0.15);
+        // The peripheral nodes are all 1m from the central node
+        assert!((settled.central_distance_cost - 400.0 * 6.5).abs() < 1e-2);
+
+        // Only the disturbance, where the knocked away node stays put and the swarm never settles
+        let config = FitnessConfig {
+            fitness_window: Some((5.0, 6.9)),
+            ..FitnessConfig::default()
+        };
+        let disturbed = get_error(&mut disturbed_triangle(), &config);
+        assert!(disturbed.p_mad_cost < 1e-3);
+        assert!(disturbed.velocity_cost < 1e-3);
+        assert!(disturbed.central_distance_cost > settled.central_distance_cost);
+        assert!((disturbed.stable_time - 6.9).abs() < 1e-9);
+
+        let config = FitnessConfig {
+            fitness_window: Some((5.0, 20.0)),
+            ..FitnessConfig::default()
+        };
+        assert!(config.check_data(&disturbed_triangle()).is_err());
+    }
+
+    #[test]
+    fn steady_state_window() {
+        // The last 3 seconds of the 10 second run are the same as a 7 to 10 second fitness window
+        let config = FitnessConfig {
+            steady_state_window: Some(3.0),
+            ..FitnessConfig::default()
+        };
+        assert!(config.check_data(&disturbed_triangle()).is_ok());
+        let steady = get_error(&mut disturbed_triangle(), &config);
+        let windowed = get_error(
+            &mut disturbed_triangle(),
+            &FitnessConfig {
+                fitness_window: Some((7.0, 10.0)),
+                ..FitnessConfig::default()
+            },
+        );
+        assert_eq!(steady, windowed);
+
+        let config = FitnessConfig {
+            steady_state_window: Some(20.0),
+            ..FitnessConfig::default()
+        };
+        assert!(config.check_data(&disturbed_triangle()).is_err());
+    }
+
+    #[test]
+    fn sparse_timesteps() {
+        // Only the central node is logged at the start
+        let mut csv = String::from("Time (s),IP Address, X (m), Y (m), Z (m)\n0,10.1.1.1,0,0,0,\n");
+        for time in [1.0, 2.0, 3.0] {
+            csv.push_str(&format!("{},10.1.1.1,0,0,0,\n", time));
+            csv.push_str(&format!("{},10.1.1.2,2,0,0,\n", time));
+            csv.push_str(&format!("{},10.1.1.3,0,2,0,\n", time));
+        }
+        let data = || SimulationData::parse(&csv, &Default::default()).unwrap();
+        let config = FitnessConfig {
+            sampling: Sampling::Recorded,
+            ..FitnessConfig::default()
+        };
+        let skipped = get_error(&mut data(), &config);
+        assert!((skipped.central_distance_cost - 400.0 * 5.5).abs() < 1e-3);
+        assert!(skipped.p_mad_cost.abs() < 1e-3);
+
+        let config = FitnessConfig {
+            min_uavs_per_timestep: 1,
+            ..config
+        };
+        let counted = get_error(&mut data(), &config);
+        assert!((counted.central_distance_cost - 400.0 * 6.0).abs() < 1e-3);
+        assert!(counted.p_mad_cost > 1.0);
+    }
+
+    #[test]
+    fn central_outlier() {
+        // The central node drifts 100m away from a square of peripheral UAVs halfway through
+        let mut csv = String::from("Time (s),IP Address, X (m), Y (m), Z (m)\n");
+        for time in 0..4 {
+            let central_x = if time < 2 { 0 } else { 100 };
+            csv.push_str(&format!("{},10.1.1.1,{},0,0,\n", time, central_x));
+            csv.push_str(&format!("{},10.1.1.2,5,0,0,\n", time));
+            csv.push_str(&format!("{},10.1.1.3,0,5,0,\n", time));
+            csv.push_str(&format!("{},10.1.1.4,-5,0,0,\n", time));
+            csv.push_str(&format!("{},10.1.1.5,0,-5,0,\n", time));
+        }
+        let data = || SimulationData::parse(&csv, &Default::default()).unwrap();
+        let unchecked = FitnessConfig {
+            sampling: Sampling::Recorded,
+            ..FitnessConfig::default()
+        };
+        let config = |action| FitnessConfig {
+            central_outlier_factor: Some(3.0),
+            central_outlier_action: action,
+            ..unchecked.clone()
+        };
+
+        let drifting = get_error(&mut data(), &unchecked);
+        assert_eq!(drifting.central_outlier_timesteps, 0);
+
+        let excluded = get_error(&mut data(), &config(CentralOutlierAction::Exclude));
+        assert_eq!(excluded.central_outlier_timesteps, 2);
+        assert!((excluded.central_distance_cost - 400.0 * 2.5).abs() < 1e-3);
+        assert!(excluded.central_distance_cost < drifting.central_distance_cost);
+
+        let flagged = get_error(&mut data(), &config(CentralOutlierAction::Flag));
+        assert_eq!(flagged.central_outlier_timesteps, 2);
+        assert_eq!(
+            flagged.central_distance_cost,
+            drifting.central_distance_cost
+        );
+
+        // The drifting UAV now spreads out the peripheral distances instead
+        let reselected = get_error(&mut data(), &config(CentralOutlierAction::Reselect));
+        assert_eq!(reselected.central_outlier_timesteps, 2);
+        assert!(reselected.p_mad_cost > drifting.p_mad_cost);
+    }
+
+    #[test]
+    fn stability_window() {
+        // An equilateral triangle around the central node, with one UAV knocked outwards at 5s
+        let mut csv = String::from("Time (s),IP Address, X (m), Y (m), Z (m)\n");
+        for time in 0..10 {
+            let x = if time == 5 { 20.0 } else { 10.0 };
+            csv.push_str(&format!("{},10.1.1.1,0,0,0,\n", time));
+            csv.push_str(&format!("{},10.1.1.2,{},0,0,\n", time, x));
+            csv.push_str(&format!("{},10.1.1.3,-5,8.66,0,\n", time));
+            csv.push_str(&format!("{},10.1.1.4,-5,-8.66,0,\n", time));
+        }
+        let data = || SimulationData::parse(&csv, &Default::default()).unwrap();
+        let config = FitnessConfig {
+            sampling: Sampling::Recorded,
+            stability_criterion: StabilityCriterion::AbsoluteMad(3.5),
+            ..FitnessConfig::default()
+        };
+        assert_eq!(get_error(&mut data(), &config).stable_time, 6.0);
+
+        // Pooled with the two timesteps before it, the spike no longer breaks the streak
+        let config = FitnessConfig {
+            stability_window: 3,
+            ..config
+        };
+        assert_eq!(get_error(&mut data(), &config).stable_time, 0.0);
+    }
+
+    #[test]
+    fn anchor_nodes() {
+        // Two anchors 20m apart on the x axis with a pair of UAVs 3m apart near the first one
+        let mut csv = String::from("Time (s),IP Address, X (m), Y (m), Z (m)\n");
+        for time in 0..4 {
+            csv.push_str(&format!("{},10.1.1.1,0,0,0,\n", time));
+            csv.push_str(&format!("{},10.1.1.2,20,0,0,\n", time));
+            csv.push_str(&format!("{},10.1.1.3,4,0,0,\n", time));
+            csv.push_str(&format!("{},10.1.1.4,4,3,0,\n", time));
+        }
+        let data = || SimulationData::parse(&csv, &Default::default()).unwrap();
+        let uav = |id| UavId::from([10, 1, 1, id]);
+        let single = FitnessConfig {
+            sampling: Sampling::Recorded,
+            ..FitnessConfig::default()
+        };
+        let defaulted = get_error(&mut data(), &single);
+        let listed = get_error(
+            &mut data(),
+            &FitnessConfig {
+                anchor_nodes: vec![(uav(1), 1.0)].into_iter().collect(),
+                ..single.clone()
+            },
+        );
+        assert_eq!(
+            defaulted.central_distance_cost,
+            listed.central_distance_cost
+        );
+
+        let config = FitnessConfig {
+            anchor_nodes: vec![(uav(1), 1.0), (uav(2), 3.0)].into_iter().collect(),
+            ..single
+        };
+        assert_eq!(config.anchors(&data()), vec![(uav(1), 1.0), (uav(2), 3.0)]);
+        let error = get_error(&mut data(), &config);
+        let near = (4.0 + 5.0) / 2.0;
+        let far = (16.0 + 265f64.sqrt()) / 2.0;
+        let combined = (near + 3.0 * far) / 4.0;
+        assert!((error.central_distance_cost - 400.0 * (combined - 7.5)).abs() < 1e-3);
+        // Only the two non-anchor UAVs are peripheral, and they never move apart
+        assert_eq!(error.p_mad_cost, 0.0);
+
+        assert!(FitnessConfig {
+            anchor_nodes: vec![(uav(9), 1.0)].into_iter().collect(),
+            ..FitnessConfig::default()
+        }
+        .check_data(&data())
+        .is_err());
+    }
+
+    #[test]
+    fn formation_fit() {
+        let pair =
+            |x, y, target_x, target_y| (Vec3A::new(x, y, 0.0), Vec3A::new(target_x, target_y, 0.0));
+        // A square flown 100m away is a perfect fit
+        let square = [
+            pair(100.0, 0.0, 0.0, 0.0),
+            pair(110.0, 0.0, 10.0, 0.0),
+            pair(110.0, 10.0, 10.0, 10.0),
+            pair(100.0, 10.0, 0.0, 10.0),
+        ];
+        assert!(formation_error(&square, false) < 1e-4);
+
+        // The same square turned 90 degrees only fits when rotation is allowed
+        let turned = [
+            pair(0.0, 0.0, 0.0, 0.0),
+            pair(0.0, 10.0, 10.0, 0.0),
+            pair(-10.0, 10.0, 10.0, 10.0),
+            pair(-10.0, 0.0, 0.0, 10.0),
+        ];
+        assert!(formation_error(&turned, true) < 1e-4);
+        assert!((formation_error(&turned, false) - 10.0).abs() < 1e-4);
+
+        // One UAV 2m out of place in a line of two leaves each 1m off after centering
+        let stretched = [pair(0.0, 0.0, 0.0, 0.0), pair(12.0, 0.0, 10.0, 0.0)];
+        assert!((formation_error(&stretched, false) - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn target_formation() {
+        // A line of three UAVs that should be a right angle
+        let mut csv = String::from("Time (s),IP Address, X (m), Y (m), Z (m)\n");
+        for time in 0..3 {
+            csv.push_str(&format!("{},10.1.1.1,0,0,0,\n", time));
+            csv.push_str(&format!("{},10.1.1.2,5,0,0,\n", time));
+            csv.push_str(&format!("{},10.1.1.3,-5,0,0,\n", time));
+        }
+        let data = || SimulationData::parse(&csv, &Default::default()).unwrap();
+        let uav = |id| UavId::from([10, 1, 1, id]);
+        let config = FitnessConfig {
+            sampling: Sampling::Recorded,
+            ..FitnessConfig::default()
+        };
+        assert_eq!(get_error(&mut data(), &config).formation_cost, 0.0);
+
+        let line = FitnessConfig {
+            target_formation: vec![
+                (uav(1), Vec3A::ZERO),
+                (uav(2), Vec3A::new(5.0, 0.0, 0.0)),
+                (uav(3), Vec3A::new(-5.0, 0.0, 0.0)),
+            ]
+            .into_iter()
+            .collect(),
+            ..config.clone()
+        };
+        assert!(get_error(&mut data(), &line).formation_cost < 1e-3);
+        let parsed: FitnessConfig =
+            serde_json::from_str(r#"{"target_formation": {"10.1.1.2": [5, 0, 0]}}"#).unwrap();
+        assert_eq!(parsed.target_formation[&uav(2)], Vec3A::new(5.0, 0.0, 0.0));
+
+        let corner = FitnessConfig {
+            target_formation: vec![
+                (uav(1), Vec3A::ZERO),
+                (uav(2), Vec3A::new(5.0, 0.0, 0.0)),
+                (uav(3), Vec3A::new(0.0, 5.0, 0.0)),
+            ]
+            .into_iter()
+            .collect(),
+            ..config
+        };
+        let error = get_error(&mut data(), &corner);
+        let expected = 400.0
+            * formation_error(
+                &[
+                    (Vec3A::ZERO, Vec3A::ZERO),
+                    (Vec3A::new(5.0, 0.0, 0.0), Vec3A::new(5.0, 0.0, 0.0)),
+                    (Vec3A::new(-5.0, 0.0, 0.0), Vec3A::new(0.0, 5.0, 0.0)),
+                ],
+                false,
+            );
+        assert!(expected > 1000.0);
+        assert!((error.formation_cost - expected).abs() < 1e-3);
+        assert!(
+            (error.total() - error.formation_cost - get_error(&mut data(), &line).total()).abs()
+                < 1e-3
+        );
+    }
+
+    #[test]
+    fn central_outlier_detection() {
+        let uav = |id, x, y| (UavId::from([10, 1, 1, id]), Vec3A::new(x, y, 0.0));
+        let square = [
+            uav(2, 5.0, 0.0),
+            uav(3, -5.0, 0.0),
+            uav(4, 0.0, 5.0),
+            uav(5, 0.0, -5.0),
+        ];
+        assert!(!is_central_outlier(Vec3A::ZERO, &square, 3.0));
+        assert!(is_central_outlier(Vec3A::new(20.0, 0.0, 0.0), &square, 3.0));
+        assert!(!is_central_outlier(Vec3A::new(20.0, 0.0, 0.0), &[], 3.0));
+
+        // Regular formations with the central node at a corner have no spread to measure against
+        let triangle = [uav(2, 10.0, 0.0), uav(3, 5.0, 8.660254)];
+        assert!(!is_central_outlier(Vec3A::ZERO, &triangle, 3.0));
+        let corners = [uav(2, 10.0, 0.0), uav(3, 10.0, 10.0), uav(4, 0.0, 10.0)];
+        assert!(!is_central_outlier(Vec3A::ZERO, &corners, 3.0));
+        assert!(is_central_outlier(
+            Vec3A::new(-50.0, -50.0, 0.0),
+            &corners,
+            3.0
+        ));
+    }
+
+    #[test]
+    fn windowed_velocity() {
+        // A UAV that jitters back and forth by 1m every 0.1s while creeping forward at 1 m/s
+        let mut csv = String::from("Time (s),IP Address, X (m), Y (m), Z (m)\n");
+        for step in 0..=40 {
+            let time = step as f64 / 10.0;
+            let jitter = if step % 2 == 0 { 0.0 } else { 1.0 };
+            csv.push_str(&format!("{},10.1.1.1,0,0,0,\n", time));
+            csv.push_str(&format!("{},10.1.1.2,{},5,0,\n", time, time + jitter));
+        }
+        let data = || SimulationData::parse(&csv, &Default::default()).unwrap();
+        let config = FitnessConfig {
+            sampling: Sampling::Recorded,
+            include_central_in_velocity: false,
+            ..FitnessConfig::default()
+        };
+        let per_step = get_error(&mut data(), &config);
+        let config = FitnessConfig {
+            velocity_mode: VelocityMode::Window(1.0),
+            ..config
+        };
+        let windowed = get_error(&mut data(), &config);
+        // Per step the jitter dominates at about 10 m/s
+        assert!(per_step.velocity_cost > 250.0 * 8.0);
+        // Over a 1s window only the creep is left, apart from the first 0.1s
+        assert!(windowed.velocity_cost < 250.0 * 1.5);
+        assert!(windowed.velocity_cost > 250.0 * 0.9);
+        assert_eq!(windowed.p_mad_cost, per_step.p_mad_cost);
+    }
+
+    #[test]
+    fn velocity_cap() {
+        let mut csv = String::from("Time (s),IP Address, X (m), Y (m), Z (m)\n");
+        for (time, x) in [(0.0, 1.0), (1.0, 1.0), (1.1, 101.0), (2.0, 101.0)] {
+            csv.push_str(&format!("{},10.1.1.1,0,0,0,\n", time));
+            csv.push_str(&format!("{},10.1.1.2,{},0,0,\n", time, x));
+        }
+        let data = || SimulationData::parse(&csv, &Default::default()).unwrap();
+        let uncapped = get_error(&mut data(), &FitnessConfig::default());
+
+        let config = FitnessConfig {
+            velocity_cap: Some(f64::INFINITY),
+            ..FitnessConfig::default()
+        };
+        assert_eq!(get_error(&mut data(), &config), uncapped);
+
+        let config = FitnessConfig {
+            velocity_cap: Some(5.0),
+            ..FitnessConfig::default()
+        };
+        let capped = get_error(&mut data(), &config);
+        assert!(capped.velocity_cost < uncapped.velocity_cost);
+        //No averaged velocity can be above the cap
+        assert!(capped.velocity_cost <= 250.0 * 5.0);
+    }
+
+    #[test]
+    fn control_effort() {
+        let score = |position: fn(f64) -> f64| {
+            let mut csv = String::from("Time (s),IP Address, X (m), Y (m), Z (m)\n");
+            for time in [0.0, 1.0, 2.0, 3.0, 4.0] {
+                csv.push_str(&format!("{},10.1.1.1,0,0,0,\n", time));
+                csv.push_str(&format!("{},10.1.1.2,{},0,0,\n", time, position(time)));
+            }
+            let mut data = SimulationData::parse(&csv, &Default::default()).unwrap();
+            let config = FitnessConfig {
+                sampling: Sampling::Recorded,
+                control_effort_weight: 10.0,
+                ..FitnessConfig::default()
+            };
+            get_error(&mut data, &config)
+        };
+
+        assert_eq!(score(|time| time).control_effort_cost, 0.0);
+        // Accelerating at 1 m/s², averaged with the central node that doesn't move
+        let accelerating = score(|time| time * time / 2.0);
+        assert!((accelerating.control_effort_cost - 5.0).abs() < 1e-4);
+
+        let mut unweighted = accelerating.clone();
+        unweighted.control_effort_cost = 0.0;
+        assert!((accelerating.total() - unweighted.total() - 5.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn central_velocity() {
+        let mut csv = String::from("Time (s),IP Address, X (m), Y (m), Z (m)\n");
+        for time in [0.0, 1.0, 2.0, 3.0] {
+            // The central node moves at 2 m/s and the peripheral node at 1 m/s
+            csv.push_str(&format!("{},10.1.1.1,{},0,0,\n", time, 2.0 * time));
+            csv.push_str(&format!("{},10.1.1.2,{},0,0,\n", time, 10.0 + time));
+        }
+        let score = |include_central_in_velocity: bool| {
+            let mut data = SimulationData::parse(&csv, &Default::default()).unwrap();
+            let config = FitnessConfig {
+                sampling: Sampling::Recorded,
+                velocity_start_time: 0.5,
+                include_central_in_velocity,
+                ..FitnessConfig::default()
+            };
+            get_error(&mut data, &config)
+        };
+
+        assert!((score(true).velocity_cost - 250.0 * 1.5).abs() < 1e-3);
+        assert!((score(false).velocity_cost - 250.0 * 1.0).abs() < 1e-3);
+        assert_eq!(
+            score(true).central_distance_cost,
+            score(false).central_distance_cost
+        );
+    }
+
+    #[test]
+    fn distance_aggregations() {
+        let values: Vec<f64> = (1..=20).map(f64::from).collect();
+        assert_eq!(DistanceAggregation::Mean.apply(&values), 10.5);
+        assert_eq!(DistanceAggregation::Max.apply(&values), 20.0);
+        assert_eq!(DistanceAggregation::P95.apply(&values), 19.0);
+        assert_eq!(DistanceAggregation::P95.apply(&[3.0]), 3.0);
+        assert_eq!(DistanceAggregation::Max.apply(&[2.0, f64::NAN, 1.0]), 2.0);
+
+        // The peripheral nodes spread from 2m to 6m at the end
+        let mut csv = String::from("Time (s),IP Address, X (m), Y (m), Z (m)\n");
+        for (time, distance) in [(0.0, 2.0), (1.0, 2.0), (2.0, 2.0), (3.0, 6.0)] {
+            csv.push_str(&format!("{},10.1.1.1,0,0,0,\n", time));
+            csv.push_str(&format!("{},10.1.1.2,{},0,0,\n", time, distance));
+            csv.push_str(&format!("{},10.1.1.3,0,{},0,\n", time, distance));
+        }
+        let score = |distance_aggregation| {
+            let mut data = SimulationData::parse(&csv, &Default::default()).unwrap();
+            let config = FitnessConfig {
+                sampling: Sampling::Recorded,
+                target_distance: TargetDistance::Fixed(2.0),
+                distance_aggregation,
+                ..FitnessConfig::default()
+            };
+            get_error(&mut data, &config).central_distance_cost
+        };
+        assert!((score(DistanceAggregation::Mean) - 400.0 * 1.0).abs() < 1e-3);
+        assert!((score(DistanceAggregation::Max) - 400.0 * 4.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn recorded_sampling() {
+        let mut csv = String::from("Time (s),IP Address, X (m), Y (m), Z (m)\n");
+        for (time, x) in [(0.0, 2.0), (1.0, 3.0), (2.0, 4.0)] {
+            csv.push_str(&format!("{},10.1.1.1,0,0,0,\n", time));
+            csv.push_str(&format!("{},10.1.1.2,{},0,0,\n", time, x));
+        }
+        //Only the moving node is logged at the end
+        csv.push_str("3,10.1.1.2,5,0,0,\n");
+        let data = || SimulationData::parse(&csv, &Default::default()).unwrap();
+
+        let fixed = get_error(&mut data(), &FitnessConfig::default());
+        let config = FitnessConfig {
+            sampling: Sampling::Recorded,
+            ..FitnessConfig::default()
+        };
+        let recorded = get_error(&mut data(), &config);
+        //The first timestep has no velocities yet, and the central node only adds a velocity of 0
+        //to the timesteps it was logged at, so the means are 0, 0.5, 0.5 and 1
+        assert!((recorded.velocity_cost - 250.0 * 0.5).abs() < 1e-3);
+        //2 to 5 meters away, on average 3.5
+        assert!((recorded.central_distance_cost - 400.0 * 4.0).abs() < 1e-3);
+        assert!((fixed.central_distance_cost - recorded.central_distance_cost).abs() < 1.0);
+    }
+
+    #[test]
+    fn velocity_start_time() {
+        let mut csv = String::from("Time (s),IP Address, X (m), Y (m), Z (m)\n");
+        //Moves 10 m/s for the first second, then sits still
+        for (time, x) in [(0.0, 0.0), (1.0, 10.0), (2.0, 10.0), (3.0, 10.0)] {
+            csv.push_str(&format!("{},10.1.1.1,0,0,0,\n", time));
+            csv.push_str(&format!("{},10.1.1.2,{},0,0,\n", time, x));
+        }
+        let data = || SimulationData::parse(&csv, &Default::default()).unwrap();
+        let all = get_error(&mut data(), &FitnessConfig::default());
+        assert!(all.velocity_cost > 250.0);
+
+        let config = FitnessConfig {
+            velocity_start_time: 1.5,
+            ..FitnessConfig::default()
+        };
+        let settled = get_error(&mut data(), &config);
+        assert_eq!(settled.velocity_cost, 0.0);
+        assert_eq!(settled.p_mad_cost, all.p_mad_cost);
+
+        let config = FitnessConfig {
+            velocity_start_time: 100.0,
+            ..FitnessConfig::default()
+        };
+        assert_eq!(get_error(&mut data(), &config).velocity_cost, 0.0);
+    }
+
+    #[test]
+    fn normalized_total() {
+        let mut ranges = TermRanges::default();
+        let low = CostBreakdown {
+            p_mad_cost: 1.0,
+            velocity_cost: 1000.0,
+            ..CostBreakdown::default()
+        };
+        let high = CostBreakdown {
+            p_mad_cost: 3.0,
+            velocity_cost: 5000.0,
+            ..CostBreakdown::default()
+        };
+        ranges.update(&low);
+        //Nothing has a spread after one run
+        assert_eq!(ranges.normalized_total(&low, &IndexMap::new()), 0.0);
+
+        assert!(ranges.update(&high));
+        assert!(!ranges.update(&low));
+        assert_eq!(ranges.normalized_total(&low, &IndexMap::new()), 0.0);
+        assert_eq!(ranges.normalized_total(&high, &IndexMap::new()), 2.0);
+
+        let mid = CostBreakdown {
+            p_mad_cost: 2.0,
+            velocity_cost: 2000.0,
+            ..CostBreakdown::default()
+        };
+        let mut weights = IndexMap::new();
+        weights.insert("velocity_cost".to_owned(), 2.0);
+        assert_eq!(ranges.normalized_total(&mid, &weights), 0.5 + 2.0 * 0.25);
+    }
+
+    #[test]
+    fn stability_criterion() {
+        let percent = StabilityCriterion::PercentCv(30.0);
+        assert!(percent.is_stable(2.0, 10.0));
+        assert!(!percent.is_stable(4.0, 10.0));
+        assert!(!percent.is_stable(f64::NAN, f64::NAN));
+
+        let absolute = StabilityCriterion::AbsoluteMad(3.0);
+        assert!(absolute.is_stable(2.0, 1.0));
+        assert!(!absolute.is_stable(4.0, 100.0));
+        assert!(!absolute.is_stable(f64::NAN, f64::NAN));
+    }
+}
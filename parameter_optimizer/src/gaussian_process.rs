@@ -0,0 +1,366 @@
+use crate::config::GaussianProcessConfig;
+
+use rand::Rng;
+use std::collections::VecDeque;
+use std::ops::Range;
+
+/// How many picks that haven't been scored yet are remembered. Picks whose simulation failed are
+/// never scored, so the oldest are forgotten instead
+const MAX_PENDING: usize = 64;
+
+/// The noise added to the diagonal on top of the configured noise so the kernel matrix can
+/// always be factored
+const JITTER: f64 = 1e-9;
+
+/// A Gaussian process over every parameter at once that picks the point with the highest expected
+/// improvement over the lowest error so far. Points are scaled to the unit cube, and the errors
+/// are standardized before fitting. Each pick refits the process, which is cubic in the number of
+/// results
+pub struct GaussianProcess {
+    config: GaussianProcessConfig,
+    ranges: Vec<Range<f64>>,
+    /// Scored points in the unit cube and their errors
+    observations: Vec<(Vec<f64>, f64)>,
+    /// Points that were picked but haven't been scored yet. They are fitted as if they scored the
+    /// mean error so parallel runners don't all pick the same point
+    pending: VecDeque<Vec<f64>>,
+}
+
+impl GaussianProcess {
+    pub fn new(config: GaussianProcessConfig, ranges: Vec<Range<f64>>) -> Self {
+        Self {
+            config,
+            ranges,
+            observations: Vec::new(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Records the `error` of the parameter values `values`. Non-finite errors are ignored
+    pub fn tell(&mut self, values: &[f64], error: f64) {
+        let point = self.unit_point(values);
+        if let Some(index) = self
+            .pending
+            .iter()
+            .position(|pending| squared_distance(pending, &point) < 1e-12)
+        {
+            self.pending.remove(index);
+        }
+        if error.is_finite() {
+            self.observations.push((point, error));
+        }
+    }
+
+    /// Replaces every scored point with the parameter values and errors in `observations`, such
+    /// as after the errors were rescaled. Picks that haven't been scored yet are kept
+    pub fn retell(&mut self, observations: impl IntoIterator<Item = (Vec<f64>, f64)>) {
+        self.observations = observations
+            .into_iter()
+            .filter(|(_, error)| error.is_finite())
+            .map(|(values, error)| (self.unit_point(&values), error))
+            .collect();
+    }
+
+    /// The parameter values to evaluate next. Until `initial_samples` results are in they are
+    /// picked uniformly at random. `simulated` turns the picked values into the ones the
+    /// simulation is run with, such as after rounding, so `tell` finds the pick it scores
+    pub fn ask(
+        &mut self,
+        rng: &mut impl Rng,
+        simulated: impl FnOnce(Vec<f64>) -> Vec<f64>,
+    ) -> Vec<f64> {
+        let dimensions = self.ranges.len();
+        let random_point = |rng: &mut dyn rand::RngCore| -> Vec<f64> {
+            (0..dimensions).map(|_| rng.gen::<f64>()).collect()
+        };
+        let point = if self.observations.len() < self.config.initial_samples.max(1) {
+            random_point(rng)
+        } else {
+            let model = self.fit();
+            let best = model.best;
+            // Half the candidates are spread over the whole space and half stay near the best
+            // point so far
+            let incumbent = self
+                .observations
+                .iter()
+                .min_by(|a, b| a.1.total_cmp(&b.1))
+                .map(|(point, _)| point.clone())
+                .unwrap();
+            (0..self.config.candidates.max(1))
+                .map(|i| {
+                    if i % 2 == 0 {
+                        random_point(rng)
+                    } else {
+                        incumbent
+                            .iter()
+                            .map(|x| {
+                                let step = self.config.length_scale * rng.gen_range(-1.0..1.0);
+                                (x + step).clamp(0.0, 1.0)
+                            })
+                            .collect()
+                    }
+                })
+                .map(|candidate| {
+                    let (mean, variance) = model.predict(&candidate);
+                    (expected_improvement(mean, variance, best), candidate)
+                })
+                .max_by(|a, b| a.0.total_cmp(&b.0))
+                .unwrap()
+                .1
+        };
+        let values = simulated(self.parameter_values(&point));
+        if self.pending.len() == MAX_PENDING {
+            self.pending.pop_front();
+        }
+        self.pending.push_back(self.unit_point(&values));
+        values
+    }
+
+    fn unit_point(&self, values: &[f64]) -> Vec<f64> {
+        values
+            .iter()
+            .zip(&self.ranges)
+            .map(|(value, range)| (value - range.start) / (range.end - range.start))
+            .collect()
+    }
+
+    fn parameter_values(&self, point: &[f64]) -> Vec<f64> {
+        point
+            .iter()
+            .zip(&self.ranges)
+            .map(|(x, range)| range.start + x * (range.end - range.start))
+            .collect()
+    }
+
+    /// Fits the process to the observations and pending points
+    fn fit(&self) -> Model {
+        let errors: Vec<f64> = self.observations.iter().map(|(_, error)| *error).collect();
+        let mean = errors.iter().sum::<f64>() / errors.len() as f64;
+        let variance = errors
+            .iter()
+            .map(|error| (error - mean).powi(2))
+            .sum::<f64>()
+            / errors.len() as f64;
+        let scale = if variance > 0.0 { variance.sqrt() } else { 1.0 };
+
+        let mut points: Vec<Vec<f64>> = Vec::new();
+        let mut targets = Vec::new();
+        for (point, error) in &self.observations {
+            points.push(point.clone());
+            targets.push((error - mean) / scale);
+        }
+        for point in &self.pending {
+            points.push(point.clone());
+            targets.push(0.0);
+        }
+
+        let n = points.len();
+        let mut kernel = vec![0.0; n * n];
+        for i in 0..n {
+            for j in 0..=i {
+                let k = self.kernel(&points[i], &points[j]);
+                kernel[i * n + j] = k;
+                kernel[j * n + i] = k;
+            }
+            kernel[i * n + i] += self.config.noise + JITTER;
+        }
+        let cholesky = cholesky(kernel, n);
+        let alpha = solve_upper(&cholesky, n, &solve_lower(&cholesky, n, &targets));
+        let best = targets[..self.observations.len()]
+            .iter()
+            .copied()
+            .fold(f64::INFINITY, f64::min);
+        Model {
+            length_scale: self.config.length_scale,
+            points,
+            cholesky,
+            alpha,
+            best,
+        }
+    }
+
+    fn kernel(&self, a: &[f64], b: &[f64]) -> f64 {
+        squared_exponential(a, b, self.config.length_scale)
+    }
+}
+
+/// A fitted process, in standardized error units
+struct Model {
+    length_scale: f64,
+    points: Vec<Vec<f64>>,
+    /// The lower triangular factor of the kernel matrix, row major
+    cholesky: Vec<f64>,
+    alpha: Vec<f64>,
+    /// The lowest standardized error observed
+    best: f64,
+}
+
+impl Model {
+    /// The predicted mean and variance of the error at `point`
+    fn predict(&self, point: &[f64]) -> (f64, f64) {
+        let n = self.points.len();
+        let covariances: Vec<f64> = self
+            .points
+            .iter()
+            .map(|other| squared_exponential(point, other, self.length_scale))
+            .collect();
+        let mean = covariances
+            .iter()
+            .zip(&self.alpha)
+            .map(|(k, a)| k * a)
+            .sum();
+        let v = solve_lower(&self.cholesky, n, &covariances);
+        let variance = (1.0 - v.iter().map(|x| x * x).sum::<f64>()).max(0.0);
+        (mean, variance)
+    }
+}
+
+fn squared_distance(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).map(|(a, b)| (a - b).powi(2)).sum()
+}
+
+fn squared_exponential(a: &[f64], b: &[f64], length_scale: f64) -> f64 {
+    (-squared_distance(a, b) / (2.0 * length_scale * length_scale)).exp()
+}
+
+/// The lower triangular `L` with `L * L^T = matrix`, for a symmetric positive definite `n` by `n`
+/// matrix stored row major
+fn cholesky(matrix: Vec<f64>, n: usize) -> Vec<f64> {
+    let mut lower = vec![0.0; n * n];
+    for i in 0..n {
+        for j in 0..=i {
+            let sum: f64 = (0..j).map(|k| lower[i * n + k] * lower[j * n + k]).sum();
+            if i == j {
+                lower[i * n + i] = (matrix[i * n + i] - sum).max(JITTER).sqrt();
+            } else {
+                lower[i * n + j] = (matrix[i * n + j] - sum) / lower[j * n + j];
+            }
+        }
+    }
+    lower
+}
+
+/// Solves `L * x = b` by forward substitution
+fn solve_lower(lower: &[f64], n: usize, b: &[f64]) -> Vec<f64> {
+    let mut x = vec![0.0; n];
+    for i in 0..n {
+        let sum: f64 = (0..i).map(|k| lower[i * n + k] * x[k]).sum();
+        x[i] = (b[i] - sum) / lower[i * n + i];
+    }
+    x
+}
+
+/// Solves `L^T * x = b` by back substitution
+fn solve_upper(lower: &[f64], n: usize, b: &[f64]) -> Vec<f64> {
+    let mut x = vec![0.0; n];
+    for i in (0..n).rev() {
+        let sum: f64 = (i + 1..n).map(|k| lower[k * n + i] * x[k]).sum();
+        x[i] = (b[i] - sum) / lower[i * n + i];
+    }
+    x
+}
+
+/// How much lower than `best` an error with this predicted mean and variance is expected to be
+fn expected_improvement(mean: f64, variance: f64, best: f64) -> f64 {
+    let sd = variance.sqrt();
+    if sd < 1e-12 {
+        return (best - mean).max(0.0);
+    }
+    let z = (best - mean) / sd;
+    (best - mean) * normal_cdf(z) + sd * normal_pdf(z)
+}
+
+fn normal_pdf(z: f64) -> f64 {
+    (-0.5 * z * z).exp() / (2.0 * std::f64::consts::PI).sqrt()
+}
+
+/// The standard normal CDF, using the Abramowitz and Stegun approximation of erf (7.1.26) which
+/// is accurate to about 1e-7
+fn normal_cdf(z: f64) -> f64 {
+    let x = z.abs() / std::f64::consts::SQRT_2;
+    let t = 1.0 / (1.0 + 0.3275911 * x);
+    let poly = t
+        * (0.254829592
+            + t * (-0.284496736 + t * (1.421413741 + t * (-1.453152027 + t * 1.061405429))));
+    let erf = 1.0 - poly * (-x * x).exp();
+    if z >= 0.0 {
+        0.5 * (1.0 + erf)
+    } else {
+        0.5 * (1.0 - erf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    #[test]
+    fn normal_distribution() {
+        assert!((normal_cdf(0.0) - 0.5).abs() < 1e-7);
+        assert!((normal_cdf(1.96) - 0.975).abs() < 1e-4);
+        assert!((normal_cdf(-1.0) - 0.158655).abs() < 1e-5);
+        assert!((normal_pdf(0.0) - 0.398942).abs() < 1e-5);
+        assert_eq!(expected_improvement(0.0, 0.0, 1.0), 1.0);
+        assert!(expected_improvement(0.0, 1.0, 0.0) > 0.0);
+    }
+
+    #[test]
+    fn linear_algebra() {
+        let matrix = vec![4.0, 2.0, 2.0, 3.0];
+        let lower = cholesky(matrix, 2);
+        assert_eq!(lower, vec![2.0, 0.0, 1.0, 2f64.sqrt()]);
+        // [4 2; 2 3] * [1; 2] = [8; 8]
+        let x = solve_upper(&lower, 2, &solve_lower(&lower, 2, &[8.0, 8.0]));
+        assert!((x[0] - 1.0).abs() < 1e-12 && (x[1] - 2.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn finds_minimum() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+        let config = GaussianProcessConfig::default();
+        let mut gp = GaussianProcess::new(config, vec![0.0..18.0, 0.0..18.0]);
+        let error = |values: &[f64]| (values[0] - 7.0).powi(2) + (values[1] - 12.0).powi(2);
+        let mut best = f64::INFINITY;
+        for _ in 0..40 {
+            let values = gp.ask(&mut rng, |values| values);
+            assert!(values.iter().all(|value| (0.0..=18.0).contains(value)));
+            best = best.min(error(&values));
+            gp.tell(&values, error(&values));
+        }
+        assert_eq!(gp.observations.len(), 40);
+        assert!(gp.pending.is_empty());
+        assert!(best < 1.0, "best error was {}", best);
+    }
+
+    #[test]
+    fn pending_points() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+        let config = GaussianProcessConfig {
+            initial_samples: 3,
+            ..GaussianProcessConfig::default()
+        };
+        let mut gp = GaussianProcess::new(config, vec![0.0..1.0]);
+        for x in [0.1, 0.5, 0.9].iter() {
+            gp.tell(&[*x], (x - 0.5f64).abs());
+        }
+        // A second pick made before the first is scored avoids it
+        let first = gp.ask(&mut rng, |values| values);
+        let second = gp.ask(&mut rng, |values| values);
+        assert!((first[0] - second[0]).abs() > 1e-3);
+        gp.tell(&first, f64::NAN);
+        assert_eq!(gp.pending.len(), 1);
+        assert_eq!(gp.observations.len(), 3);
+
+        // Telling the rounded values the simulation was run with clears the pick
+        let rounded = gp.ask(&mut rng, |values| {
+            values
+                .iter()
+                .map(|value| (value * 10.0).round() / 10.0)
+                .collect()
+        });
+        assert_eq!(gp.pending.len(), 2);
+        gp.tell(&rounded, 0.2);
+        assert_eq!(gp.pending.len(), 1);
+    }
+}
@@ -1,11 +1,37 @@
+use crate::log;
 use std::fmt;
-use std::io::Read;
-use std::process::Command;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+/// Messages git prints when it couldn't reach the remote, as opposed to the remote or the repo
+/// being wrong
+const NETWORK_ERRORS: [&str; 8] = [
+    "Could not resolve host",
+    "Connection timed out",
+    "Connection refused",
+    "Connection reset",
+    "Failed to connect",
+    "Operation timed out",
+    "The remote end hung up unexpectedly",
+    "early EOF",
+];
+
+/// The NS3 repo `--use-git` checks out when neither a flag nor `NS3_REPO_URL` picks another
+pub const DEFAULT_REPO_URL: &str = "https://github.com/TroyNeubauer/NS3NonIdealConditions2021.git";
+
+/// The commit `--use-git` checks out when neither a flag nor `NS3_COMMIT` picks another
+pub const DEFAULT_COMMIT: &str = "ba8ea4ac58eada9679146ba2dc755789bbfbe91e";
+
+/// How long to wait before retrying a git command that failed with a network error. Doubles after
+/// every retry
+const FIRST_RETRY_DELAY: Duration = Duration::from_secs(2);
 
 pub struct RepoInfo {
     pub url: String,
     pub path: String,
     pub commit_hash: String,
+    /// How many times a clone that failed with a network error is retried
+    pub retries: u32,
 }
 
 #[derive(Debug)]
@@ -29,6 +55,15 @@ impl GitError {
     }
 }
 
+impl GitError {
+    /// If git failed because the remote couldn't be reached, so trying again may work
+    pub fn is_network_error(&self) -> bool {
+        NETWORK_ERRORS
+            .iter()
+            .any(|pattern| self.message.contains(pattern))
+    }
+}
+
 impl std::error::Error for GitError {}
 
 impl fmt::Display for GitError {
@@ -44,15 +79,17 @@ impl fmt::Display for GitError {
 pub fn setup_repo(info: &RepoInfo) -> Result<bool, crate::Error> {
     let mut needs_configure = false;
     if !std::path::Path::new(&info.path).exists() {
-        println!("Cloning repo: {}", info.url);
-        run_git_command(&["clone", info.url.as_str(), info.path.as_str()], "./")?;
+        log!("Cloning repo: {}", info.url);
+        retry_network_errors(info.retries, FIRST_RETRY_DELAY, "Clone", || {
+            run_git_command(&["clone", info.url.as_str(), info.path.as_str()], "./")
+        })?;
         needs_configure = true;
     }
     let current_hash = run_git_command(&["rev-parse", "HEAD"], info.path.as_str())?;
 
-    println!("Checkout complete!");
+    log!("Checkout complete!");
     if current_hash != info.commit_hash {
-        println!("Hashes differ");
+        log!("Hashes differ");
         let _ = run_git_command(&["checkout", info.commit_hash.as_str()], info.path.as_str())?;
         //We just checked out a new commit so reconfigure!
         Ok(true)
@@ -61,21 +98,170 @@ pub fn setup_repo(info: &RepoInfo) -> Result<bool, crate::Error> {
     }
 }
 
+/// Picks a repo setting from `flag` if it was given, otherwise from the environment variable
+/// `env_var` if it is set and not empty, and otherwise `default`. Logs which one was used
+pub fn repo_setting(description: &str, flag: Option<&str>, env_var: &str, default: &str) -> String {
+    let env_value = std::env::var(env_var).ok();
+    let (value, source) = pick_setting(flag, env_value.as_deref(), default);
+    let source = match source {
+        SettingSource::Flag => "the command line".to_owned(),
+        SettingSource::Environment => format!("${}", env_var),
+        SettingSource::Default => "the built in default".to_owned(),
+    };
+    log!("Using {} {} from {}", description, value, source);
+    value.to_owned()
+}
+
+#[derive(Debug, PartialEq)]
+enum SettingSource {
+    Flag,
+    Environment,
+    Default,
+}
+
+fn pick_setting<'a>(
+    flag: Option<&'a str>,
+    env_value: Option<&'a str>,
+    default: &'a str,
+) -> (&'a str, SettingSource) {
+    match (flag, env_value) {
+        (Some(flag), _) => (flag, SettingSource::Flag),
+        (None, Some(env_value)) if !env_value.trim().is_empty() => {
+            (env_value.trim(), SettingSource::Environment)
+        }
+        _ => (default, SettingSource::Default),
+    }
+}
+
+/// Runs `operation` until it succeeds or fails with something other than a network error, trying
+/// at most `retries` more times. The wait between attempts starts at `delay` and doubles each time
+fn retry_network_errors<T>(
+    retries: u32,
+    mut delay: Duration,
+    description: &str,
+    mut operation: impl FnMut() -> Result<T, crate::Error>,
+) -> Result<T, crate::Error> {
+    let mut attempt = 0;
+    loop {
+        match operation() {
+            Err(err)
+                if attempt < retries
+                    && matches!(&err, crate::Error::Setup(git) if git.is_network_error()) =>
+            {
+                attempt += 1;
+                log!(
+                    "{} failed with a network error, retrying in {:?} (retry {} of {})",
+                    description,
+                    delay,
+                    attempt,
+                    retries
+                );
+                std::thread::sleep(delay);
+                delay *= 2;
+            }
+            result => return result,
+        }
+    }
+}
+
 fn run_git_command(args: &[&str], current_dir: &str) -> Result<String, crate::Error> {
-    let mut process = Command::new("git")
+    let process = Command::new("git")
         .current_dir(current_dir)
         .args(args)
+        .stdout(crate::util::child_stdout())
+        .stderr(Stdio::piped())
         .spawn()?;
 
-    let exit_code = process.wait()?;
-    let mut buf = String::new();
-    if let Some(mut stdout) = process.stdout {
-        let _ = stdout.read_to_string(&mut buf)?;
-    }
-    if !exit_code.success() {
+    // stderr is captured to tell network errors apart, and still shown
+    let output = process.wait_with_output()?;
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    eprint!("{}", stderr);
+    if !output.status.success() {
         let message = args.join(" ");
-        Err(GitError::new(message, buf, exit_code).into())
+        Err(GitError::new(message, stderr, output.status).into())
     } else {
-        Ok(buf)
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+/// The commit checked out in the repo at `path`, or `None` if it isn't a git repo
+pub fn head_commit(path: impl AsRef<std::path::Path>) -> Option<String> {
+    let output = Command::new("git")
+        .current_dir(path)
+        .args(["rev-parse", "HEAD"])
+        .output()
+        .ok()?;
+    if output.status.success() {
+        Some(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn git_error(message: &str) -> crate::Error {
+        use std::os::unix::process::ExitStatusExt;
+        GitError::new(
+            "clone".to_owned(),
+            message,
+            ExitStatusExt::from_raw(128 << 8),
+        )
+        .into()
+    }
+
+    #[test]
+    fn setting_sources() {
+        assert_eq!(
+            pick_setting(Some("abc"), Some("def"), "ghi"),
+            ("abc", SettingSource::Flag)
+        );
+        assert_eq!(
+            pick_setting(None, Some(" def\n"), "ghi"),
+            ("def", SettingSource::Environment)
+        );
+        assert_eq!(
+            pick_setting(None, Some(""), "ghi"),
+            ("ghi", SettingSource::Default)
+        );
+        assert_eq!(
+            pick_setting(None, None, "ghi"),
+            ("ghi", SettingSource::Default)
+        );
+    }
+
+    #[test]
+    fn network_errors() {
+        let unreachable = "fatal: unable to access 'https://github.com/x.git/': Could not resolve host: github.com";
+        let attempts = std::cell::Cell::new(0);
+        let result: Result<(), _> = retry_network_errors(2, Duration::ZERO, "Clone", || {
+            attempts.set(attempts.get() + 1);
+            Err(git_error(unreachable))
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts.get(), 3);
+
+        let missing = "fatal: reference is not a tree: ba8ea4ac58eada9679146ba2dc755789bbfbe91e";
+        attempts.set(0);
+        let result: Result<(), _> = retry_network_errors(2, Duration::ZERO, "Checkout", || {
+            attempts.set(attempts.get() + 1);
+            Err(git_error(missing))
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts.get(), 1);
+
+        attempts.set(0);
+        let result = retry_network_errors(2, Duration::ZERO, "Clone", || {
+            attempts.set(attempts.get() + 1);
+            if attempts.get() < 2 {
+                Err(git_error(unreachable))
+            } else {
+                Ok(5)
+            }
+        });
+        assert_eq!(result.unwrap(), 5);
+        assert_eq!(attempts.get(), 2);
     }
 }
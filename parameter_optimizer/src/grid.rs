@@ -0,0 +1,138 @@
+use std::collections::{BTreeSet, VecDeque};
+use std::ops::Range;
+use std::path::Path;
+
+/// An evenly spaced grid over the parameter space. Cells are numbered so that the last
+/// parameter changes fastest
+pub struct Grid {
+    steps: usize,
+    ranges: Vec<Range<f64>>,
+}
+
+impl Grid {
+    /// A grid with `steps` values for each parameter, from the start to the end of its range
+    pub fn new(steps: usize, ranges: Vec<Range<f64>>) -> Self {
+        assert!(steps >= 2, "A grid needs at least 2 steps per parameter");
+        Self { steps, ranges }
+    }
+
+    pub fn cells(&self) -> usize {
+        self.steps.pow(self.ranges.len() as u32)
+    }
+
+    /// The value of each parameter at cell `index`
+    pub fn cell_values(&self, index: usize) -> Vec<f64> {
+        let mut remaining = index;
+        let mut values = vec![0.0; self.ranges.len()];
+        for (value, range) in values.iter_mut().zip(&self.ranges).rev() {
+            let step = remaining % self.steps;
+            remaining /= self.steps;
+            *value =
+                range.start + (range.end - range.start) * step as f64 / (self.steps - 1) as f64;
+        }
+        values
+    }
+}
+
+/// Which cells of a grid sweep have been evaluated, saved after every cell so an interrupted
+/// sweep can be resumed
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default, PartialEq)]
+pub struct GridProgress {
+    /// The grid this progress belongs to, checked before resuming
+    pub steps: usize,
+    pub parameters: Vec<String>,
+    /// The (lowest, highest) value of each parameter. Empty in progress saved before ranges were
+    /// recorded, which is resumed whatever the ranges are
+    #[serde(default)]
+    pub ranges: Vec<(f64, f64)>,
+
+    pub completed: BTreeSet<usize>,
+}
+
+impl GridProgress {
+    pub fn new(steps: usize, parameters: Vec<String>, ranges: Vec<(f64, f64)>) -> Self {
+        Self {
+            steps,
+            parameters,
+            ranges,
+            completed: BTreeSet::new(),
+        }
+    }
+
+    /// Loads the progress at `path`, failing if it was saved for a different grid
+    pub fn load(
+        path: impl AsRef<Path>,
+        steps: usize,
+        parameters: &[String],
+        ranges: &[(f64, f64)],
+    ) -> Result<Self, crate::Error> {
+        let mut progress: Self = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        if progress.steps != steps || progress.parameters != parameters {
+            return Err(format!(
+                "Saved progress is for a grid with {} steps over {:?}, not {} steps over {:?}",
+                progress.steps, progress.parameters, steps, parameters
+            )
+            .into());
+        }
+        if !progress.ranges.is_empty() && progress.ranges != ranges {
+            return Err(format!(
+                "Saved progress is for a grid over the ranges {:?}, not {:?}",
+                progress.ranges, ranges
+            )
+            .into());
+        }
+        progress.ranges = ranges.to_vec();
+        Ok(progress)
+    }
+
+    /// Writes to a temporary file first so a crash while saving can't lose earlier progress
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), crate::Error> {
+        let path = path.as_ref();
+        let temp = path.with_extension("tmp");
+        std::fs::write(&temp, serde_json::to_string(self)?)?;
+        std::fs::rename(temp, path)?;
+        Ok(())
+    }
+
+    /// The cells of a grid with `cells` cells that haven't been evaluated yet, in order
+    pub fn remaining(&self, cells: usize) -> VecDeque<usize> {
+        (0..cells)
+            .filter(|cell| !self.completed.contains(cell))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cell_values() {
+        let grid = Grid::new(3, vec![0.0..2.0, 10.0..20.0]);
+        assert_eq!(grid.cells(), 9);
+        assert_eq!(grid.cell_values(0), vec![0.0, 10.0]);
+        assert_eq!(grid.cell_values(1), vec![0.0, 15.0]);
+        assert_eq!(grid.cell_values(5), vec![1.0, 20.0]);
+        assert_eq!(grid.cell_values(8), vec![2.0, 20.0]);
+    }
+
+    #[test]
+    fn resume_progress() {
+        let params = vec!["a".to_owned(), "r".to_owned()];
+        let ranges = vec![(0.0, 18.0), (0.0, 9.0)];
+        let mut progress = GridProgress::new(3, params.clone(), ranges.clone());
+        progress.completed.extend([0, 2, 3, 8]);
+        assert_eq!(progress.remaining(9), VecDeque::from(vec![1, 4, 5, 6, 7]));
+
+        let path = std::env::temp_dir().join(format!("grid-progress-{}.json", std::process::id()));
+        progress.save(&path).unwrap();
+        assert_eq!(
+            GridProgress::load(&path, 3, &params, &ranges).unwrap(),
+            progress
+        );
+        assert!(GridProgress::load(&path, 4, &params, &ranges).is_err());
+        assert!(GridProgress::load(&path, 3, &params[..1], &ranges).is_err());
+        assert!(GridProgress::load(&path, 3, &params, &[(0.0, 18.0); 2]).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -1,22 +1,38 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use std::collections::HashMap;
 
+mod animation;
+mod config;
+mod error;
+mod fitness;
+#[cfg(feature = "gaussian-process")]
+mod gaussian_process;
 mod git;
+mod grid;
+mod manifest;
 mod optimization;
 mod position_parser;
+#[cfg(feature = "remote")]
+mod remote;
 mod util;
+mod warmup;
 
-type Error = Box<dyn std::error::Error>;
+type Error = error::OptimizerError;
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
     /// Name of the person to greet
     #[clap(
         long,
-        help = "Re-exports the graphs associated with the json run data in RE-EXPORT"
+        multiple_occurrences(true),
+        multiple_values(true),
+        help = "Re-exports the graphs associated with the json run data in RE-EXPORT. The runs of several files, such as from a sweep split between machines, are merged into one set of graphs"
     )]
-    re_export: Option<String>,
+    re_export: Vec<String>,
 
     #[clap(
         long,
@@ -33,47 +49,602 @@ struct Args {
         help = "When enabled, checks out the commit that the program wants"
     )]
     use_git: bool,
+
+    #[clap(
+        long,
+        help = "The NS3 commit --use-git checks out. Overrides the NS3_COMMIT environment variable"
+    )]
+    ns3_commit: Option<String>,
+
+    #[clap(
+        long,
+        help = "The NS3 repo --use-git clones. Overrides the NS3_REPO_URL environment variable"
+    )]
+    ns3_repo_url: Option<String>,
+
+    #[clap(
+        long,
+        help = "Loads optimizer settings from the file CONFIG, which is read as YAML if it ends in .yaml or .yml and as json otherwise"
+    )]
+    config: Option<String>,
+
+    #[clap(
+        long,
+        multiple_occurrences(true),
+        help = "Overrides one config setting, such as `--set fitness.recovery_weight=200`. Can be given more than once"
+    )]
+    set: Vec<String>,
+
+    #[clap(
+        long,
+        multiple_occurrences(true),
+        parse(try_from_str = config::parse_param_range),
+        help = "Restricts one parameter to MIN..MAX, such as `--param-range a=0:9`, so a grid sweep or TPE search only covers part of the space. Can be given more than once"
+    )]
+    param_range: Vec<(String, (f64, f64))>,
+
+    #[clap(
+        long,
+        help = "Stops the optimizer when too many recent samples failed instead of only warning"
+    )]
+    abort_on_failure_rate: bool,
+
+    #[clap(
+        long,
+        help = "Stops the optimizer at the first NaN or infinite fitness and keeps that simulation's positions file"
+    )]
+    fail_on_nan_fitness: bool,
+
+    #[clap(
+        long,
+        help = "Kills simulations that use more than SIM_MEM_LIMIT megabytes of memory (Unix only)"
+    )]
+    sim_mem_limit: Option<u64>,
+
+    #[clap(
+        long,
+        help = "Writes each simulation's stdout and stderr to SIM_LOG_DIR/<positions file>.log, keeping the logs of failed samples"
+    )]
+    sim_log_dir: Option<std::path::PathBuf>,
+
+    #[clap(
+        long,
+        help = "Keeps the positions file and simulation log of every scored sample instead of deleting them. The positions files are gzipped unless compress_positions is turned off"
+    )]
+    keep_all_positions: bool,
+
+    #[clap(
+        long,
+        help = "Stops once the best error hasn't improved for PATIENCE evaluations"
+    )]
+    patience: Option<usize>,
+
+    #[clap(
+        long,
+        help = "Shows a rough estimate of how many more evaluations it will take to reach TARGET_FITNESS in the status line"
+    )]
+    target_fitness: Option<f64>,
+
+    #[clap(
+        long,
+        help = "Runs each simulation for MAX_SIM_DURATION seconds, regardless of which part of it is scored"
+    )]
+    max_sim_duration: Option<f64>,
+
+    #[clap(
+        long,
+        help = "Passes parameter values to the simulation and names the best positions files with POSITIONS_PRECISION decimal places"
+    )]
+    positions_precision: Option<usize>,
+
+    #[clap(
+        long,
+        help = "Runs the shell command ON_NEW_BEST whenever a new best error is found, with the error and parameters in OPTIMIZER_* environment variables"
+    )]
+    on_new_best: Option<String>,
+
+    #[clap(
+        long,
+        conflicts_with = "overwrite-results",
+        help = "Adds this run's rows to an existing results.csv instead of writing a new timestamped results file. Fails if its columns don't match"
+    )]
+    append_results: bool,
+
+    #[clap(
+        long,
+        help = "Replaces results.csv instead of writing a new timestamped results file"
+    )]
+    overwrite_results: bool,
+
+    #[clap(
+        long,
+        help = "Writes the plots, results, backup, manifest and best positions files to OUT-DIR instead of a new timestamped run directory"
+    )]
+    out_dir: Option<std::path::PathBuf>,
+
+    #[clap(long, help = "Prints extra progress messages")]
+    verbose: bool,
+
+    #[clap(
+        long,
+        help = "Prints the shell command for every simulation so it can be re-run and re-scored by hand with `analyze`"
+    )]
+    log_commands: bool,
+
+    #[clap(
+        long,
+        help = "Writes one json object per completed sample to stdout and moves progress messages to stderr"
+    )]
+    ndjson: bool,
+
+    #[clap(
+        long,
+        help = "Reports the total time spent simulating, parsing and scoring when the optimizer stops"
+    )]
+    profile: bool,
+
+    #[clap(
+        long,
+        help = "Continues an interrupted grid sweep, skipping the cells it already evaluated"
+    )]
+    resume: bool,
+
+    #[clap(
+        long,
+        help = "Seeds the optimizers with the runs in a results.csv from an earlier optimization. Parameters added since then start without history"
+    )]
+    warm_start: Option<String>,
+
+    #[clap(
+        long,
+        help = "Evaluates the parameter set BASELINE_PARAMS, written as name=value pairs such as `a=1.5,r=2`, before optimizing and reports the best error relative to it at the end"
+    )]
+    baseline_params: Option<String>,
+
+    #[clap(
+        long,
+        help = "Runs one simulation with the parameter set SINGLE_EVAL, written as name=value pairs such as `a=1.5,r=2`, with a fixed seed, prints its error and breakdown and exits without optimizing"
+    )]
+    single_eval: Option<String>,
+
+    #[clap(
+        long,
+        help = "Only warm starts from the last WARM_START_RECENT results in the csv"
+    )]
+    warm_start_recent: Option<usize>,
+
+    #[clap(
+        long,
+        help = "Only warm starts from results whose error is at most WARM_START_BAND above the lowest one"
+    )]
+    warm_start_band: Option<f64>,
+
+    #[clap(
+        long,
+        help = "Also exports hot/cold graphs colored by the distance, velocity and stability costs"
+    )]
+    per_term_plots: bool,
+
+    #[clap(
+        long,
+        help = "Also writes the runs binned into a grid over the hot/cold graph's parameters to the csv EXPORT_GRID, with the sample count and mean and lowest error of each cell"
+    )]
+    export_grid: Option<String>,
+
+    #[clap(
+        long,
+        parse(try_from_str = config::parse_color_range),
+        help = "Colors the hot/cold graph over the fixed fitness range MIN:MAX instead of the run's own errors, so graphs from different runs can be compared"
+    )]
+    fitness_color_range: Option<(f64, f64)>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Computes the error score of existing positions files without running any simulations
+    Analyze {
+        #[clap(required = true)]
+        positions_files: Vec<String>,
+
+        #[clap(
+            long,
+            help = "Always parse the csv files instead of using or writing .simdata caches"
+        )]
+        no_cache: bool,
+    },
+
+    /// Runs one simulation with the default parameters and scores it, reporting how long each step
+    /// took. Exits with an error if any step fails
+    SmokeTest,
+
+    /// Evaluates the parameter sets in a csv in order without involving the optimizer.
+    /// The header row names the parameters, and an optional `seed` column sets the simulation seed
+    Replay { params_csv: String },
+
+    /// Runs one parameter set several times with different seeds and reports how much the error
+    /// varies. PARAMS is written as name=value pairs, such as `a=1.5,r=2`
+    MeasureNoise {
+        params: String,
+
+        #[clap(
+            long,
+            default_value = "10",
+            help = "How many seeds to run the parameters with"
+        )]
+        repeats: usize,
+    },
+
+    /// Estimates how much the error changes with each parameter around PARAMS by evaluating it with
+    /// one parameter at a time moved STEP down and up, stopping at the ends of its range. PARAMS is
+    /// written as name=value pairs
+    Sensitivity {
+        params: String,
+
+        #[clap(long, default_value = "0.1", help = "How far to move each parameter")]
+        step: f64,
+
+        #[clap(
+            long,
+            default_value = "1",
+            help = "The simulation seed, shared by every evaluation so only the parameters change"
+        )]
+        seed: usize,
+    },
+
+    /// Draws the UAVs in a positions file from above over time, with the central node in red, as a
+    /// GIF or as a directory of numbered PNGs
+    Animate {
+        positions_file: String,
+
+        #[clap(
+            long,
+            default_value = "animation.gif",
+            help = "Writes a GIF if OUTPUT ends in .gif, and otherwise one PNG per frame in the directory OUTPUT"
+        )]
+        output: String,
+
+        #[clap(
+            long,
+            help = "Seconds of simulation time between frames. Defaults to the interval the positions were logged at"
+        )]
+        frame_interval: Option<f32>,
+
+        #[clap(
+            long,
+            default_value = "100",
+            help = "How many milliseconds each GIF frame is shown for"
+        )]
+        frame_delay: u32,
+    },
+
+    /// Prints the parameter sets the optimizers would try next after being warm started from the
+    /// runs in RESULTS_CSV, without running any simulations. The output is a csv that `replay` can
+    /// evaluate
+    Suggest {
+        results_csv: String,
+
+        #[clap(
+            long,
+            default_value = "10",
+            help = "How many parameter sets to suggest"
+        )]
+        count: usize,
+    },
+
+    /// Parses positions files without scoring them and reports each one's UAVs, time range and
+    /// logging interval along with any gaps, out of order timesteps, duplicate lines or non-finite
+    /// positions. Exits with an error if any file has problems
+    Validate {
+        #[clap(
+            required = true,
+            help = "Positions files, or patterns such as 'output/*.csv' where only the file name has wildcards"
+        )]
+        files: Vec<String>,
+    },
+
+    /// Parses a positions file with both the in-memory and the streaming parser and reports any
+    /// difference between the results. Used to check parser changes
+    #[clap(hide = true)]
+    VerifyParser { positions_file: String },
+}
+
+/// The value in `result`, or prints the error and exits with a failure code
+fn exit_on_error<T>(result: Result<T, Error>, context: &str) -> T {
+    match result {
+        Ok(value) => value,
+        Err(err) => {
+            eprintln!("{}: {}", context, err);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Runs waf configure for the NS3 repo at `path`
+fn configure_repo(path: &str) -> Result<(), Error> {
+    util::run_waf_command(
+        path,
+        "configure --build-profile=optimized",
+        map!("CXXFLAGS" => "-Wall"),
+    )
+}
+
+/// Builds the NS3 repo at `path` and checks that the simulation binary was produced. If it wasn't,
+/// such as when the build directory was deleted without git noticing, configure and build are run
+/// once more before giving up
+fn build_repo(path: &str) -> Result<optimization::Ns3Paths, Error> {
+    util::run_waf_command(path, "build", HashMap::new())?;
+    let paths = optimization::Ns3Paths::from_repo(path);
+    if paths.binary().exists() {
+        return Ok(paths);
+    }
+
+    log!(
+        "Build finished without producing {}, running configure and build again",
+        paths.binary().display()
+    );
+    configure_repo(path)?;
+    util::run_waf_command(path, "build", HashMap::new())?;
+    if paths.binary().exists() {
+        Ok(paths)
+    } else {
+        Err(Error::Build(format!(
+            "Build finished after re-running configure but {} is still missing",
+            paths.binary().display()
+        )))
+    }
 }
 
 fn main() {
     let args = Args::parse();
+    let mut config = match &args.config {
+        Some(config_path) => exit_on_error(
+            config::Config::load(config_path),
+            &format!("Error while loading config {}", config_path),
+        ),
+        None => config::Config::default(),
+    };
+    for assignment in &args.set {
+        exit_on_error(
+            config.set(assignment),
+            &format!("Error in --set {}", assignment),
+        );
+    }
+    for (name, range) in &args.param_range {
+        exit_on_error(
+            config.restrict_parameter(name, *range),
+            &format!("Error in --param-range {}", name),
+        );
+    }
+    if args.verbose {
+        config.verbose = true;
+    }
+    if args.log_commands {
+        config.log_commands = true;
+    }
+    if args.profile {
+        config.profile = true;
+    }
+    if args.ndjson {
+        config.ndjson = true;
+    }
+    if config.ndjson {
+        util::LOG_TO_STDERR.store(true, std::sync::atomic::Ordering::Relaxed);
+    }
+    if args.per_term_plots {
+        config.plot.per_term = true;
+    }
+    if args.fitness_color_range.is_some() {
+        config.plot.fitness_color_range = args.fitness_color_range;
+    }
+    if let Some(path) = &args.export_grid {
+        config.plot.export_grid = Some(path.into());
+    }
+    if args.patience.is_some() {
+        config.patience = args.patience;
+    }
+    if args.sim_log_dir.is_some() {
+        config.sim_log_dir = args.sim_log_dir.clone();
+    }
+    if args.keep_all_positions {
+        config.keep_all_positions = true;
+    }
+    if args.target_fitness.is_some() {
+        config.target_fitness = args.target_fitness;
+    }
+    if let Some(duration) = args.max_sim_duration {
+        config.sim_duration = duration;
+    }
+    if args.positions_precision.is_some() {
+        config.positions_precision = args.positions_precision;
+    }
+    if args.sim_mem_limit.is_some() {
+        config.simulation_memory_limit = args.sim_mem_limit;
+    }
+    if args.on_new_best.is_some() {
+        config.on_new_best = args.on_new_best.clone();
+    }
+    if args.warm_start_recent.is_some() {
+        config.warm_start.recent = args.warm_start_recent;
+    }
+    if args.warm_start_band.is_some() {
+        config.warm_start.error_band = args.warm_start_band;
+    }
+    if args.append_results {
+        config.results_file = config::ResultsFile::Append;
+    }
+    if args.overwrite_results {
+        config.results_file = config::ResultsFile::Overwrite;
+    }
+    if args.out_dir.is_some() {
+        config.out_dir = args.out_dir;
+    }
+    if args.abort_on_failure_rate {
+        config.failure_monitor.abort = true;
+    }
+    if args.fail_on_nan_fitness {
+        config.fail_on_nan_fitness = true;
+    }
+    // After the command line overrides, which can be invalid too
+    exit_on_error(config.validate(), "Invalid config");
+
+    if let Some(assignments) = &args.single_eval {
+        if args.command.is_some() {
+            eprintln!("--single-eval can't be combined with a subcommand");
+            std::process::exit(1);
+        }
+        // Check the parameters before spending time setting up NS3
+        exit_on_error(
+            optimization::parse_parameter_assignments(assignments, &config).map(|_| ()),
+            "Invalid --single-eval parameters",
+        );
+    }
+
+    if let Some(Command::Analyze {
+        positions_files,
+        no_cache,
+    }) = &args.command
+    {
+        exit_on_error(
+            optimization::analyze(positions_files, &config, !no_cache),
+            "Error while analyzing",
+        );
+        return;
+    }
+    if let Some(Command::Animate {
+        positions_file,
+        output,
+        frame_interval,
+        frame_delay,
+    }) = &args.command
+    {
+        exit_on_error(
+            animation::animate(
+                positions_file,
+                output,
+                *frame_interval,
+                *frame_delay,
+                &config,
+            ),
+            "Error while animating",
+        );
+        return;
+    }
+    if let Some(Command::Suggest { results_csv, count }) = &args.command {
+        exit_on_error(
+            optimization::suggest(&config, results_csv, *count),
+            "Error while suggesting parameters",
+        );
+        return;
+    }
+    if let Some(Command::Validate { files }) = &args.command {
+        exit_on_error(optimization::validate(files), "Validation failed");
+        return;
+    }
+    if let Some(Command::VerifyParser { positions_file }) = &args.command {
+        exit_on_error(
+            optimization::verify_parser(positions_file, &config),
+            "Parsers disagree",
+        );
+        return;
+    }
 
     let path = "NS3".to_owned();
-    if args.use_git {
-        let url = "https://github.com/TroyNeubauer/NS3NonIdealConditions2021.git";
-        let needs_configure = match git::setup_repo(&git::RepoInfo {
-            url: url.to_owned(),
-            path: path.to_owned(),
-            commit_hash: "ba8ea4ac58eada9679146ba2dc755789bbfbe91e".to_owned(),
-        }) {
-            Ok(needs_configure) => needs_configure,
-            Err(err) => {
-                eprintln!("Error while setting up repo: {}", err);
-                return;
-            }
-        };
+    if config.remote.is_some() && args.use_git {
+        log!("Running simulations on the remote host, ignoring --use-git");
+    } else if config.install.is_some() && args.use_git {
+        log!("Using the NS3 install from the config, ignoring --use-git");
+    }
+    if args.use_git && config.install.is_none() && config.remote.is_none() {
+        let url = git::repo_setting(
+            "NS3 repo",
+            args.ns3_repo_url.as_deref(),
+            "NS3_REPO_URL",
+            git::DEFAULT_REPO_URL,
+        );
+        let commit_hash = git::repo_setting(
+            "NS3 commit",
+            args.ns3_commit.as_deref(),
+            "NS3_COMMIT",
+            git::DEFAULT_COMMIT,
+        );
+        let needs_configure = exit_on_error(
+            git::setup_repo(&git::RepoInfo {
+                url,
+                path: path.to_owned(),
+                commit_hash,
+                retries: config.git_retries,
+            }),
+            "Error while setting up repo",
+        );
         if needs_configure {
-            println!("Running configure");
-            util::run_waf_command(
-                &path,
-                "configure --build-profile=optimized",
-                map!("CXXFLAGS" => "-Wall"),
-            )
-            .unwrap();
+            log!("Running configure");
+            exit_on_error(configure_repo(&path), "Failed to configure NS3");
         }
     }
 
-    if let Some(file_path) = args.re_export {
-        println!("Re-exporting data from {}", file_path);
-        optimization::re_export(&file_path, args.re_export_prefix.as_deref())
-            .expect("Failed to re-export data");
+    if !args.re_export.is_empty() {
+        log!("Re-exporting data from {}", args.re_export.join(", "));
+        exit_on_error(
+            optimization::re_export(&args.re_export, args.re_export_prefix.as_deref(), &config),
+            "Failed to re-export data",
+        );
     } else if let Some(dir_path) = args.re_export_all {
-        optimization::re_export_all(&dir_path)
-            .expect("Failed to re-export data");
-        
+        exit_on_error(
+            optimization::re_export_all(&dir_path, &config),
+            "Failed to re-export data",
+        );
     } else {
-        util::run_waf_command(&path, "build", HashMap::new()).expect("failed to build waf");
+        let paths = match (&config.remote, &config.install) {
+            (Some(remote), _) => exit_on_error(
+                optimization::Ns3Paths::from_remote(remote),
+                &format!("Can't run simulations on {}", remote.host),
+            ),
+            (None, Some(install)) => optimization::Ns3Paths::from_install(install),
+            (None, None) => exit_on_error(build_repo(&path), "Failed to build NS3"),
+        };
 
-        optimization::run(&path);
+        if let Some(assignments) = &args.single_eval {
+            exit_on_error(
+                optimization::single_eval(&paths, &config, assignments),
+                "Single evaluation failed",
+            );
+            return;
+        }
+        match args.command {
+            Some(Command::SmokeTest) => exit_on_error(
+                optimization::smoke_test(&paths, &config),
+                "Smoke test failed",
+            ),
+            Some(Command::Replay { params_csv }) => exit_on_error(
+                optimization::replay(&paths, &config, &params_csv),
+                "Replay failed",
+            ),
+            Some(Command::MeasureNoise { params, repeats }) => exit_on_error(
+                optimization::measure_noise(&paths, &config, &params, repeats),
+                "Measuring noise failed",
+            ),
+            Some(Command::Sensitivity { params, step, seed }) => exit_on_error(
+                optimization::sensitivity(&paths, &config, &params, step, seed),
+                "Sensitivity analysis failed",
+            ),
+            Some(Command::Analyze { .. })
+            | Some(Command::Animate { .. })
+            | Some(Command::Suggest { .. })
+            | Some(Command::Validate { .. })
+            | Some(Command::VerifyParser { .. }) => {
+                unreachable!("handled before building")
+            }
+            None => exit_on_error(
+                optimization::run(
+                    paths,
+                    config,
+                    args.resume,
+                    args.warm_start.as_deref(),
+                    args.baseline_params.as_deref(),
+                )
+                .map(|report| log!("{}", report)),
+                "Optimization failed",
+            ),
+        }
     }
 }
@@ -0,0 +1,64 @@
+use crate::config::Config;
+use crate::optimization::Ns3Paths;
+
+use indexmap::IndexMap;
+use std::path::Path;
+use std::time::SystemTime;
+
+/// Environment variables that change how the simulation runs or which libraries it loads
+const RECORDED_ENV_VARS: [&str; 5] = [
+    "LD_LIBRARY_PATH",
+    "NS_LOG",
+    "NS_GLOBAL_VALUE",
+    "CXXFLAGS",
+    "PATH",
+];
+
+/// Everything needed to know how a set of results was produced, written when a run starts
+#[derive(serde::Serialize)]
+struct Manifest<'a> {
+    optimizer_version: &'static str,
+    started: String,
+    command_line: Vec<String>,
+    hostname: Option<String>,
+    cpus: usize,
+    /// The commit checked out in the NS3 working directory, if it is a git repo
+    ns3_commit: Option<String>,
+    paths: &'a Ns3Paths,
+    env: IndexMap<&'static str, Option<String>>,
+    config: &'a Config,
+}
+
+fn hostname() -> Option<String> {
+    let output = std::process::Command::new("hostname").output().ok()?;
+    let name = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+    if output.status.success() && !name.is_empty() {
+        Some(name)
+    } else {
+        std::env::var("HOSTNAME").ok()
+    }
+}
+
+/// Writes the manifest for a run using `paths` and `config` as json
+pub fn write(
+    path: impl AsRef<Path>,
+    paths: &Ns3Paths,
+    config: &Config,
+) -> Result<(), crate::Error> {
+    let manifest = Manifest {
+        optimizer_version: env!("CARGO_PKG_VERSION"),
+        started: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+        command_line: std::env::args().collect(),
+        hostname: hostname(),
+        cpus: num_cpus::get(),
+        ns3_commit: crate::git::head_commit(paths.working_dir()),
+        paths,
+        env: RECORDED_ENV_VARS
+            .iter()
+            .map(|name| (*name, std::env::var(name).ok()))
+            .collect(),
+        config,
+    };
+    std::fs::write(path, serde_json::to_string_pretty(&manifest)?)?;
+    Ok(())
+}
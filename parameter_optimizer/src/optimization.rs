@@ -1,17 +1,25 @@
-use crate::position_parser::{SimulationData, TimePoint};
+use crate::config::{
+    Config, EmptyPositionsPolicy, InstallConfig, ParameterConfig, PlotConfig, RemoteConfig,
+    ResultsFile, SearchStrategy, WarmStartConfig,
+};
+use crate::fitness::{self, CostBreakdown, Normalization, TermRanges};
+use crate::grid::{Grid, GridProgress};
+use crate::log;
+use crate::position_parser::SimulationData;
+use crate::util::P2Quantile;
 
-use glam::Vec3A;
 use once_cell::sync::OnceCell;
 use plotters::prelude::*;
 use rand::{distributions::Alphanumeric, Rng};
 
 use indexmap::IndexMap;
+use std::collections::VecDeque;
 use std::ops::{Deref, Range};
 use std::path::{Path, PathBuf};
 use std::process::Command;
-use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
-use std::sync::{Arc, Mutex};
-use std::time::SystemTime;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant, SystemTime};
 
 #[derive(serde::Serialize, serde::Deserialize)]
 struct Parameter {
@@ -20,8 +28,28 @@ struct Parameter {
     optim: tpe::TpeOptimizer,
 }
 
-const PARAM_MAX: f64 = 18.0;
-const PARAM_MIN: f64 = 0.0;
+pub const PARAM_MAX: f64 = 18.0;
+pub const PARAM_MIN: f64 = 0.0;
+/// The value the optimizers are seeded with, and that single test runs use
+const DEFAULT_PARAM_VALUE: f64 = 1.0;
+
+/// Forces a value from an optimizer into the parameter's `range`. The range excludes its end
+/// because `TpeOptimizer::tell` rejects it, so values at or above it become the largest value
+/// below it
+fn clamp_param(value: f64, range: &Range<f64>) -> f64 {
+    let clamped = value.clamp(range.start, range.end * (1.0 - f64::EPSILON));
+    debug_assert!(range.contains(&clamped));
+    clamped
+}
+
+/// The search range of the configured parameter `name`, or the full range if it isn't configured
+fn configured_range(config: &Config, name: &str) -> Range<f64> {
+    config
+        .parameters
+        .iter()
+        .find(|param| param.name == name)
+        .map_or(PARAM_MIN..PARAM_MAX, |param| param.search_range())
+}
 
 fn optim_new() -> tpe::TpeOptimizer {
     tpe::TpeOptimizer::new(
@@ -32,15 +60,34 @@ fn optim_new() -> tpe::TpeOptimizer {
 
 type State = Arc<Mutex<StateImpl>>;
 
-#[derive(serde::Serialize, serde::Deserialize)]
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
 struct SimulationRun {
     /// The parameters used in this run
     parameters: IndexMap<String, f64>,
     /// The error score for this run
     #[serde(rename = "fitness")]
     error: f64,
+    /// The terms that were summed to get `error`. With `scenario_seeds` these are from the seed
+    /// with the worst error
+    #[serde(default)]
+    breakdown: CostBreakdown,
+    /// The error with each of the `scenario_seeds`, empty when they aren't used
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    seed_errors: IndexMap<usize, f64>,
+    /// The terms of each of the `scenario_seeds` in the order of `seed_errors`, so the errors can
+    /// be normalized again as the term ranges grow. Empty when they aren't used
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    seed_breakdowns: Vec<CostBreakdown>,
     /// The time this run finished
     time: SystemTime,
+    /// The order this run's parameters were picked in, starting at 0. Runs can finish in a
+    /// different order when several runners are active. 0 in backups from before it was recorded
+    #[serde(default)]
+    sample: usize,
+    /// The optimization run this run was evaluated in, named by the time it started. Empty in
+    /// backups from before it was recorded
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    session: String,
 }
 
 #[derive(serde::Serialize, serde::Deserialize)]
@@ -51,114 +98,756 @@ struct StateImpl {
 
     /// Finished runs
     results: Vec<SimulationRun>,
+
+    /// The range of each cost term over `results`, used by `Normalization::PopulationRange`
+    #[serde(default)]
+    term_ranges: TermRanges,
+
+    /// Estimates of the error distribution for the status line, updated as results arrive so the
+    /// results don't have to be sorted every time
+    #[serde(skip)]
+    error_quantiles: ErrorQuantiles,
+}
+
+struct ErrorQuantiles {
+    median: P2Quantile,
+    p90: P2Quantile,
+}
+
+impl Default for ErrorQuantiles {
+    fn default() -> Self {
+        Self {
+            median: P2Quantile::new(0.5),
+            p90: P2Quantile::new(0.9),
+        }
+    }
+}
+
+impl ErrorQuantiles {
+    fn add(&mut self, error: f64) {
+        self.median.add(error);
+        self.p90.add(error);
+    }
 }
 
 static RUNNING: AtomicBool = AtomicBool::new(true);
-static PATH: OnceCell<String> = OnceCell::new();
+/// Why the runners were told to stop, reported once they have all exited
+static STOP_REASON: OnceCell<String> = OnceCell::new();
+/// Set once a runner has picked up the configured default parameters as its sample
+static DEFAULTS_EVALUATED: AtomicBool = AtomicBool::new(false);
+/// The `seed_points` that haven't been picked up by a runner yet
+static SEED_POINTS: OnceCell<Mutex<VecDeque<IndexMap<String, f64>>>> = OnceCell::new();
+/// The `warmup_samples` that haven't been picked up by a runner yet
+static WARMUP_POINTS: OnceCell<Mutex<VecDeque<IndexMap<String, f64>>>> = OnceCell::new();
+/// How many of the default parameters, seed points and warmup samples haven't finished yet. The
+/// optimizers aren't asked for a sample until they all have
+static PLANNED_SAMPLES: AtomicUsize = AtomicUsize::new(0);
+/// The index the next picked sample gets in `SimulationRun::sample`
+static NEXT_SAMPLE: AtomicUsize = AtomicUsize::new(0);
+/// How many evaluations had finished when `LOWEST_ERROR` last improved
+static LAST_IMPROVEMENT: AtomicUsize = AtomicUsize::new(0);
+static PATHS: OnceCell<Ns3Paths> = OnceCell::new();
 static STATE: OnceCell<State> = OnceCell::new();
+static CONFIG: OnceCell<Config> = OnceCell::new();
+
+static ACTIVE_RUNNERS: AtomicUsize = AtomicUsize::new(0);
+/// Whether each of the most recent samples failed, newest in the lowest bit
+static RECENT_FAILURES: AtomicU64 = AtomicU64::new(0);
+/// How many samples have been recorded in `RECENT_FAILURES`
+static RECENT_SAMPLES: AtomicUsize = AtomicUsize::new(0);
+/// How many samples panicked while being scored
+static PANICKED_SAMPLES: AtomicUsize = AtomicUsize::new(0);
+static RUN_START: OnceCell<Instant> = OnceCell::new();
+/// `SimulationRun::session` of the runs evaluated by this process
+static SESSION: OnceCell<String> = OnceCell::new();
+/// Milliseconds after `RUN_START` that each runner last finished a sample successfully
+static LAST_SUCCESS: OnceCell<Vec<AtomicU64>> = OnceCell::new();
+/// Microseconds spent by all runners in each phase of a sample, reported with `profile`
+static SIMULATION_TIME: AtomicU64 = AtomicU64::new(0);
+static PARSE_TIME: AtomicU64 = AtomicU64::new(0);
+static FITNESS_TIME: AtomicU64 = AtomicU64::new(0);
+/// Set when running a grid sweep instead of letting the optimizers pick parameters
+static GRID: OnceCell<GridSweep> = OnceCell::new();
+/// Set when the Gaussian process picks the parameters instead of the TPE optimizers
+#[cfg(feature = "gaussian-process")]
+static GAUSSIAN_PROCESS: OnceCell<Mutex<crate::gaussian_process::GaussianProcess>> =
+    OnceCell::new();
+
+/// Where a grid sweep records which cells have been evaluated
+const GRID_PROGRESS_FILE: &str = "grid-progress.json";
+/// The runs of the cells in `GRID_PROGRESS_FILE`, one json object per line, so a resumed sweep
+/// exports the runs of earlier sessions too
+const GRID_RESULTS_FILE: &str = "grid-results.ndjson";
+
+/// The state of a grid sweep shared by all runners
+struct GridSweep {
+    grid: Grid,
+    /// Cells that no runner has picked up yet
+    queue: Mutex<VecDeque<usize>>,
+    progress: Mutex<GridProgress>,
+}
+
+impl GridSweep {
+    fn new(steps: usize, config: &Config, resume: bool) -> Result<Self, crate::Error> {
+        let names: Vec<String> = config.parameters.iter().map(|p| p.name.clone()).collect();
+        let ranges: Vec<Range<f64>> = config.parameters.iter().map(|p| p.search_range()).collect();
+        let bounds: Vec<(f64, f64)> = ranges.iter().map(|r| (r.start, r.end)).collect();
+        let grid = Grid::new(steps, ranges);
+        let progress = if resume && Path::new(GRID_PROGRESS_FILE).exists() {
+            GridProgress::load(GRID_PROGRESS_FILE, steps, &names, &bounds)?
+        } else {
+            if resume {
+                log!(
+                    "No {} to resume from, starting a new sweep",
+                    GRID_PROGRESS_FILE
+                );
+            }
+            // The runs of an earlier sweep don't belong to this one
+            match std::fs::remove_file(GRID_RESULTS_FILE) {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            }
+            GridProgress::new(steps, names, bounds)
+        };
+        let queue = progress.remaining(grid.cells());
+        log!(
+            "Grid sweep of {} cells, {} left to evaluate",
+            grid.cells(),
+            queue.len()
+        );
+        Ok(Self {
+            grid,
+            queue: Mutex::new(queue),
+            progress: Mutex::new(progress),
+        })
+    }
+
+    /// Records that `cell` was evaluated with the result `run` and saves the progress
+    fn complete(&self, cell: usize, run: &SimulationRun) {
+        use std::io::Write;
+
+        let mut progress = self.progress.lock().unwrap();
+        let saved = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(GRID_RESULTS_FILE)
+            .and_then(|mut file| writeln!(file, "{}", serde_json::to_string(run).unwrap()));
+        if let Err(err) = saved {
+            log!("Failed to save the grid result: {}", err);
+        }
+        progress.completed.insert(cell);
+        if let Err(err) = progress.save(GRID_PROGRESS_FILE) {
+            log!("Failed to save grid progress: {}", err);
+        }
+    }
+}
 
-static BASE_ARGUMENTS: [&str; 5] = [
-    "--duration=180",
+static BASE_ARGUMENTS: [&str; 4] = [
     "--pNodes=8",
     "--packetInterval=0.3",
     "--calculateInterval=0.01",
     "--spawnRadius=8.5",
 ];
-const TARGET_DISTANCE: f64 = 7.5;
-
 const MAX_SIMULATIONS: usize = 1000;
 
-static LOWEST_ERROR: atomic_float::AtomicF64 = atomic_float::AtomicF64::new(10000.0);
+/// The error when there are no results to export, which usually means every simulation failed
+const NO_RESULTS: &str = "0 successful evaluations - check the simulation binary and logs";
+
+/// The error a run starts out needing to beat before any positions file is saved as the best
+const LOWEST_ERROR_START: f64 = 10000.0;
+static LOWEST_ERROR: atomic_float::AtomicF64 = atomic_float::AtomicF64::new(LOWEST_ERROR_START);
+/// The sample of each positions file saved as the best in this session, so files named after a
+/// normalized error can be renamed once the final errors are known
+static BEST_FILES: OnceCell<Mutex<Vec<(usize, PathBuf)>>> = OnceCell::new();
+
+/// Where the simulation is run from and what it needs to run
+#[derive(serde::Serialize)]
+pub struct Ns3Paths {
+    working_dir: PathBuf,
+    binary: PathBuf,
+    lib_dir: PathBuf,
+}
+
+impl Ns3Paths {
+    /// The paths inside an NS3 repo that was built by waf
+    pub fn from_repo(repo_path: &str) -> Self {
+        let working_dir = crate::util::resolve_path(repo_path);
+        Self {
+            binary: working_dir.join("build/scratch/non-ideal/non-ideal"),
+            lib_dir: working_dir.join("build/lib"),
+            working_dir,
+        }
+    }
+
+    pub fn binary(&self) -> &Path {
+        &self.binary
+    }
+
+    pub fn working_dir(&self) -> &Path {
+        &self.working_dir
+    }
+
+    pub fn from_install(install: &InstallConfig) -> Self {
+        Self {
+            working_dir: crate::util::resolve_path(&install.working_dir),
+            binary: crate::util::resolve_path(&install.binary),
+            lib_dir: crate::util::resolve_path(&install.lib_dir),
+        }
+    }
+
+    /// Positions files end up in the local directory, while the binary and libraries are the
+    /// remote host's. Fails if the host can't run the simulation
+    pub fn from_remote(remote: &RemoteConfig) -> Result<Self, crate::Error> {
+        #[cfg(feature = "remote")]
+        crate::remote::check(remote)?;
+        Ok(Self {
+            working_dir: crate::util::resolve_path(&remote.local_dir),
+            binary: remote.binary.clone(),
+            lib_dir: remote.lib_dir.clone(),
+        })
+    }
+}
+
+/// Tells the runners to stop after their current simulation. Only the first reason is kept
+fn stop(reason: impl Into<String>) {
+    let _ = STOP_REASON.set(reason.into());
+    RUNNING.store(false, Ordering::Relaxed);
+}
+
+/// The directory a run's output goes to when `Config::out_dir` isn't set
+fn default_out_dir() -> PathBuf {
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap();
+    PathBuf::from(format!("run-{}", now.as_secs()))
+}
+
+/// The path of the file `name` in the running optimizer's output directory
+fn out_file(name: &str) -> String {
+    let out_dir = CONFIG.get().unwrap().out_dir.as_ref().unwrap();
+    out_dir.join(name).to_string_lossy().into_owned()
+}
+
+/// What a finished optimization found and where its output went
+pub struct OptimizationReport {
+    pub evaluations: usize,
+    pub best_error: f64,
+    pub best_parameters: IndexMap<String, f64>,
+    pub stop_reason: String,
+    pub out_dir: PathBuf,
+    /// The plots that couldn't be drawn. The results csv and backup are written regardless
+    pub failed_exports: Vec<String>,
+}
+
+impl std::fmt::Display for OptimizationReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Best error {} for {:?} after {} evaluations ({}), written to {}",
+            self.best_error,
+            self.best_parameters,
+            self.evaluations,
+            self.stop_reason,
+            self.out_dir.display()
+        )?;
+        if !self.failed_exports.is_empty() {
+            write!(f, ". Failed to export {}", self.failed_exports.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+pub fn run(
+    paths: Ns3Paths,
+    mut config: Config,
+    resume: bool,
+    warm_start_csv: Option<&str>,
+    baseline_params: Option<&str>,
+) -> Result<OptimizationReport, crate::Error> {
+    let out_dir = config.out_dir.get_or_insert_with(default_out_dir).clone();
+    std::fs::create_dir_all(&out_dir)?;
+    log!("Writing the output of this run to {}", out_dir.display());
+
+    match config.search {
+        SearchStrategy::Grid { steps } => {
+            let _ = GRID.set(GridSweep::new(steps, &config, resume)?);
+        }
+        SearchStrategy::Tpe | SearchStrategy::GaussianProcess(_) if resume => {
+            log!("--resume only applies to grid sweeps, ignoring it");
+        }
+        SearchStrategy::Tpe | SearchStrategy::GaussianProcess(_) => {}
+    }
+    #[cfg(feature = "gaussian-process")]
+    if let SearchStrategy::GaussianProcess(gp) = config.search {
+        let ranges = config
+            .parameters
+            .iter()
+            .map(|param| param.search_range())
+            .collect();
+        let _ = GAUSSIAN_PROCESS.set(Mutex::new(crate::gaussian_process::GaussianProcess::new(
+            gp, ranges,
+        )));
+    }
+    if config.results_file == ResultsFile::Append {
+        let names = config.parameters.iter().map(|param| param.name.as_str());
+        let path = out_dir.join("results.csv");
+        check_appendable(&path.to_string_lossy(), &results_header(names))?;
+    }
 
-pub fn run(path: &str) {
     ctrlc::set_handler(|| {
         static FORCE_EXIT: AtomicUsize = AtomicUsize::new(0);
         let count = FORCE_EXIT.fetch_add(1, Ordering::Relaxed);
         if count == 5 {
-            println!("Failed to stop after 5 tries, force quitting");
+            log!("Failed to stop after 5 tries, force quitting");
             std::process::exit(1);
         }
-        RUNNING.store(false, Ordering::Relaxed);
-        println!(" Shutting down runners");
+        stop("interrupted");
+        if count == 0 {
+            log!(" Shutting down runners, waiting for running simulations to finish");
+        }
     })
-    .expect("failed to to set Control-C handler");
+    .map_err(|err| crate::Error::Other(Box::new(err)))?;
 
     let _ = STATE.set(Arc::new(Mutex::new(StateImpl {
-        params: vec![
-            Parameter {
-                name: "a".to_owned(),
-                optim: tpe::TpeOptimizer::new(
-                    tpe::parzen_estimator(),
-                    tpe::range(PARAM_MIN, PARAM_MAX).unwrap(),
-                ),
-            },
-            Parameter {
-                name: "r".to_owned(),
-                optim: tpe::TpeOptimizer::new(
-                    tpe::parzen_estimator(),
-                    tpe::range(PARAM_MIN, PARAM_MAX).unwrap(),
-                ),
-            },
-        ],
+        params: seeded_parameters(&config),
         results: Vec::new(),
+        term_ranges: TermRanges::default(),
+        error_quantiles: ErrorQuantiles::default(),
     })));
-    let default_error = LOWEST_ERROR.load(Ordering::Relaxed);
-    for param in STATE.get().unwrap().lock().unwrap().params.iter_mut() {
-        // Fill in default values so parameters start around 1 by default
-        param.optim.tell(1.0, default_error).unwrap();
+    match (warm_start_csv, config.search) {
+        (Some(_), SearchStrategy::Tpe)
+            if config.fitness.normalization == Normalization::PopulationRange =>
+        {
+            log!("--warm-start errors aren't on the scale of PopulationRange normalized errors, ignoring it");
+        }
+        (Some(csv_path), SearchStrategy::Tpe) => {
+            let mut state = STATE.get().unwrap().lock().unwrap();
+            warm_start(&mut state, &config, csv_path)?;
+        }
+        (Some(_), SearchStrategy::Grid { .. } | SearchStrategy::GaussianProcess(_)) => {
+            log!("--warm-start only applies to the TPE search, ignoring it");
+        }
+        (None, _) => {}
+    }
+    if GRID.get().is_some() {
+        let previous = load_grid_results(GRID_RESULTS_FILE)?;
+        if !previous.is_empty() {
+            log!("Loaded {} runs from earlier sessions", previous.len());
+            let mut state = STATE.get().unwrap().lock().unwrap();
+            for run in previous {
+                state.term_ranges.update(&run.breakdown);
+                state.error_quantiles.add(run.error);
+                state.results.push(run);
+            }
+            rescore(&mut state, &config);
+            // Patience only counts the runs of this session
+            LAST_IMPROVEMENT.store(state.results.len(), Ordering::Relaxed);
+        }
+    }
+    NEXT_SAMPLE.store(
+        next_sample(&STATE.get().unwrap().lock().unwrap().results),
+        Ordering::Relaxed,
+    );
+    match (&config.seed_points, config.search) {
+        (Some(_), SearchStrategy::Grid { .. }) => {
+            log!("seed_points don't apply to grid sweeps, ignoring them");
+        }
+        (Some(path), _) => {
+            let points = load_seed_points(&crate::util::resolve_path(path), &config)?;
+            log!("Evaluating {} seed points before searching", points.len());
+            PLANNED_SAMPLES.fetch_add(points.len(), Ordering::Relaxed);
+            let _ = SEED_POINTS.set(Mutex::new(points));
+        }
+        (None, _) => {}
+    }
+    match config.search {
+        _ if config.warmup_samples == 0 => {}
+        SearchStrategy::Grid { .. } => {
+            log!("warmup_samples don't apply to grid sweeps, ignoring them");
+        }
+        _ => {
+            let points = warmup_points(&config, &mut rand::thread_rng());
+            log!(
+                "Evaluating {} {:?} warmup samples before searching",
+                points.len(),
+                config.warmup_sampler
+            );
+            PLANNED_SAMPLES.fetch_add(points.len(), Ordering::Relaxed);
+            let _ = WARMUP_POINTS.set(Mutex::new(points));
+        }
+    }
+    let grid = matches!(config.search, SearchStrategy::Grid { .. });
+    if !grid && config.parameters.iter().any(|p| p.default.is_some()) {
+        PLANNED_SAMPLES.fetch_add(1, Ordering::Relaxed);
+    }
+
+    match crate::manifest::write(out_dir.join("manifest.json"), &paths, &config) {
+        Ok(()) => log!("Wrote manifest.json"),
+        Err(err) => log!("Failed to write manifest.json: {}", err),
     }
 
+    let baseline_error = match baseline_params {
+        Some(assignments) => {
+            let param_map = parse_parameter_assignments(assignments, &config)?;
+            evaluate_baseline(&paths, &config, &param_map)
+        }
+        None => None,
+    };
+
     let mut threads = Vec::new();
-    let _ = PATH.set(path.to_owned());
-    for _ in 0..num_cpus::get() {
-        //for _ in 0..1 {
-        threads.push(std::thread::spawn(run_thread));
+    let _ = PATHS.set(paths);
+    let stagger = Duration::from_millis(config.runner_stagger_ms);
+    let _ = CONFIG.set(config);
+    let runners = num_cpus::get();
+    let _ = RUN_START.set(Instant::now());
+    let _ = SESSION.set(humantime::format_rfc3339_nanos(SystemTime::now()).to_string());
+    let _ = LAST_SUCCESS.set((0..runners).map(|_| AtomicU64::new(0)).collect());
+    for runner in 0..runners {
+        if runner > 0 && !stagger.is_zero() {
+            std::thread::sleep(stagger);
+        }
+        if !RUNNING.load(Ordering::Relaxed) {
+            break;
+        }
+        threads.push(std::thread::spawn(move || run_thread(runner)));
     }
-    println!("Runners started");
+    log!("Runners started");
     for thread in threads {
         let _ = thread.join();
     }
 
-    println!("All runners stopped");
-    let state = STATE.get().unwrap().lock().unwrap();
-    println!("Exporting results from {} simulations", state.results.len());
+    // A runner that panicked while holding the lock doesn't make the finished results any less
+    // worth saving
+    let mut state = STATE
+        .get()
+        .unwrap()
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    rescore(&mut state, CONFIG.get().unwrap());
+    let stop_reason = STOP_REASON
+        .get()
+        .map_or("unknown reason", String::as_str)
+        .to_owned();
+    log!(
+        "All {} runners stopped after {} evaluations: {}",
+        runners,
+        state.results.len(),
+        stop_reason
+    );
+    if let Some(sweep) = GRID.get() {
+        let completed = sweep.progress.lock().unwrap().completed.len();
+        log!(
+            "{} of {} grid cells evaluated",
+            completed,
+            sweep.grid.cells()
+        );
+    }
+    if CONFIG.get().unwrap().profile {
+        let wall_time = RUN_START.get().unwrap().elapsed();
+        log!("{}", EvaluationTimes::total().report(wall_time));
+    }
+    if state.results.is_empty() {
+        return Err(crate::Error::Simulation(NO_RESULTS.to_owned()));
+    }
+    let summary_rows = CONFIG.get().unwrap().summary_rows;
+    if summary_rows > 0 {
+        log!(
+            "Best {} parameter sets:",
+            summary_rows.min(state.results.len())
+        );
+        for line in summary_table(&state, summary_rows).lines() {
+            log!("{}", line);
+        }
+    }
+    let best_run = best_run(&state.results).unwrap();
+    let best = best_run.error;
+    if let Some(baseline) = baseline_error {
+        log!("{}", baseline_comparison(best, baseline));
+    }
+    let precision = CONFIG.get().unwrap().positions_precision;
+    if CONFIG.get().unwrap().fitness.normalization == Normalization::PopulationRange {
+        rename_best_files(&state.results, precision);
+    }
+    let lowest_error = LOWEST_ERROR.load(Ordering::Relaxed);
+    // Only this session's runs can have saved a best file here
+    let session_best = session_lowest(&state.results);
+    if let Some(mismatch) = best_file_mismatch(
+        Path::new(&out_file("best")),
+        lowest_error,
+        session_best,
+        precision,
+    ) {
+        log!("Best file check failed: {}", mismatch);
+    }
+    log!("Sampled parameter ranges:");
+    for line in range_usage(&state.results, CONFIG.get().unwrap()) {
+        log!("  {}", line);
+    }
+    log!("Exporting results from {} simulations", state.results.len());
+
+    // The backup and the csv are written before any plots, and both are attempted even if the
+    // other fails, so a broken plot or a full disk loses as little as possible
+    let timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let backup = serde_json::to_string(state.deref())
+        .map_err(crate::Error::from)
+        .and_then(|json| {
+            std::fs::write(out_file(&format!("output-{}.json", timestamp)), json)?;
+            Ok(())
+        });
+    match &backup {
+        Ok(()) => log!("Wrote data backup file"),
+        Err(err) => log!("Failed to write the data backup file: {}", err),
+    }
+    let (stem, append) = match CONFIG.get().unwrap().results_file {
+        ResultsFile::Timestamped => (format!("results-{}", timestamp), false),
+        ResultsFile::Append => ("results".to_owned(), true),
+        ResultsFile::Overwrite => ("results".to_owned(), false),
+    };
+    let stem = out_file(&stem);
+    let results = write_results(&state, &stem, append);
+    match &results {
+        Ok(()) => log!("Wrote {}.csv", stem),
+        Err(err) => log!("Failed to write {}.csv: {}", stem, err),
+    }
 
-    let json = serde_json::to_string(state.deref()).unwrap();
-    let now = SystemTime::now();
-    let delta = now
-        .duration_since(std::time::SystemTime::UNIX_EPOCH)
-        .unwrap();
-    std::fs::write(format!("output-{}.json", delta.as_secs()), json)
-        .expect("Failed to write stats to file");
-    println!("Wrote data backup file");
+    let plot = &CONFIG.get().unwrap().plot;
+    let mut exports: Vec<(&str, Result<(), crate::Error>)> = vec![
+        (
+            "hot_cold.png",
+            write_hot_cold(&state, &out_file("hot_cold.png"), plot),
+        ),
+        (
+            "error_time.png",
+            write_error_time(&state, &out_file("error_time.png")),
+        ),
+        (
+            "the fitness grid",
+            write_fitness_grid(&state, CONFIG.get().unwrap()),
+        ),
+    ];
+    if plot.per_term {
+        exports.push((
+            "the per term plots",
+            write_per_term_hot_cold(&state, &out_file(""), plot),
+        ));
+    }
+    if plot.trajectory {
+        exports.push((
+            "trajectory.png",
+            write_trajectory(&state, &out_file("trajectory.png"), plot),
+        ));
+    }
+    let mut failed_exports = Vec::new();
+    for (name, result) in exports {
+        if let Err(err) = result {
+            log!("Failed to export {}: {}", name, err);
+            failed_exports.push(name.to_owned());
+        }
+    }
+
+    backup?;
+    results?;
+    Ok(OptimizationReport {
+        evaluations: state.results.len(),
+        best_error: best,
+        best_parameters: best_run.parameters.clone(),
+        stop_reason,
+        out_dir,
+        failed_exports,
+    })
+}
+
+/// Reads the runs saved in `GRID_RESULTS_FILE` at `path`. A missing file has no runs, and a line
+/// cut short by a crash is skipped
+fn load_grid_results(path: impl AsRef<Path>) -> Result<Vec<SimulationRun>, crate::Error> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+    let mut runs = Vec::new();
+    for line in text.lines().filter(|line| !line.trim().is_empty()) {
+        match serde_json::from_str(line) {
+            Ok(run) => runs.push(run),
+            Err(err) => log!("Skipping an unreadable grid result: {}", err),
+        }
+    }
+    Ok(runs)
+}
+
+/// The sample index after the last one in `results`
+fn next_sample(results: &[SimulationRun]) -> usize {
+    results.iter().map(|run| run.sample + 1).max().unwrap_or(0)
+}
 
-    write_hot_cold(&state, "hot_cold.png").unwrap();
-    write_error_time(&state, "error_time.png").unwrap();
+/// Combines the runs of several backups, such as from a sweep split between machines, into the
+/// first one. The backups must be for the same parameters. The runs are sorted by the time they
+/// finished, and each backup's sample indices continue after the ones before it
+fn merge_backups(backups: Vec<(String, StateImpl)>) -> Result<StateImpl, crate::Error> {
+    let names = |state: &StateImpl| -> Vec<String> {
+        state
+            .params
+            .iter()
+            .map(|param| param.name.clone())
+            .collect()
+    };
+    let mut backups = backups.into_iter();
+    let (first_path, mut merged) = backups.next().ok_or("No backups to merge")?;
+    for (path, state) in backups {
+        if names(&state) != names(&merged) {
+            return Err(format!(
+                "{} has the parameters {:?} but {} has {:?}",
+                path,
+                names(&state),
+                first_path,
+                names(&merged)
+            )
+            .into());
+        }
+        let offset = next_sample(&merged.results);
+        merged
+            .results
+            .extend(state.results.into_iter().map(|run| SimulationRun {
+                sample: run.sample + offset,
+                ..run
+            }));
+    }
+    merged.results.sort_by_key(|run| run.time);
+    Ok(merged)
 }
 
-pub fn re_export(json_path: impl AsRef<Path>, prefix: Option<&str>) -> Result<(), crate::Error> {
-    let json = std::fs::read_to_string(json_path)?;
-    let state: StateImpl = serde_json::from_str(&json)?;
+/// Redraws the graphs and rewrites the results csv from one or more backup json files. Several
+/// backups are merged into one set of runs first
+pub fn re_export(
+    json_paths: &[impl AsRef<Path>],
+    prefix: Option<&str>,
+    config: &Config,
+) -> Result<(), crate::Error> {
+    let plot = &config.plot;
+    let mut backups = Vec::new();
+    for path in json_paths {
+        let path = path.as_ref();
+        let json = std::fs::read_to_string(path)?;
+        let state: StateImpl = serde_json::from_str(&json)
+            .map_err(|err| format!("Failed to read {}: {}", path.display(), err))?;
+        backups.push((path.display().to_string(), state));
+    }
+    if backups.len() > 1 {
+        log!("Merging the runs of {} backups", backups.len());
+    }
+    let mut state = merge_backups(backups)?;
+    rescore(&mut state, config);
+    if state.results.is_empty() {
+        return Err(crate::Error::Simulation(NO_RESULTS.to_owned()));
+    }
     if state.results.len() < 1000 {
-        println!(
+        log!(
             "WARN: only {} runs counted. Dataset might be too small",
             state.results.len()
         );
     }
 
     let hot_cold_path = format!("{}hot_cold.png", prefix.unwrap_or(""));
-    write_hot_cold(&state, &hot_cold_path)?;
+    write_hot_cold(&state, &hot_cold_path, plot)?;
+    if plot.per_term {
+        write_per_term_hot_cold(&state, prefix.unwrap_or(""), plot)?;
+    }
 
     let error_time_path = format!("{}error_time.png", prefix.unwrap_or(""));
     write_error_time(&state, &error_time_path)?;
+    if plot.trajectory {
+        let trajectory_path = format!("{}trajectory.png", prefix.unwrap_or(""));
+        write_trajectory(&state, &trajectory_path, plot)?;
+    }
+    write_fitness_grid(&state, config)?;
+
+    write_results(&state, &format!("{}results", prefix.unwrap_or("")), false)?;
+
+    if config.summary_rows > 0 {
+        log!(
+            "Best {} parameter sets:",
+            config.summary_rows.min(state.results.len())
+        );
+        for line in summary_table(&state, config.summary_rows).lines() {
+            log!("{}", line);
+        }
+    }
+    log!("Exported {} runs successfully", state.results.len());
+    Ok(())
+}
 
-    println!("Exported {} runs successfully", state.results.len());
+/// Scores existing positions files and prints the result for each
+pub fn analyze(
+    positions_files: &[String],
+    config: &Config,
+    use_cache: bool,
+) -> Result<(), crate::Error> {
+    for path in positions_files {
+        let mut data = SimulationData::load(path, &config.parser, use_cache)?;
+        if let Err(err) = config.fitness.check_data(&data) {
+            log!("{}: {}", path, err);
+            continue;
+        }
+        let breakdown = fitness::get_error(&mut data, &config.fitness);
+        log!("{}: error {}", path, breakdown.total());
+        log!("  breakdown: {:?}", breakdown);
+    }
     Ok(())
 }
 
-pub fn re_export_all(dir_path: impl AsRef<Path>) -> Result<(), crate::Error> {
+/// Checks the structure of the positions files matching `patterns` without scoring them, logging
+/// a report for each one. Fails if any file couldn't be parsed or looks suspicious
+pub fn validate(patterns: &[String]) -> Result<(), crate::Error> {
+    let files: Vec<PathBuf> = patterns
+        .iter()
+        .flat_map(|pattern| crate::util::expand_glob(pattern))
+        .collect();
+    if files.is_empty() {
+        return Err(format!("No files match {}", patterns.join(" ")).into());
+    }
+    let mut passed = 0;
+    for path in &files {
+        let report = crate::util::open_positions(path)
+            .map_err(crate::Error::from)
+            .and_then(SimulationData::validate);
+        match report {
+            Ok(report) if report.passed() => {
+                passed += 1;
+                log!("OK    {}: {}", path.display(), report);
+            }
+            Ok(report) => log!("WARN  {}: {}", path.display(), report),
+            Err(err) => log!("FAIL  {}: {}", path.display(), err),
+        }
+    }
+    log!("{} of {} files passed", passed, files.len());
+    if passed == files.len() {
+        Ok(())
+    } else {
+        Err(format!("{} files had problems", files.len() - passed).into())
+    }
+}
+
+/// Checks that `SimulationData::parse` and `SimulationData::parse_reader` agree on a file
+pub fn verify_parser(positions_file: &str, config: &Config) -> Result<(), crate::Error> {
+    use std::io::Read;
+
+    let mut positions = String::new();
+    crate::util::open_positions(Path::new(positions_file))?.read_to_string(&mut positions)?;
+    match SimulationData::verify_parsers(&positions, &config.parser) {
+        Some(difference) => Err(difference.into()),
+        None => {
+            log!("parse and parse_reader agree on {}", positions_file);
+            Ok(())
+        }
+    }
+}
+
+pub fn re_export_all(dir_path: impl AsRef<Path>, config: &Config) -> Result<(), crate::Error> {
     let path = dir_path.as_ref();
-    println!("Checking {:?} for json files", path.to_str());
+    log!("Checking {:?} for json files", path.to_str());
     for entry in walkdir::WalkDir::new(dir_path)
         .contents_first(true)
         .into_iter()
@@ -173,26 +862,36 @@ pub fn re_export_all(dir_path: impl AsRef<Path>) -> Result<(), crate::Error> {
     {
         if entry.file_type().is_file() {
             let parent = entry.path().parent().expect("json file has no parent!");
-            if let Err(err) = re_export(entry.path(), parent.to_str()) {
-                println!(
+            if let Err(err) = re_export(&[entry.path()], parent.to_str(), config) {
+                log!(
                     "Failed to export {}: {:?}",
                     entry.path().to_str().unwrap(),
                     err
                 );
             } else {
-                println!("Exported {} successfully", entry.path().to_str().unwrap(),);
+                log!("Exported {} successfully", entry.path().to_str().unwrap(),);
             }
         }
     }
     Ok(())
 }
 
-/// Returns the axis ranges for a set of points of which points within `range_include` standard
-/// deviations of the mean are within the range
-fn get_bounds_and_regression(
-    points: &[(f64, f64, f64)], //(x, y, error)
-    range_include: f64,
-) -> (Range<f64>, Range<f64>, f64, f64) {
+/// The span of `values` widened by `margin` times itself on both sides, so every value is drawn
+/// inside the chart. Values that are all the same get a span of 2 around them
+fn plot_bounds(values: impl Iterator<Item = f64>, margin: f64) -> Range<f64> {
+    let range = values.fold(f64::INFINITY..f64::NEG_INFINITY, |range, value| {
+        range.start.min(value)..range.end.max(value)
+    });
+    if range.end > range.start {
+        crate::util::pad_range(range, margin)
+    } else {
+        (range.start - 1.0)..(range.end + 1.0)
+    }
+}
+
+/// Returns the slope and intercept of a regression through the points with the lowest error
+fn get_regression(points: &[(f64, f64, f64)], //(x, y, error)
+) -> (f64, f64) {
     //Clone points so we can work with a sorted version
     let mut points: Vec<_> = points.iter().collect();
     points.sort_by(|(_, _, error1), (_, _, error2)| error1.partial_cmp(error2).unwrap());
@@ -211,11 +910,6 @@ fn get_bounds_and_regression(
     assert!(x_coords.len() == weight.len());
     assert!(weight.len() == y_coords.len());
 
-    let x_mean = rgsl::statistics::mean(&x_coords, 1, x_coords.len());
-    let y_mean = rgsl::statistics::mean(&y_coords, 1, y_coords.len());
-    let x_stddev = rgsl::statistics::sd(&x_coords, 1, x_coords.len());
-    let y_stddev = rgsl::statistics::sd(&y_coords, 1, y_coords.len());
-
     // Only run regression on the 20% of the points with the lowest error
     // the lists are sorted so the best values are at the beginning
     const REGRESSION_INCLUDE_TOP_PERCENT: f64 = 0.1;
@@ -226,104 +920,610 @@ fn get_bounds_and_regression(
         .zip(weight.iter())
         .take(best_count)
     {
-        println!("[{}, {}] = {}", x, y, error);
+        log!("[{}, {}] = {}", x, y, error);
     }
     let (aa, b, m, bb, cc, dd, r_squared) =
         rgsl::fit::wlinear(&x_coords, 1, &weight, 1, &y_coords, 1, best_count);
-    println!("Got y={}x + {}, r^2={}", m, b, r_squared);
+    log!("Got y={}x + {}, r^2={}", m, b, r_squared);
     dbg!(aa, b, m, bb, cc, dd, r_squared);
-    let x_min = (x_mean - x_stddev * range_include).max(PARAM_MIN);
-    let x_max = (x_mean + x_stddev * range_include).min(PARAM_MAX);
-
-    let y_min = (y_mean - y_stddev * range_include).max(PARAM_MIN);
-    let y_max = (y_mean + y_stddev * range_include).min(PARAM_MAX);
-    println!(
-        "Bounds are x= {}..{}, y= {}..{}",
-        x_min, x_max, y_min, y_max
-    );
-
-    (
-        //clamp to the ranges that we simulated and apply `range_include`
-        x_min..x_max,
-        y_min..y_max,
-        m,
-        b,
-    )
+    (m, b)
 }
 
-fn write_hot_cold(state: &StateImpl, file_name: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let mut error_scores: Vec<f64> = state
-        .results
-        .iter()
-        .map(|r| r.error)
-        .filter(|v| !v.is_nan())
-        .collect();
+/// Formats the results as csv with one row per run, in a layout pandas can read directly.
+/// The parameters come first in the order they were configured, followed by the error, each
+/// field of the cost breakdown and the ISO 8601 time the run finished.
+/// A missing `recovery_stable_time` is left empty so it reads as NaN
+fn results_csv(state: &StateImpl) -> String {
+    let mut csv = results_header(state.params.iter().map(|param| param.name.as_str()));
+    csv.push('\n');
 
-    error_scores.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    for run in &state.results {
+        for param in &state.params {
+            let value = run.parameters.get(&param.name).copied().unwrap_or(f64::NAN);
+            csv.push_str(&format!("{},", value));
+        }
+        let b = &run.breakdown;
+        csv.push_str(&format!(
+            "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}\n",
+            run.error,
+            b.p_mad_cost,
+            b.central_distance_cost,
+            b.velocity_cost,
+            b.recovery_cost,
+            b.dropout_cost,
+            b.control_effort_cost,
+            b.uav_count_cost,
+            b.formation_cost,
+            b.target_distance,
+            b.stable_time,
+            b.recovery_stable_time
+                .map(|time| time.to_string())
+                .unwrap_or_default(),
+            b.initial_uavs,
+            b.surviving_uavs,
+            b.central_outlier_timesteps,
+            humantime::format_rfc3339_seconds(run.time),
+        ));
+    }
+    csv
+}
 
-    //We want to color the points on the scatteragram based on the fitness for that point.
-    //Finding the best fitness and assigning it green, and the worst fitness red, and then
-    //interpolating the rest results is most of the points being green because the fitness scores
-    //are not distributed evenly across the (best_fitness..worst_fitness) range.
-    //To combat this we will make a frequency table so that fitness scores in the top 10-20% range
-    //will be 80-90% green and the rest red. This will be repeated for all points with the step
-    //size being 1/256 * 100 % to make it very smooth
+/// An aligned table of the `count` runs with the lowest error, best first, with a column for each
+/// parameter, the error and each cost term
+fn summary_table(state: &StateImpl, count: usize) -> String {
+    let mut best: Vec<&SimulationRun> = state.results.iter().collect();
+    best.sort_by(|a, b| a.error.total_cmp(&b.error));
+    best.truncate(count);
 
-    let step_size = 256;
-    let smoother = crate::util::RangeSmoother::new(step_size, error_scores.as_slice());
-    let smoothed_values: Vec<_> = smoother.ranges().collect();
+    let mut rows: Vec<Vec<String>> = Vec::new();
+    let mut header: Vec<String> = state.params.iter().map(|p| p.name.clone()).collect();
+    header.push("fitness".to_owned());
+    header.extend(
+        CostBreakdown::default()
+            .terms()
+            .iter()
+            .map(|(name, _)| (*name).to_owned()),
+    );
+    rows.push(header);
+    for run in best {
+        let mut row: Vec<String> = state
+            .params
+            .iter()
+            .map(|param| {
+                let value = run.parameters.get(&param.name).copied().unwrap_or(f64::NAN);
+                format!("{:.3}", value)
+            })
+            .collect();
+        row.push(format!("{:.3}", run.error));
+        row.extend(
+            run.breakdown
+                .terms()
+                .iter()
+                .map(|(_, cost)| format!("{:.3}", cost)),
+        );
+        rows.push(row);
+    }
 
-    let mut params_to_draw: Vec<&String> = state.results[0].parameters.keys().take(2).collect();
-    params_to_draw.sort_by(|a, b| b.cmp(a));
-    let points: Vec<_> = state
-        .results
-        .iter()
-        .map(|result| {
-            let params_used = &result.parameters;
-            let x = params_used[params_to_draw[0]];
-            let y = params_used[params_to_draw[1]];
-            (x, y, result.error)
-        })
+    let widths: Vec<usize> = (0..rows[0].len())
+        .map(|column| rows.iter().map(|row| row[column].len()).max().unwrap())
         .collect();
+    let mut table = String::new();
+    for row in rows {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:>width$}", cell, width = width))
+            .collect();
+        table.push_str(&cells.join("  "));
+        table.push('\n');
+    }
+    table
+}
 
-    let root = BitMapBackend::new(file_name, (1024, 768)).into_drawing_area();
+/// The header row of `results_csv` for the parameters `names`, without a line break
+fn results_header<'a>(names: impl IntoIterator<Item = &'a str>) -> String {
+    let mut header: String = names.into_iter().map(|name| format!("{},", name)).collect();
+    header.push_str("fitness,p_mad_cost,central_distance_cost,velocity_cost,recovery_cost,");
+    header.push_str("dropout_cost,control_effort_cost,uav_count_cost,formation_cost,");
+    header.push_str("target_distance,");
+    header.push_str("stable_time,");
+    header.push_str("recovery_stable_time,initial_uavs,surviving_uavs,");
+    header.push_str("central_outlier_timesteps,time");
+    header
+}
 
-    root.fill(&WHITE)?;
-    let x_param = params_to_draw[0];
-    let y_param = params_to_draw[1];
+/// Checks that rows with the columns in `header` can be appended to the csv at `path`. A missing
+/// file can always be appended to
+fn check_appendable(path: &str, header: &str) -> Result<(), crate::Error> {
+    let existing = match std::fs::read_to_string(path) {
+        Ok(existing) => existing,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+    let existing_header = existing.lines().next().unwrap_or("");
+    if existing_header != header {
+        return Err(format!(
+            "Can't append to {}, it has the columns `{}` but this run writes `{}`. Use --overwrite-results or move it away",
+            path, existing_header, header
+        )
+        .into());
+    }
+    Ok(())
+}
 
-    // Disable title
-    // root.titled(
-    //     format!("{} vs. {}", x_param, y_param).as_str(),
-    //     ("sans-serif", 40),
-    // )?;
-    //
+/// Information about a results csv that doesn't fit in its rows
+#[derive(serde::Serialize, serde::Deserialize)]
+struct ResultsMetadata {
+    parameters: Vec<String>,
+    runs: usize,
+    first_run: Option<String>,
+    last_run: Option<String>,
+    exported: String,
+}
 
-    const INCLUDE_POINTS_STDDEVS: f64 = 1.0;
-    let areas = root.split_by_breakpoints([944], [80]);
-    let (x_bounds, y_bounds, linear_m, linear_b) =
-        get_bounds_and_regression(&points, INCLUDE_POINTS_STDDEVS);
+/// Writes `{stem}.csv` and a `{stem}.meta.json` sidecar describing the runs in it. With `append`
+/// the rows are added to an existing csv with the same columns, and the sidecar covers both
+fn write_results(state: &StateImpl, stem: &str, append: bool) -> Result<(), crate::Error> {
+    use std::io::Write;
 
-    let regression_func = |x: f64| -> f64 {
-        let y = linear_m * x + linear_b;
-        println!("f({}) = {}", x, y);
-        y
+    let csv_path = format!("{}.csv", stem);
+    let metadata_path = format!("{}.meta.json", stem);
+    let csv = results_csv(state);
+    let appending = append && Path::new(&csv_path).exists();
+    if appending {
+        let (header, rows) = csv.split_once('\n').unwrap();
+        check_appendable(&csv_path, header)?;
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&csv_path)?
+            .write_all(rows.as_bytes())?;
+    } else {
+        std::fs::write(&csv_path, csv)?;
+    }
+
+    let iso = |time: SystemTime| humantime::format_rfc3339_seconds(time).to_string();
+    let mut metadata = ResultsMetadata {
+        parameters: state
+            .params
+            .iter()
+            .map(|param| param.name.clone())
+            .collect(),
+        runs: state.results.len(),
+        first_run: state.results.iter().map(|run| run.time).min().map(iso),
+        last_run: state.results.iter().map(|run| run.time).max().map(iso),
+        exported: iso(SystemTime::now()),
     };
+    let previous = std::fs::read_to_string(&metadata_path)
+        .ok()
+        .and_then(|json| serde_json::from_str::<ResultsMetadata>(&json).ok());
+    if let (true, Some(previous)) = (appending, previous) {
+        metadata.runs += previous.runs;
+        metadata.first_run = previous.first_run.or(metadata.first_run);
+        metadata.last_run = metadata.last_run.or(previous.last_run);
+    }
+    std::fs::write(metadata_path, serde_json::to_string_pretty(&metadata)?)?;
+    Ok(())
+}
 
-    let mut scatter_ctx = ChartBuilder::on(&areas[2])
-        .x_label_area_size(60)
-        .y_label_area_size(80)
-        .build_cartesian_2d(x_bounds, y_bounds)?;
+/// The cost terms drawn by `write_per_term_hot_cold`, and the suffix of each one's file name
+const PER_TERM_PLOTS: [(&str, &str); 3] = [
+    ("central_distance_cost", "distance"),
+    ("velocity_cost", "velocity"),
+    ("p_mad_cost", "stable"),
+];
 
-    scatter_ctx
-        .configure_mesh()
-        .disable_x_mesh()
-        .disable_y_mesh()
-        .x_desc(x_param)
-        .y_desc(y_param)
-        .label_style(("sans-serif", 30))
-        .axis_desc_style(("sans-serif", 30))
-        .draw()?;
+/// Writes `write_hot_cold` graphs colored by each cost term in `PER_TERM_PLOTS` instead of the
+/// total error, named `{prefix}hot_cold_{suffix}.png`
+fn write_per_term_hot_cold(
+    state: &StateImpl,
+    prefix: &str,
+    plot: &PlotConfig,
+) -> Result<(), crate::Error> {
+    for (term, suffix) in PER_TERM_PLOTS {
+        let file_name = format!("{}hot_cold_{}.png", prefix, suffix);
+        write_scatter(state, &file_name, plot, term, None, |run| {
+            run.breakdown
+                .terms()
+                .into_iter()
+                .find(|(name, _)| *name == term)
+                .map(|(_, cost)| cost)
+                .unwrap_or(f64::NAN)
+        })?;
+    }
+    Ok(())
+}
+
+fn write_hot_cold(
+    state: &StateImpl,
+    file_name: &str,
+    plot: &PlotConfig,
+) -> Result<(), crate::Error> {
+    write_scatter(
+        state,
+        file_name,
+        plot,
+        "fitness",
+        plot.fitness_color_range,
+        |run| run.error,
+    )
+}
+
+/// The parameters on the x and y axes of the `write_scatter` graphs
+fn plotted_params(state: &StateImpl) -> Vec<&String> {
+    let mut params: Vec<&String> = state.results[0].parameters.keys().take(2).collect();
+    params.sort_by(|a, b| b.cmp(a));
+    params
+}
+
+/// Bins the runs into a `cells` by `cells` grid over the parameters of the hot/cold graph. Each
+/// row has the center of a cell, how many runs fell in it and their mean and lowest error, which
+/// are left empty for cells without runs. The grid spans the runs' parameter values unless
+/// `configured_ranges` is given, in which case it spans each parameter's configured range so
+/// grids from different sessions line up. The cells of `previous`, a grid written earlier over
+/// the same ranges, are added to the runs'
+fn fitness_grid_csv(
+    state: &StateImpl,
+    cells: usize,
+    configured_ranges: Option<&Config>,
+    previous: Option<&str>,
+) -> Result<String, crate::Error> {
+    if state.results.is_empty() || cells == 0 {
+        return Err("There are no runs to grid".into());
+    }
+    let params = plotted_params(state);
+    if params.len() < 2 {
+        return Err("Gridding the runs needs at least two parameters".into());
+    }
+    let points: Vec<(f64, f64, f64)> = state
+        .results
+        .iter()
+        .map(|run| {
+            (
+                run.parameters[params[0]],
+                run.parameters[params[1]],
+                run.error,
+            )
+        })
+        .filter(|(_, _, error)| !error.is_nan())
+        .collect();
+    let bounds = |values: &mut dyn Iterator<Item = f64>| {
+        values.fold(f64::INFINITY..f64::NEG_INFINITY, |range, value| {
+            range.start.min(value)..range.end.max(value)
+        })
+    };
+    let (x_range, y_range) = match configured_ranges {
+        Some(config) => (
+            configured_range(config, params[0]),
+            configured_range(config, params[1]),
+        ),
+        None => (
+            bounds(&mut points.iter().map(|point| point.0)),
+            bounds(&mut points.iter().map(|point| point.1)),
+        ),
+    };
+    let cell_of = |value: f64, range: &Range<f64>| {
+        let span = range.end - range.start;
+        if span > 0.0 {
+            (((value - range.start) / span * cells as f64) as usize).min(cells - 1)
+        } else {
+            0
+        }
+    };
+    let center = |cell: usize, range: &Range<f64>| {
+        range.start + (cell as f64 + 0.5) * (range.end - range.start) / cells as f64
+    };
+
+    // (runs, error sum, lowest error) of each cell, row by row
+    let mut grid = vec![(0usize, 0.0, f64::INFINITY); cells * cells];
+    for (x, y, error) in &points {
+        let cell = &mut grid[cell_of(*y, &y_range) * cells + cell_of(*x, &x_range)];
+        cell.0 += 1;
+        cell.1 += error;
+        cell.2 = cell.2.min(*error);
+    }
+
+    let header = format!(
+        "{},{},sample_count,mean_fitness,min_fitness",
+        params[0], params[1]
+    );
+    let centers: Vec<String> = (0..grid.len())
+        .map(|index| {
+            format!(
+                "{},{}",
+                center(index % cells, &x_range),
+                center(index / cells, &y_range)
+            )
+        })
+        .collect();
+    if let Some(previous) = previous {
+        add_previous_grid(&mut grid, previous, &header, &centers)?;
+    }
+
+    let mut csv = format!("{}\n", header);
+    for ((count, sum, lowest), center) in grid.iter().zip(&centers) {
+        let (x, y) = center.split_once(',').unwrap();
+        if *count == 0 {
+            csv.push_str(&format!("{},{},0,,\n", x, y));
+        } else {
+            let mean = sum / *count as f64;
+            csv.push_str(&format!("{},{},{},{},{}\n", x, y, count, mean, lowest));
+        }
+    }
+    Ok(csv)
+}
+
+/// Adds the (runs, error sum, lowest error) of each cell of the grid csv `previous` to `grid`.
+/// The csv must have the same `header` and cell `centers`
+fn add_previous_grid(
+    grid: &mut [(usize, f64, f64)],
+    previous: &str,
+    header: &str,
+    centers: &[String],
+) -> Result<(), crate::Error> {
+    let mut lines = previous.lines().filter(|line| !line.trim().is_empty());
+    if lines.next() != Some(header) {
+        return Err("The earlier fitness grid is over different parameters".into());
+    }
+    let rows: Vec<&str> = lines.collect();
+    if rows.len() != grid.len() {
+        return Err(format!(
+            "The earlier fitness grid has {} cells instead of {}",
+            rows.len(),
+            grid.len()
+        )
+        .into());
+    }
+    for ((cell, row), center) in grid.iter_mut().zip(rows).zip(centers) {
+        let fields: Vec<&str> = row.split(',').collect();
+        if fields.len() != 5 || format!("{},{}", fields[0], fields[1]) != *center {
+            return Err(format!(
+                "The earlier fitness grid's cell {} doesn't line up with {}, was it written with other parameter ranges?",
+                row, center
+            )
+            .into());
+        }
+        let count: usize = fields[2].parse()?;
+        if count > 0 {
+            let mean: f64 = fields[3].parse()?;
+            let lowest: f64 = fields[4].parse()?;
+            cell.0 += count;
+            cell.1 += mean * count as f64;
+            cell.2 = cell.2.min(lowest);
+        }
+    }
+    Ok(())
+}
+
+/// Writes `fitness_grid_csv` to the `export_grid` file if one is configured. With
+/// `accumulate_grid`, the cells already in the file are added to the runs of the sessions that
+/// aren't in it yet, which are then listed in the `.sessions` file next to it
+fn write_fitness_grid(state: &StateImpl, config: &Config) -> Result<(), crate::Error> {
+    use std::io::Write;
+
+    let plot = &config.plot;
+    let path = match &plot.export_grid {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+    if !plot.accumulate_grid {
+        std::fs::write(path, fitness_grid_csv(state, plot.grid_cells, None, None)?)?;
+        log!("Wrote the fitness grid to {}", path.display());
+        return Ok(());
+    }
+
+    let read = |path: &Path| match std::fs::read_to_string(path) {
+        Ok(text) => Ok(Some(text)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    };
+    let previous = read(path)?;
+    let mut sessions_path = path.clone().into_os_string();
+    sessions_path.push(".sessions");
+    let sessions_path = PathBuf::from(sessions_path);
+    let added = read(&sessions_path)?.unwrap_or_default();
+    let added: Vec<&str> = added.lines().filter(|line| !line.is_empty()).collect();
+    // Runs from before sessions were recorded can't be told apart, so they are always added
+    let new = StateImpl {
+        params: Vec::new(),
+        results: state
+            .results
+            .iter()
+            .filter(|run| !added.contains(&run.session.as_str()))
+            .cloned()
+            .collect(),
+        term_ranges: TermRanges::default(),
+        error_quantiles: ErrorQuantiles::default(),
+    };
+    if new.results.is_empty() {
+        log!(
+            "Every run is already in the fitness grid in {}",
+            path.display()
+        );
+        return Ok(());
+    }
+    if previous.is_some() {
+        log!(
+            "Adding {} runs to the fitness grid in {}",
+            new.results.len(),
+            path.display()
+        );
+    }
+    let csv = fitness_grid_csv(&new, plot.grid_cells, Some(config), previous.as_deref())?;
+    let mut sessions: Vec<&str> = new
+        .results
+        .iter()
+        .map(|run| run.session.as_str())
+        .filter(|session| !session.is_empty())
+        .collect();
+    sessions.sort_unstable();
+    sessions.dedup();
+    std::fs::write(path, csv)?;
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&sessions_path)?;
+    for session in sessions {
+        writeln!(file, "{}", session)?;
+    }
+    log!("Wrote the fitness grid to {}", path.display());
+    Ok(())
+}
+
+/// The color `write_scatter` uses for a point in the `index`th of its 256 quantile steps, from
+/// green for the lowest values to red for the highest. Values past the last step use its color
+fn scatter_color(index: usize) -> RGBColor {
+    // Step 0 would wrap the green channel around to 0
+    let index = index.clamp(1, 255);
+    RGBColor(index as u8, (256 - index) as u8, 50)
+}
+
+/// The `scatter_color` step of `value` on a linear scale over the (lowest, highest) `range`,
+/// clamped to its ends
+fn fixed_color_index(value: f64, (low, high): (f64, f64)) -> usize {
+    ((value - low) / (high - low) * 255.0).clamp(0.0, 255.0) as usize
+}
+
+/// Formats a color bar tick so both tiny cost terms and large totals stay readable
+fn tick_label(value: f64) -> String {
+    if value == 0.0 || (0.01..10000.0).contains(&value.abs()) {
+        format!("{:.2}", value)
+    } else {
+        format!("{:.2e}", value)
+    }
+}
+
+/// Draws a vertical bar of the `write_scatter` colors between `top` and `bottom` pixels, labeled
+/// with the lowest, median and highest of the sorted `values` at the heights of their colors
+fn draw_color_bar<DB: DrawingBackend>(
+    area: &DrawingArea<DB, plotters::coord::Shift>,
+    values: &[f64],
+    (top, bottom): (i32, i32),
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    DB::ErrorType: 'static,
+{
+    const BAR_LEFT: i32 = 10;
+    const BAR_RIGHT: i32 = 35;
+    let steps = 256;
+    let height = |fraction: f64| bottom - ((bottom - top) as f64 * fraction) as i32;
+    for step in 1..steps {
+        area.draw(&Rectangle::new(
+            [
+                (BAR_LEFT, height(step as f64 / steps as f64)),
+                (BAR_RIGHT, height((step - 1) as f64 / steps as f64)),
+            ],
+            scatter_color(step).filled(),
+        ))?;
+    }
+
+    let font = ("sans-serif", 20).into_font();
+    if values.is_empty() {
+        return Ok(());
+    }
+    let ticks = [
+        (0.0, values[0]),
+        (0.5, values[values.len() / 2]),
+        (1.0, values[values.len() - 1]),
+    ];
+    for (fraction, value) in ticks {
+        let y = height(fraction);
+        area.draw(&PathElement::new(
+            vec![(BAR_RIGHT, y), (BAR_RIGHT + 5, y)],
+            BLACK,
+        ))?;
+        area.draw(&Text::new(
+            tick_label(value),
+            (BAR_RIGHT + 8, y - 8),
+            font.clone(),
+        ))?;
+    }
+    Ok(())
+}
+
+/// Draws each run at its first two parameters, colored from green to red by `value`, with a color
+/// bar showing which values the colors stand for. The colors follow the quantiles of the values
+/// unless a fixed `color_range` is given
+fn write_scatter(
+    state: &StateImpl,
+    file_name: &str,
+    plot: &PlotConfig,
+    value_name: &str,
+    color_range: Option<(f64, f64)>,
+    value: impl Fn(&SimulationRun) -> f64,
+) -> Result<(), crate::Error> {
+    let mut error_scores: Vec<f64> = state
+        .results
+        .iter()
+        .map(&value)
+        .filter(|v| !v.is_nan())
+        .collect();
+
+    error_scores.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    //We want to color the points on the scatteragram based on the fitness for that point.
+    //Finding the best fitness and assigning it green, and the worst fitness red, and then
+    //interpolating the rest results is most of the points being green because the fitness scores
+    //are not distributed evenly across the (best_fitness..worst_fitness) range.
+    //To combat this we will make a frequency table so that fitness scores in the top 10-20% range
+    //will be 80-90% green and the rest red. This will be repeated for all points with the step
+    //size being 1/256 * 100 % to make it very smooth
+
+    let step_size = 256;
+    let smoother = crate::util::RangeSmoother::new(step_size, error_scores.as_slice());
+    let smoothed_values: Vec<_> = smoother.ranges().collect();
+
+    let params_to_draw = plotted_params(state);
+    let points: Vec<_> = state
+        .results
+        .iter()
+        .map(|result| {
+            let params_used = &result.parameters;
+            let x = params_used[params_to_draw[0]];
+            let y = params_used[params_to_draw[1]];
+            (x, y, value(result))
+        })
+        .collect();
+
+    let root = BitMapBackend::new(file_name, (1024, 768)).into_drawing_area();
+
+    root.fill(&WHITE)?;
+    let x_param = params_to_draw[0];
+    let y_param = params_to_draw[1];
+
+    // Disable title
+    // root.titled(
+    //     format!("{} vs. {}", x_param, y_param).as_str(),
+    //     ("sans-serif", 40),
+    // )?;
+    //
+
+    const X_LABEL_AREA: i32 = 60;
+    let areas = root.split_by_breakpoints([864], [80]);
+    let (linear_m, linear_b) = get_regression(&points);
+    // Every sample is kept in view, outliers included
+    let x_bounds = plot_bounds(points.iter().map(|point| point.0), plot.axis_margin);
+    let y_bounds = plot_bounds(points.iter().map(|point| point.1), plot.axis_margin);
+
+    let _regression_func = |x: f64| -> f64 {
+        let y = linear_m * x + linear_b;
+        log!("f({}) = {}", x, y);
+        y
+    };
+
+    let mut scatter_ctx = ChartBuilder::on(&areas[2])
+        .x_label_area_size(X_LABEL_AREA)
+        .y_label_area_size(80)
+        .build_cartesian_2d(x_bounds, y_bounds)?;
+
+    scatter_ctx
+        .configure_mesh()
+        .disable_x_mesh()
+        .disable_y_mesh()
+        .x_desc(x_param)
+        .y_desc(y_param)
+        .label_style(("sans-serif", 30))
+        .axis_desc_style(("sans-serif", 30))
+        .draw()?;
 
     scatter_ctx
         .draw_series(LineSeries::new(
@@ -334,28 +1534,49 @@ fn write_hot_cold(state: &StateImpl, file_name: &str) -> Result<(), Box<dyn std:
         .label("Average error");
 
     scatter_ctx.draw_series(points.iter().map(|(x, y, error)| {
-        let mut i = 0;
-        for limit in smoothed_values.iter() {
-            i += 1;
-            if error < limit {
-                break;
+        let i = match color_range {
+            Some(range) => fixed_color_index(*error, range),
+            None => {
+                let mut i = 0;
+                for limit in smoothed_values.iter() {
+                    i += 1;
+                    if error < limit {
+                        break;
+                    }
+                }
+                i
             }
-        }
+        };
 
-        let color = plotters::style::RGBColor(i as u8, (256 - i) as u8, 50);
-        Circle::new((*x, *y), 2, color.filled())
+        Circle::new((*x, *y), 2, scatter_color(i).filled())
     }))?;
 
+    let (_, bar_height) = areas[3].dim_in_pixel();
+    let bar_values = match color_range {
+        Some((low, high)) => vec![low, (low + high) / 2.0, high],
+        None => error_scores,
+    };
+    draw_color_bar(
+        &areas[3],
+        &bar_values,
+        (10, bar_height as i32 - X_LABEL_AREA),
+    )?;
+    areas[1].draw(&Text::new(
+        value_name.to_owned(),
+        (10, 50),
+        ("sans-serif", 20).into_font(),
+    ))?;
+
     root.present().expect("Unable to write image to file");
 
     Ok(())
 }
 
-fn write_error_time(state: &StateImpl, file_name: &str) -> Result<(), Box<dyn std::error::Error>> {
+fn write_error_time(state: &StateImpl, file_name: &str) -> Result<(), crate::Error> {
     let root = BitMapBackend::new(file_name, (1024, 768)).into_drawing_area();
     root.fill(&WHITE)?;
     if state.results.is_empty() {
-        println!("No data to graph");
+        log!("No data to graph");
         return Ok(());
     }
     let worst_error = state
@@ -384,12 +1605,12 @@ fn write_error_time(state: &StateImpl, file_name: &str) -> Result<(), Box<dyn st
         .y_desc("error")
         .label_style(("sans-serif", 25))
         .axis_desc_style(("sans-serif", 25))
-        .light_line_style(&WHITE)
+        .light_line_style(WHITE)
         .draw()?;
 
     chart.draw_series(state.results.iter().map(|r| {
         let a = (seconds_since_start(&r.time) as f32, r.error as f32);
-        Circle::new(a, 2u32, &BLACK)
+        Circle::new(a, 2u32, BLACK)
     }))?;
 
     chart
@@ -411,218 +1632,3084 @@ fn write_error_time(state: &StateImpl, file_name: &str) -> Result<(), Box<dyn st
     Ok(())
 }
 
-fn run_binary(
-    rel_working_dir: &str,
-    rel_bin_path: &str,
-    args: &[String],
-) -> Result<(), Box<dyn std::error::Error>> {
-    let mut base = std::env::current_dir().unwrap();
-    base.push(rel_working_dir);
-    //We need the NS3 libs to be in LD_LIBRARY_PATH
-    let lib_path = {
-        let mut base = base.clone();
-        base.push("build");
-        base.push("lib");
-        base
-    };
-    let current_dir = base.clone();
-    base.push(rel_bin_path);
-    let bin_path = base;
-
-    if Command::new(bin_path)
-        .current_dir(current_dir)
-        .env("LD_LIBRARY_PATH", lib_path.to_str().unwrap())
-        .args(args)
-        .spawn()?
-        .wait()?
-        .success()
-    {
-        Ok(())
-    } else {
-        Err("Error running binary".into())
+/// The hot/cold graph's parameters of every run, in the order the runs were sampled
+fn trajectory(state: &StateImpl) -> Vec<(f64, f64)> {
+    let params = plotted_params(state);
+    let mut runs: Vec<&SimulationRun> = state.results.iter().collect();
+    // Stable, so backups without sample indices keep the order the runs finished in
+    runs.sort_by_key(|run| run.sample);
+    runs.iter()
+        .map(|run| (run.parameters[params[0]], run.parameters[params[1]]))
+        .collect()
+}
+
+/// Draws the samples over the hot/cold graph's parameters with each one joined to the next,
+/// fading from light to dark blue as the search goes on
+fn write_trajectory(
+    state: &StateImpl,
+    file_name: &str,
+    plot: &PlotConfig,
+) -> Result<(), crate::Error> {
+    let params = plotted_params(state);
+    let points = trajectory(state);
+    let x_range = plot_bounds(points.iter().map(|point| point.0), plot.axis_margin);
+    let y_range = plot_bounds(points.iter().map(|point| point.1), plot.axis_margin);
+
+    let root = BitMapBackend::new(file_name, (1024, 768)).into_drawing_area();
+    root.fill(&WHITE)?;
+    let mut chart = ChartBuilder::on(&root)
+        .margin(20u32)
+        .x_label_area_size(60u32)
+        .y_label_area_size(80u32)
+        .build_cartesian_2d(x_range, y_range)?;
+    chart
+        .configure_mesh()
+        .disable_x_mesh()
+        .disable_y_mesh()
+        .x_desc(params[0].as_str())
+        .y_desc(params[1].as_str())
+        .label_style(("sans-serif", 30))
+        .axis_desc_style(("sans-serif", 30))
+        .draw()?;
+
+    let color = |index: usize| {
+        let fraction = index as f64 / (points.len().max(2) - 1) as f64;
+        let shade = |light: f64, dark: f64| (light + (dark - light) * fraction) as u8;
+        RGBColor(shade(210.0, 0.0), shade(210.0, 0.0), shade(255.0, 140.0))
+    };
+    for (index, pair) in points.windows(2).enumerate() {
+        chart.draw_series(LineSeries::new(pair.iter().copied(), &color(index + 1)))?;
     }
+    chart.draw_series(
+        points
+            .iter()
+            .enumerate()
+            .map(|(index, point)| Circle::new(*point, 3u32, color(index).filled())),
+    )?;
+    Ok(())
 }
 
-fn run_thread() {
-    let mut rng = rand::thread_rng();
-    let mut param_map = IndexMap::new();
-    let mut args: Vec<String> = Vec::new();
-    for arg in BASE_ARGUMENTS.iter() {
-        args.push((*arg).to_owned());
+/// Quotes `arg` for a POSIX shell if it contains anything that the shell would interpret
+pub fn shell_quote(arg: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "-_=.,/:+@%".contains(c);
+    if !arg.is_empty() && arg.chars().all(safe) {
+        arg.to_owned()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
     }
+}
 
-    while RUNNING.load(Ordering::Relaxed) {
-        let pos_file_name: String = rand::thread_rng()
-            .sample_iter(&Alphanumeric)
-            .take(10)
-            .map(char::from)
-            .collect();
+/// The command `run_binary` runs, in a form that can be pasted into a shell
+fn shell_command(paths: &Ns3Paths, wrapper: &[String], args: &[String]) -> String {
+    let mut command = format!(
+        "cd {} && LD_LIBRARY_PATH={}",
+        shell_quote(paths.working_dir.to_str().unwrap()),
+        shell_quote(paths.lib_dir.to_str().unwrap()),
+    );
+    for arg in wrapper
+        .iter()
+        .map(String::as_str)
+        .chain([paths.binary.to_str().unwrap()])
+        .chain(args.iter().map(String::as_str))
+    {
+        command.push(' ');
+        command.push_str(&shell_quote(arg));
+    }
+    command
+}
 
-        //Keep base arguments
-        args.resize(BASE_ARGUMENTS.len(), String::new());
-
-        let ns3_path = PATH.get().unwrap();
-        let mut buf = PathBuf::from(ns3_path);
-        buf.push(pos_file_name);
-        buf.set_extension("csv");
-        let mut positions_file = std::env::current_dir().unwrap();
-        positions_file.push(buf);
-        args.push(format!(
-            "--positionsFile={}",
-            &positions_file.to_str().unwrap()
-        ));
+/// How a simulation that didn't fail ended
+#[derive(Debug, PartialEq, Eq)]
+enum RunOutcome {
+    Finished,
+    /// The simulation was killed after running longer than the timeout. Its positions file only
+    /// covers part of the simulation
+    TimedOut,
+    /// The simulation was killed by a signal while running with `simulation_memory_limit`, which
+    /// is how running out of the capped memory usually ends
+    OutOfMemory,
+}
 
-        let seed: usize = rng.gen();
-        args.push(format!("--seed={}", seed));
+/// Limits the address space of the process `command` starts to `megabytes`
+#[cfg(unix)]
+fn limit_memory(command: &mut Command, megabytes: u64) {
+    use std::os::unix::process::CommandExt;
 
-        {
-            let mut state = STATE.get().unwrap().lock().unwrap();
-            param_map.clear();
-            for param in state.params.iter_mut() {
-                let value = param.optim.ask(&mut rng).unwrap();
-                param_map.insert(param.name.clone(), value);
-                args.push(format!("--{}={}", param.name, value));
+    let bytes = megabytes.saturating_mul(1024 * 1024) as libc::rlim_t;
+    // Safety: the closure runs between fork and exec, where it only calls setrlimit, which is
+    // async-signal-safe, and doesn't allocate
+    unsafe {
+        command.pre_exec(move || {
+            let limit = libc::rlimit {
+                rlim_cur: bytes,
+                rlim_max: bytes,
+            };
+            if libc::setrlimit(libc::RLIMIT_AS, &limit) == 0 {
+                Ok(())
+            } else {
+                Err(std::io::Error::last_os_error())
             }
-        };
+        });
+    }
+}
 
-        //Run simulation
-        match run_binary(ns3_path, "build/scratch/non-ideal/non-ideal", &args) {
-            Ok(_) => match run_analysis(&positions_file, &param_map, &positions_file) {
-                Ok(_) => {}
-                Err(err) => {
-                    println!("Error while doing analysis: {}", err);
-                }
-            },
-            Err(err) => {
-                println!("Error while running waf: {}", err);
-                let _ = std::fs::remove_file(positions_file);
-            }
-        }
-    }
-    println!("Runner exiting cleanly");
-}
-
-fn get_error(data: &mut SimulationData) -> f64 {
-    let time_step = 0.1;
-    let mut time = 0.0;
-    let mut last_poses = IndexMap::new();
-    let uavs = data.uavs.clone();
-    let central_node = uavs.iter().min().unwrap();
-
-    let mut all_central_distances = Vec::new();
-    let mut all_peripheral_distances = Vec::new();
-    let mut all_velocities = Vec::new();
-    while time <= data.simulation_length {
-        let mut central_distances: Vec<f64> = Vec::new();
-        let mut peripheral_distances: Vec<f64> = Vec::new();
-        let mut velocities: Vec<f64> = Vec::new();
-
-        let central_pos = data.pos_at_time(TimePoint(time), *central_node).unwrap();
-        for uav in &uavs {
-            if let Some(now_pos) = data.pos_at_time(TimePoint(time), *uav) {
-                match last_poses.get(uav) {
-                    None => {}
-                    Some((last_pos, last_time)) => {
-                        let pos_delta = now_pos - *last_pos;
-                        let time_delta = time - last_time;
-                        let velocity: Vec3A = pos_delta / time_delta;
-                        velocities.push(velocity.length() as f64);
-                    }
-                }
-                last_poses.insert(uav, (now_pos, time));
-                if uav != central_node {
-                    central_distances.push((now_pos - central_pos).length() as f64);
-                    for uav_2 in &uavs {
-                        if uav != uav_2 && uav_2 != central_node {
-                            //Calculate the distance between this node and every other peripheral node
-                            if let Some(now_2_pos) = data.pos_at_time(TimePoint(time), *uav_2) {
-                                peripheral_distances.push((now_2_pos - now_pos).length() as f64);
-                            }
-                        }
-                    }
-                }
-            }
-        }
+#[cfg(not(unix))]
+fn limit_memory(_command: &mut Command, _megabytes: u64) {
+    static WARNED: std::sync::Once = std::sync::Once::new();
+    WARNED.call_once(|| log!("simulation_memory_limit is only supported on Unix, ignoring it"));
+}
 
-        let central_distances_mean =
-            rgsl::statistics::mean(&central_distances, 1, central_distances.len());
-        let peripheral_distances_mean =
-            rgsl::statistics::mean(&peripheral_distances, 1, peripheral_distances.len());
+/// If a simulation that ended with `status` was most likely killed for using too much memory
+#[cfg(unix)]
+fn killed_by_memory_limit(status: std::process::ExitStatus) -> bool {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal().is_some()
+}
 
-        let mean_velocity = rgsl::statistics::mean(&velocities, 1, velocities.len());
+#[cfg(not(unix))]
+fn killed_by_memory_limit(_status: std::process::ExitStatus) -> bool {
+    false
+}
 
-        all_central_distances.push(central_distances_mean);
-        all_velocities.push(mean_velocity);
-        all_peripheral_distances.push(peripheral_distances_mean);
-        //println!("T: {}, V: {}, D: {}", time, mean_velocity, mad_of_distance);
+/// Where the output of the simulation writing `positions_file` is captured, if `sim_log_dir` is
+/// set
+fn sim_log_path(config: &Config, positions_file: &Path) -> Option<PathBuf> {
+    let name = positions_file.file_name()?.to_string_lossy();
+    Some(config.sim_log_dir.as_ref()?.join(format!("{}.log", name)))
+}
 
-        time += time_step;
+/// Deletes a scored sample's positions file and simulation log, unless `keep_all_positions` is
+/// set. Kept positions files are gzipped if `compress_positions` is set
+fn remove_scored_files(config: &Config, positions_file: &Path) -> std::io::Result<()> {
+    if config.keep_all_positions {
+        if config.compress_positions {
+            crate::util::gzip_file(positions_file)?;
+        }
+        return Ok(());
     }
-    let mean_velocity: f64 = all_velocities.iter().sum::<f64>() / all_velocities.len() as f64;
-
-    let mean_central_distance: f64 =
-        rgsl::statistics::mean(&all_central_distances, 1, all_central_distances.len());
-
-    let mad_of_peripheral_distance: f64 =
-        rgsl::statistics::absdev(&all_peripheral_distances, 1, all_peripheral_distances.len());
+    if let Some(log_path) = sim_log_path(config, positions_file) {
+        let _ = std::fs::remove_file(log_path);
+    }
+    std::fs::remove_file(positions_file)
+}
 
-    println!("mean central: {mean_central_distance}, c mad: {mad_of_peripheral_distance}");
+/// Runs the simulation with `args`, writing its output to `log_path` if one is given
+fn run_binary(
+    paths: &Ns3Paths,
+    args: &[String],
+    config: &Config,
+    log_path: Option<&Path>,
+) -> Result<RunOutcome, crate::Error> {
+    if let Some(outcome) = run_remote(args, config, log_path) {
+        return outcome;
+    }
+    if config.log_commands {
+        log!(
+            "Running: {}",
+            shell_command(paths, &config.simulation_wrapper, args)
+        );
+    }
+    //We need the NS3 libs to be in LD_LIBRARY_PATH
+    let mut command = match config.simulation_wrapper.split_first() {
+        Some((program, wrapper_args)) => {
+            let mut command = Command::new(program);
+            command.args(wrapper_args).arg(&paths.binary);
+            command
+        }
+        None => Command::new(&paths.binary),
+    };
+    command
+        .current_dir(&paths.working_dir)
+        .env("LD_LIBRARY_PATH", paths.lib_dir.to_str().unwrap())
+        .args(args)
+        .stdout(crate::util::child_stdout());
+    log_output(&mut command, log_path)?;
+    if let Some(megabytes) = config.simulation_memory_limit {
+        limit_memory(&mut command, megabytes);
+    }
+    let mut child = command.spawn().map_err(|err| spawn_error(paths, err))?;
 
-    let p_mad_cost = 400.0 * mad_of_peripheral_distance;
-    let central_distance_cost = 400.0 * (TARGET_DISTANCE - mean_central_distance).abs();
-    let velocity_cost = 250.0 * mean_velocity;
+    let timeout = config.simulation_timeout.map(Duration::from_secs_f64);
+    let status = match wait_for(&mut child, timeout)? {
+        Some(status) => status,
+        None => return Ok(RunOutcome::TimedOut),
+    };
+    if status.success() {
+        Ok(RunOutcome::Finished)
+    } else if config.simulation_memory_limit.is_some() && killed_by_memory_limit(status) {
+        Ok(RunOutcome::OutOfMemory)
+    } else {
+        Err(crate::Error::Simulation(format!(
+            "The simulation exited with {}",
+            status
+        )))
+    }
+}
 
-    p_mad_cost + central_distance_cost + velocity_cost
+/// Sends the output of `command` to `log_path` if one is given
+fn log_output(command: &mut Command, log_path: Option<&Path>) -> Result<(), crate::Error> {
+    if let Some(log_path) = log_path {
+        if let Some(dir) = log_path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let log = std::fs::File::create(log_path)?;
+        command.stdout(log.try_clone()?).stderr(log);
+    }
+    Ok(())
 }
 
-fn run_analysis(
-    pos_path: &std::path::Path,
-    param_map: &IndexMap<String, f64>,
-    positions_file: &std::path::Path,
-) -> Result<(), Box<dyn std::error::Error>> {
-    //let start = Instant::now();
-    let positions = String::from_utf8(std::fs::read(&pos_path)?)?;
-    let mut data = SimulationData::parse(&positions)?;
-    let error = get_error(&mut data);
-    {
-        let mut state = STATE.get().unwrap().lock().unwrap();
-        for param in state.params.iter_mut() {
-            let value = param_map.get(&param.name).unwrap();
-            param.optim.tell(*value, error).unwrap();
+/// Waits for `child` to exit, killing it and returning None once it runs longer than `timeout`
+fn wait_for(
+    child: &mut std::process::Child,
+    timeout: Option<Duration>,
+) -> std::io::Result<Option<std::process::ExitStatus>> {
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return child.wait().map(Some),
+    };
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
         }
-        state.results.push(SimulationRun {
-            parameters: param_map.clone(),
-            time: SystemTime::now(),
-            error,
-        });
-        let simulations = state.results.len();
-        if simulations == MAX_SIMULATIONS {
-            println!("Exiting after {}", MAX_SIMULATIONS);
-            RUNNING.store(false, Ordering::Relaxed);
-        } else {
-            println!("  {}", simulations);
+        if start.elapsed() >= timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Ok(None);
         }
+        std::thread::sleep(Duration::from_millis(50));
     }
-    let old_error = LOWEST_ERROR.load(Ordering::Relaxed);
-    if error < old_error {
-        //If multiple threads get in here we don't really care...
-        LOWEST_ERROR.store(error, Ordering::Relaxed);
-        let src = positions_file;
-        let mut dest = PathBuf::from(positions_file);
-        dest.pop(); //Pop positions csv file name
-        dest.push("out");
-        let _ = std::fs::create_dir_all(&dest);
-        dest.push(format!("{}.csv", error));
-        std::fs::copy(src, dest).unwrap();
-        println!("  got best error: {} for params: {:?}", error, param_map);
+}
+
+/// How long past the timeout a remote simulation is waited for before its ssh connection is
+/// killed, so the host has time to stop it itself
+#[cfg(feature = "remote")]
+const REMOTE_TIMEOUT_GRACE: Duration = Duration::from_secs(10);
+
+/// Runs the simulation with `args` on `Config::remote` and copies its positions file back, or
+/// returns None when the simulation runs locally. The remote command is built the same way as
+/// the local one, with the host stopping the simulation through `timeout` since killing ssh may
+/// leave it running
+#[cfg(feature = "remote")]
+fn run_remote(
+    args: &[String],
+    config: &Config,
+    log_path: Option<&Path>,
+) -> Option<Result<RunOutcome, crate::Error>> {
+    let remote = config.remote.as_ref()?;
+    let (args, positions) = crate::remote::remote_args(remote, args);
+    let host_paths = Ns3Paths {
+        working_dir: remote.working_dir.clone(),
+        binary: remote.binary.clone(),
+        lib_dir: remote.lib_dir.clone(),
+    };
+    let mut wrapper = Vec::new();
+    if let Some(timeout) = config.simulation_timeout {
+        wrapper.push("timeout".to_owned());
+        wrapper.push(timeout.to_string());
+    }
+    wrapper.extend(config.simulation_wrapper.iter().cloned());
+    let script = shell_command(&host_paths, &wrapper, &args);
+    if config.log_commands {
+        log!("Running on {}: {}", remote.host, script);
     }
 
-    if let Some(err) = std::fs::remove_file(pos_path).err() {
-        println!(
-            "failed to delete temp positions file: {} - {}",
-            pos_path.to_str().unwrap(),
-            err
-        );
+    let run = || {
+        let mut command = crate::remote::ssh(remote, &script);
+        command
+            .stdin(std::process::Stdio::null())
+            .stdout(crate::util::child_stdout());
+        log_output(&mut command, log_path)?;
+        let mut child = command
+            .spawn()
+            .map_err(|err| crate::Error::Simulation(format!("Failed to start ssh: {}", err)))?;
+        let timeout = config
+            .simulation_timeout
+            .map(|timeout| Duration::from_secs_f64(timeout) + REMOTE_TIMEOUT_GRACE);
+        let outcome = match wait_for(&mut child, timeout)? {
+            None => RunOutcome::TimedOut,
+            // The exit status of `timeout` when it had to stop the simulation
+            Some(status) if status.code() == Some(124) && config.simulation_timeout.is_some() => {
+                RunOutcome::TimedOut
+            }
+            Some(status) if status.success() => RunOutcome::Finished,
+            Some(status) => {
+                return Err(crate::Error::Simulation(format!(
+                    "The simulation on {} exited with {}",
+                    remote.host, status
+                )))
+            }
+        };
+        if let Some((local, remote_path)) = &positions {
+            if outcome == RunOutcome::Finished || config.score_timed_out {
+                crate::remote::fetch(remote, remote_path, local)?;
+            }
+        }
+        Ok(outcome)
+    };
+    let result = run();
+    if let Some((_, remote_path)) = &positions {
+        crate::remote::remove(remote, remote_path);
+    }
+    Some(result)
+}
+
+#[cfg(not(feature = "remote"))]
+fn run_remote(
+    _args: &[String],
+    _config: &Config,
+    _log_path: Option<&Path>,
+) -> Option<Result<RunOutcome, crate::Error>> {
+    None
+}
+
+/// Explains why a simulation couldn't be started. The paths were resolved when the optimizer
+/// started, so this is usually the working directory or the binary being deleted during a run
+fn spawn_error(paths: &Ns3Paths, err: std::io::Error) -> crate::Error {
+    if !paths.working_dir.is_dir() {
+        crate::Error::Simulation(format!(
+            "The simulation working directory {} no longer exists ({})",
+            paths.working_dir.display(),
+            err
+        ))
+    } else if !paths.binary.exists() {
+        crate::Error::Simulation(format!(
+            "The simulation binary {} no longer exists ({})",
+            paths.binary.display(),
+            err
+        ))
+    } else {
+        err.into()
+    }
+}
+
+/// Returns a new randomly named path for a simulation to write its positions to
+fn temp_positions_file(paths: &Ns3Paths) -> PathBuf {
+    let pos_file_name: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(10)
+        .map(char::from)
+        .collect();
+
+    let mut positions_file = paths.working_dir.join(pos_file_name);
+    positions_file.set_extension("csv");
+    positions_file
+}
+
+/// Formats a parameter value or error with `precision` decimal places, or with as many as it
+/// takes to represent it exactly when `precision` is None
+fn format_value(value: f64, precision: Option<usize>) -> String {
+    match precision {
+        Some(precision) => format!("{:.*}", precision, value),
+        None => value.to_string(),
+    }
+}
+
+/// `value` rounded to the `precision` the simulation is given it with. A value that rounds out of
+/// `range` is moved one decimal place back inside it, and one that can't be is left as it is
+fn round_param(value: f64, precision: Option<usize>, range: &Range<f64>) -> f64 {
+    let precision = match precision {
+        Some(precision) => precision,
+        None => return value,
+    };
+    let round = |value: f64| format_value(value, Some(precision)).parse::<f64>().unwrap();
+    let step = 10f64.powi(-(precision as i32));
+    let rounded = round(value);
+    let rounded = if rounded >= range.end {
+        round(rounded - step)
+    } else if rounded < range.start {
+        round(rounded + step)
+    } else {
+        rounded
+    };
+    if range.contains(&rounded) {
+        rounded
+    } else {
+        value
+    }
+}
+
+/// Builds the command line arguments for one simulation run
+fn simulation_args(
+    positions_file: &Path,
+    seed: usize,
+    param_map: &IndexMap<String, f64>,
+    config: &Config,
+) -> Vec<String> {
+    let groups = &config.parameter_groups;
+    let format = |value: f64| format_value(value, config.positions_precision);
+    let mut args: Vec<String> = BASE_ARGUMENTS.iter().map(|arg| (*arg).to_owned()).collect();
+    args.push(format!("--duration={}", config.sim_duration));
+    args.push(format!(
+        "--positionsFile={}",
+        positions_file.to_str().unwrap()
+    ));
+    args.push(format!("--seed={}", seed));
+    for (name, value) in param_map {
+        if !groups.iter().any(|group| group.params.contains(name)) {
+            args.push(format!("--{}={}", name, format(*value)));
+        }
+    }
+    for group in groups {
+        let values: Vec<String> = group
+            .params
+            .iter()
+            .map(|name| format(param_map[name]))
+            .collect();
+        args.push(format!("--{}={}", group.arg, values.join(&group.separator)));
+    }
+    args
+}
+
+/// How long each step of an evaluation took
+struct EvaluationTimes {
+    simulation: Duration,
+    parse: Duration,
+    fitness: Duration,
+}
+
+impl EvaluationTimes {
+    /// The time accumulated in `SIMULATION_TIME`, `PARSE_TIME` and `FITNESS_TIME`
+    fn total() -> Self {
+        let load = |total: &AtomicU64| Duration::from_micros(total.load(Ordering::Relaxed));
+        Self {
+            simulation: load(&SIMULATION_TIME),
+            parse: load(&PARSE_TIME),
+            fitness: load(&FITNESS_TIME),
+        }
+    }
+
+    /// Each phase's total time and share of the time spent in all three. Runners work in
+    /// parallel, so the sum can be more than `wall_time`
+    fn report(&self, wall_time: Duration) -> String {
+        let phases = [
+            ("simulation", self.simulation),
+            ("parsing", self.parse),
+            ("fitness", self.fitness),
+        ];
+        let sum: Duration = phases.iter().map(|(_, time)| *time).sum();
+        let mut report = format!(
+            "Time spent over {:.1}s of wall time:",
+            wall_time.as_secs_f64()
+        );
+        for (name, time) in phases {
+            let share = if sum.is_zero() {
+                0.0
+            } else {
+                100.0 * time.as_secs_f64() / sum.as_secs_f64()
+            };
+            report.push_str(&format!(
+                "\n  {:<10} {:>10.2}s {:>5.1}%",
+                name,
+                time.as_secs_f64(),
+                share
+            ));
+        }
+        report
+    }
+}
+
+/// Adds the time since `start` to `total`
+fn add_time(total: &AtomicU64, start: Instant) {
+    total.fetch_add(start.elapsed().as_micros() as u64, Ordering::Relaxed);
+}
+
+/// Runs one simulation with the parameters in `param_map` and scores it, without telling the
+/// optimizers about the result
+fn evaluate(
+    paths: &Ns3Paths,
+    config: &Config,
+    param_map: &IndexMap<String, f64>,
+    seed: usize,
+) -> Result<(CostBreakdown, EvaluationTimes), crate::Error> {
+    let positions_file = temp_positions_file(paths);
+    let args = simulation_args(&positions_file, seed, param_map, config);
+
+    let start = Instant::now();
+    let log_path = sim_log_path(config, &positions_file);
+    let result = run_binary(paths, &args, config, log_path.as_deref());
+    let simulation = start.elapsed();
+    if !matches!(result, Ok(RunOutcome::Finished)) {
+        let _ = std::fs::remove_file(&positions_file);
+        return Err(match result {
+            Ok(RunOutcome::OutOfMemory) => {
+                crate::Error::Simulation("Simulation ran out of memory".to_owned())
+            }
+            Ok(_) => crate::Error::Simulation("Simulation timed out".to_owned()),
+            Err(err) => err,
+        });
+    }
+
+    let start = Instant::now();
+    let data = SimulationData::load(&positions_file, &config.parser, false);
+    let parse = start.elapsed();
+    if data.is_ok() {
+        let _ = remove_scored_files(config, &positions_file);
+    } else {
+        let _ = std::fs::remove_file(&positions_file);
+    }
+    let mut data = data?;
+
+    let start = Instant::now();
+    let breakdown = fitness::get_error(&mut data, &config.fitness);
+    let fitness = start.elapsed();
+
+    Ok((
+        breakdown,
+        EvaluationTimes {
+            simulation,
+            parse,
+            fitness,
+        },
+    ))
+}
+
+/// Runs a single simulation with the default parameters, printing how long each step took.
+/// Used to check that the whole pipeline works before starting a long optimization
+pub fn smoke_test(paths: &Ns3Paths, config: &Config) -> Result<(), crate::Error> {
+    let param_map: IndexMap<String, f64> = config
+        .parameters
+        .iter()
+        .map(|param| (param.name.clone(), DEFAULT_PARAM_VALUE))
+        .collect();
+    let (breakdown, times) = evaluate(paths, config, &param_map, rand::thread_rng().gen())?;
+
+    log!("Simulation took {:.2?}", times.simulation);
+    log!("Parsing took {:.2?}", times.parse);
+    log!("Scoring took {:.2?}", times.fitness);
+    log!("Error for {:?}: {}", param_map, breakdown.total());
+    log!("  breakdown: {:?}", breakdown);
+    Ok(())
+}
+
+/// The seed used for parameter sets that don't specify one, so that replays are repeatable
+const DEFAULT_REPLAY_SEED: usize = 0;
+
+/// Runs one simulation with the parameters in `assignments`, written as name=value pairs, and
+/// prints its error and breakdown. It uses the replay seed so repeated checks of the same
+/// parameters agree
+pub fn single_eval(
+    paths: &Ns3Paths,
+    config: &Config,
+    assignments: &str,
+) -> Result<(), crate::Error> {
+    let param_map = parse_parameter_assignments(assignments, config)?;
+    let (breakdown, times) = evaluate(paths, config, &param_map, DEFAULT_REPLAY_SEED)?;
+    log!(
+        "Error for {:?} with seed {}: {}",
+        param_map,
+        DEFAULT_REPLAY_SEED,
+        breakdown.total()
+    );
+    log!("  breakdown: {:?}", breakdown);
+    log!(
+        "Took {:.2?} to simulate, {:.2?} to parse and {:.2?} to score",
+        times.simulation,
+        times.parse,
+        times.fitness
+    );
+    Ok(())
+}
+
+/// A row from a parameter set csv
+#[derive(Debug, PartialEq)]
+struct ParameterSet {
+    params: IndexMap<String, f64>,
+    seed: Option<usize>,
+}
+
+/// Parses a csv with a header row naming every configured parameter and one parameter set per
+/// row. An optional `seed` column sets the simulation seed for that row
+fn parse_parameter_sets(csv: &str, config: &Config) -> Result<Vec<ParameterSet>, crate::Error> {
+    let mut lines = csv.lines().filter(|line| !line.trim().is_empty());
+    let header: Vec<&str> = match lines.next() {
+        Some(header) => header.split(',').map(str::trim).collect(),
+        None => {
+            return Err(crate::Error::Config(
+                "Parameter set csv is empty".to_owned(),
+            ))
+        }
+    };
+    for param in &config.parameters {
+        if !header.contains(&param.name.as_str()) {
+            return Err(crate::Error::Config(format!(
+                "Parameter set csv has no column for {}",
+                param.name
+            )));
+        }
+    }
+    for column in &header {
+        if *column != "seed" && !config.parameters.iter().any(|p| p.name == *column) {
+            return Err(crate::Error::Config(format!(
+                "Unknown parameter set column {}",
+                column
+            )));
+        }
+    }
+
+    let mut sets = Vec::new();
+    for (row, line) in lines.enumerate() {
+        let values: Vec<&str> = line.split(',').map(str::trim).collect();
+        if values.len() != header.len() {
+            return Err(crate::Error::Config(format!(
+                "Parameter set {} has {} values but the header has {}",
+                row + 1,
+                values.len(),
+                header.len()
+            )));
+        }
+        let mut set = ParameterSet {
+            params: IndexMap::new(),
+            seed: None,
+        };
+        for (column, value) in header.iter().zip(values) {
+            if *column == "seed" {
+                set.seed = Some(value.parse()?);
+            } else {
+                set.params.insert((*column).to_owned(), value.parse()?);
+            }
+        }
+        sets.push(set);
+    }
+    Ok(sets)
+}
+
+/// Reads the parameter sets in the `seed_points` csv at `path`. Every value has to be inside its
+/// parameter's range, as the optimizers can't be told about values outside it
+fn load_seed_points(
+    path: &Path,
+    config: &Config,
+) -> Result<VecDeque<IndexMap<String, f64>>, crate::Error> {
+    let sets = parse_parameter_sets(&std::fs::read_to_string(path)?, config)?;
+    if sets.iter().any(|set| set.seed.is_some()) {
+        log!("Ignoring the seed column of {}", path.display());
+    }
+    sets.into_iter()
+        .enumerate()
+        .map(|(row, set)| {
+            let mut params = IndexMap::new();
+            for param in &config.parameters {
+                let range = param.search_range();
+                let value = set.params[&param.name];
+                if !(range.start..=range.end).contains(&value) {
+                    return Err(crate::Error::Config(format!(
+                        "Seed point {} has {} = {}, outside its range {}..{}",
+                        row + 1,
+                        param.name,
+                        value,
+                        range.start,
+                        range.end
+                    )));
+                }
+                params.insert(param.name.clone(), clamp_param(value, &range));
+            }
+            Ok(params)
+        })
+        .collect()
+}
+
+/// The `warmup_samples` parameter sets, spread over each parameter's search range
+fn warmup_points(config: &Config, rng: &mut impl Rng) -> VecDeque<IndexMap<String, f64>> {
+    let ranges: Vec<Range<f64>> = config.parameters.iter().map(|p| p.search_range()).collect();
+    crate::warmup::sample(config.warmup_sampler, config.warmup_samples, &ranges, rng)
+        .into_iter()
+        .map(|values| {
+            config
+                .parameters
+                .iter()
+                .zip(values.into_iter().zip(&ranges))
+                .map(|(param, (value, range))| (param.name.clone(), clamp_param(value, range)))
+                .collect()
+        })
+        .collect()
+}
+
+/// A finished run from an earlier `results.csv`, used to warm start the optimizers
+#[derive(Debug, PartialEq)]
+struct PastResult {
+    /// Only the parameters the old run had, which may be fewer than are configured now
+    params: IndexMap<String, f64>,
+    error: f64,
+}
+
+/// Parses a `results.csv` written by an earlier run. The columns before `fitness` are that run's
+/// parameters, which must all still be configured, but configured parameters may be missing from
+/// it. Rows without a fitness are skipped
+fn parse_past_results(csv: &str, config: &Config) -> Result<Vec<PastResult>, crate::Error> {
+    let mut lines = csv.lines().filter(|line| !line.trim().is_empty());
+    let header: Vec<&str> = match lines.next() {
+        Some(header) => header.split(',').map(str::trim).collect(),
+        None => return Err("Results csv is empty".into()),
+    };
+    let fitness = header
+        .iter()
+        .position(|column| *column == "fitness")
+        .ok_or("Results csv has no fitness column")?;
+    let params = &header[..fitness];
+    for param in params {
+        if !config.parameters.iter().any(|p| p.name == *param) {
+            return Err(format!(
+                "Results csv has parameter {} which is no longer configured",
+                param
+            )
+            .into());
+        }
+    }
+
+    let mut results = Vec::new();
+    for (row, line) in lines.enumerate() {
+        let values: Vec<&str> = line.split(',').map(str::trim).collect();
+        if values.len() <= fitness {
+            return Err(format!("Result {} is missing its fitness", row + 1).into());
+        }
+        let error: f64 = values[fitness].parse()?;
+        if error.is_nan() {
+            continue;
+        }
+        let mut result = PastResult {
+            params: IndexMap::new(),
+            error,
+        };
+        for (param, value) in params.iter().zip(&values) {
+            result.params.insert((*param).to_owned(), value.parse()?);
+        }
+        results.push(result);
+    }
+    Ok(results)
+}
+
+/// Keeps the results in `past` that the `warm_start` limits allow, in their original order
+fn select_past_results(mut past: Vec<PastResult>, limits: &WarmStartConfig) -> Vec<PastResult> {
+    if let Some(recent) = limits.recent {
+        past.drain(..past.len().saturating_sub(recent));
+    }
+    if let Some(band) = limits.error_band {
+        let lowest = past
+            .iter()
+            .map(|result| result.error)
+            .fold(f64::INFINITY, f64::min);
+        past.retain(|result| result.error <= lowest + band);
+    }
+    past
+}
+
+/// Tells the optimizers about every result in the `results.csv` at `csv_path`. Each parameter is
+/// optimized on its own, so a parameter that is new since that run just starts without history
+/// while the others keep theirs. The old errors are used as they are, so they are only comparable
+/// if the fitness settings haven't changed
+fn warm_start(state: &mut StateImpl, config: &Config, csv_path: &str) -> Result<(), crate::Error> {
+    let past = parse_past_results(&std::fs::read_to_string(csv_path)?, config)?;
+    let parsed = past.len();
+    let past = select_past_results(past, &config.warm_start);
+    if past.len() < parsed {
+        log!(
+            "Using {} of the {} results in {} because of the warm_start limits",
+            past.len(),
+            parsed,
+            csv_path
+        );
+    }
+    for result in &past {
+        for param in state.params.iter_mut() {
+            // Results outside a restricted range say nothing about the part being searched
+            let range = configured_range(config, &param.name);
+            match result.params.get(&param.name) {
+                Some(value) if (range.start..=range.end).contains(value) => {
+                    param
+                        .optim
+                        .tell(clamp_param(*value, &range), result.error)?;
+                }
+                _ => {}
+            }
+        }
+    }
+    let (known, new): (Vec<&Parameter>, Vec<&Parameter>) = state.params.iter().partition(|param| {
+        past.first()
+            .is_some_and(|result| result.params.contains_key(&param.name))
+    });
+    let names = |params: Vec<&Parameter>| -> Vec<String> {
+        params.iter().map(|param| param.name.clone()).collect()
+    };
+    log!(
+        "Warm started {:?} from {} results in {}",
+        names(known),
+        past.len(),
+        csv_path
+    );
+    if !new.is_empty() {
+        log!("{:?} have no history and start from scratch", names(new));
+    }
+    Ok(())
+}
+
+/// The value `param` is seeded with, which is its configured default or else 1. Either way it is
+/// clamped to the parameter's range
+fn default_value(param: &ParameterConfig) -> f64 {
+    clamp_param(
+        param.default.unwrap_or(DEFAULT_PARAM_VALUE),
+        &param.search_range(),
+    )
+}
+
+/// An optimizer for each configured parameter, seeded with the default value
+fn seeded_parameters(config: &Config) -> Vec<Parameter> {
+    let default_error = config.tpe.default_error.unwrap_or(LOWEST_ERROR_START);
+    config
+        .parameters
+        .iter()
+        .map(|param| {
+            let range = param.search_range();
+            let mut optim = config
+                .tpe
+                .build_optimizer(tpe::range(range.start, range.end).unwrap())
+                .unwrap();
+            // Fill in default values so parameters start around them
+            optim.tell(default_value(param), default_error).unwrap();
+            Parameter {
+                name: param.name.clone(),
+                optim,
+            }
+        })
+        .collect()
+}
+
+/// The next `count` parameter sets the optimizers would pick after being warm started from the
+/// results in `csv_path`. The optimizers aren't told about the suggestions, so each one is drawn
+/// from the same history
+fn suggestions(
+    config: &Config,
+    csv_path: &str,
+    count: usize,
+) -> Result<Vec<IndexMap<String, f64>>, crate::Error> {
+    let mut state = StateImpl {
+        params: seeded_parameters(config),
+        results: Vec::new(),
+        term_ranges: TermRanges::default(),
+        error_quantiles: ErrorQuantiles::default(),
+    };
+    warm_start(&mut state, config, csv_path)?;
+    let mut rng = rand::thread_rng();
+    let mut suggestions = Vec::new();
+    for _ in 0..count {
+        let mut params = IndexMap::new();
+        for param in state.params.iter_mut() {
+            let range = configured_range(config, &param.name);
+            let value = clamp_param(param.optim.ask(&mut rng)?, &range);
+            params.insert(param.name.clone(), value);
+        }
+        suggestions.push(params);
+    }
+    Ok(suggestions)
+}
+
+/// Prints the parameter sets `suggestions` returns as a csv that `replay` can run. Progress
+/// messages go to stderr so the csv can be redirected to a file
+pub fn suggest(config: &Config, csv_path: &str, count: usize) -> Result<(), crate::Error> {
+    crate::util::LOG_TO_STDERR.store(true, Ordering::Relaxed);
+    let suggestions = suggestions(config, csv_path, count)?;
+    let names: Vec<&str> = config
+        .parameters
+        .iter()
+        .map(|param| param.name.as_str())
+        .collect();
+    println!("{}", names.join(","));
+    for params in suggestions {
+        let values: Vec<String> = params.values().map(f64::to_string).collect();
+        println!("{}", values.join(","));
+    }
+    Ok(())
+}
+
+/// Evaluates every parameter set in `csv_path` in order and prints the errors. The optimizers
+/// are not involved, which makes this useful for checking that results are reproducible
+pub fn replay(paths: &Ns3Paths, config: &Config, csv_path: &str) -> Result<(), crate::Error> {
+    let sets = parse_parameter_sets(&std::fs::read_to_string(csv_path)?, config)?;
+    log!("Replaying {} parameter sets", sets.len());
+    for (i, set) in sets.iter().enumerate() {
+        let seed = set.seed.unwrap_or(DEFAULT_REPLAY_SEED);
+        match evaluate(paths, config, &set.params, seed) {
+            Ok((breakdown, _)) => {
+                log!(
+                    "{}: {:?} seed {} -> error {}",
+                    i + 1,
+                    set.params,
+                    seed,
+                    breakdown.total()
+                );
+                log!("  breakdown: {:?}", breakdown);
+            }
+            Err(err) => log!("{}: {:?} seed {} failed: {}", i + 1, set.params, seed, err),
+        }
+    }
+    Ok(())
+}
+
+/// Parses a parameter set written as `name=value` pairs separated by commas, such as `a=1.5,r=2`.
+/// Every configured parameter must be given
+pub fn parse_parameter_assignments(
+    assignments: &str,
+    config: &Config,
+) -> Result<IndexMap<String, f64>, crate::Error> {
+    let mut params = IndexMap::new();
+    for assignment in assignments.split(',').map(str::trim) {
+        let (name, value) = assignment.split_once('=').ok_or_else(|| {
+            crate::Error::Config(format!("Expected name=value but got {}", assignment))
+        })?;
+        if !config.parameters.iter().any(|param| param.name == name) {
+            return Err(crate::Error::Config(format!("Unknown parameter {}", name)));
+        }
+        let value = value.parse().map_err(|err| {
+            crate::Error::Config(format!("Invalid value {} for {}: {}", value, name, err))
+        })?;
+        params.insert(name.to_owned(), value);
+    }
+    for param in &config.parameters {
+        if !params.contains_key(&param.name) {
+            return Err(crate::Error::Config(format!(
+                "No value given for parameter {}",
+                param.name
+            )));
+        }
+    }
+    Ok(params)
+}
+
+/// Prints the mean, standard deviation, min and max of `values` on one line
+fn print_spread(label: &str, values: &[f64]) {
+    let mean = rgsl::statistics::mean(values, 1, values.len());
+    let sd = if values.len() > 1 {
+        rgsl::statistics::sd(values, 1, values.len())
+    } else {
+        0.0
+    };
+    let min = values.iter().cloned().fold(f64::INFINITY, f64::min);
+    let max = values.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    log!(
+        "{:>22}: mean {:.4}, std {:.4}, min {:.4}, max {:.4}",
+        label,
+        mean,
+        sd,
+        min,
+        max
+    );
+}
+
+/// Evaluates the reference parameter set `param_map` once with every scenario seed, or with seed
+/// 1 when there are none, and combines the errors like a sample's. `None` if it couldn't be
+/// scored in a way that is comparable to the samples
+fn evaluate_baseline(
+    paths: &Ns3Paths,
+    config: &Config,
+    param_map: &IndexMap<String, f64>,
+) -> Option<f64> {
+    if config.fitness.normalization == Normalization::PopulationRange {
+        log!("Skipping the baseline, errors aren't comparable across runs with PopulationRange normalization");
+        return None;
+    }
+    let seeds = if config.scenario_seeds.is_empty() {
+        vec![1]
+    } else {
+        config.scenario_seeds.clone()
+    };
+    let mut errors = Vec::new();
+    for seed in seeds {
+        match evaluate(paths, config, param_map, seed) {
+            Ok((breakdown, _)) => errors.push(breakdown.total()),
+            Err(err) => {
+                log!(
+                    "The baseline {:?} failed with seed {}: {}",
+                    param_map,
+                    seed,
+                    err
+                );
+                return None;
+            }
+        }
+    }
+    let error = config.seed_reducer.reduce(&errors);
+    log!("Baseline {:?} has error {}", param_map, error);
+    Some(error)
+}
+
+/// Checks that the lowest error among the positions files saved in `best_dir`, which are named
+/// after their error with `precision`, is both `lowest_error` and the `summary_best` error of the
+/// results. Describes the mismatch if not. Nothing is saved until an error beats the starting
+/// `LOWEST_ERROR`, so there is nothing to check before then
+fn best_file_mismatch(
+    best_dir: &Path,
+    lowest_error: f64,
+    summary_best: f64,
+    precision: Option<usize>,
+) -> Option<String> {
+    let saved_best = std::fs::read_dir(best_dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            if path.extension()? != "csv" {
+                return None;
+            }
+            path.file_stem()?.to_str()?.parse::<f64>().ok()
+        })
+        .fold(f64::INFINITY, f64::min);
+    if saved_best.is_infinite() && lowest_error >= LOWEST_ERROR_START {
+        return None;
+    }
+    let saved = format_value(saved_best, precision);
+    if saved == format_value(lowest_error, precision)
+        && saved == format_value(summary_best, precision)
+    {
+        return None;
+    }
+    Some(format!(
+        "The best saved positions file in {} has error {}, but the lowest error recorded was {} and the best result in the summary has error {}. A file left over from an earlier run with the same output directory can also cause this",
+        best_dir.display(),
+        saved_best,
+        lowest_error,
+        summary_best
+    ))
+}
+
+/// Describes how much lower `best` is than the `baseline` error
+fn baseline_comparison(best: f64, baseline: f64) -> String {
+    let mut comparison = format!("Best error {} vs baseline {}", best, baseline);
+    if baseline > 0.0 {
+        let change = 100.0 * (baseline - best) / baseline;
+        if change >= 0.0 {
+            comparison += &format!(", a {:.1}% improvement", change);
+        } else {
+            comparison += &format!(", {:.1}% worse", -change);
+        }
+    }
+    comparison
+}
+
+/// How many results there have to be before a parameter is flagged for only being sampled in part
+/// of its range. Early on the optimizers haven't had the chance to explore the whole range
+const RANGE_USAGE_MIN_RESULTS: usize = 50;
+/// Parameters sampled over less than this fraction of their range are flagged
+const RANGE_USAGE_FRACTION: f64 = 0.25;
+
+/// The lowest and highest value of each configured parameter in `results`, flagging the ones that
+/// only covered a small part of their range as candidates for a tighter `min` and `max`
+fn range_usage(results: &[SimulationRun], config: &Config) -> Vec<String> {
+    config
+        .parameters
+        .iter()
+        .map(|param| {
+            let range = configured_range(config, &param.name);
+            let (low, high) = results
+                .iter()
+                .filter_map(|run| run.parameters.get(&param.name))
+                .fold((f64::INFINITY, f64::NEG_INFINITY), |(low, high), value| {
+                    (low.min(*value), high.max(*value))
+                });
+            if low > high {
+                return format!("{}: never sampled", param.name);
+            }
+            let fraction = (high - low) / (range.end - range.start);
+            let mut line = format!(
+                "{}: sampled {:.3}..{:.3} of {}..{} ({:.0}% of the range)",
+                param.name,
+                low,
+                high,
+                range.start,
+                range.end,
+                100.0 * fraction
+            );
+            if results.len() >= RANGE_USAGE_MIN_RESULTS && fraction < RANGE_USAGE_FRACTION {
+                // Leave some room around what was sampled
+                let margin = 0.1 * (range.end - range.start);
+                line += &format!(
+                    " - the range may be too wide, consider narrowing it to about {:.3}..{:.3}",
+                    (low - margin).max(range.start),
+                    (high + margin).min(range.end)
+                );
+            }
+            line
+        })
+        .collect()
+}
+
+/// Evaluates the same parameter set `repeats` times with seeds 1 to `repeats` and prints how much
+/// the error and each cost term vary between seeds
+pub fn measure_noise(
+    paths: &Ns3Paths,
+    config: &Config,
+    assignments: &str,
+    repeats: usize,
+) -> Result<(), crate::Error> {
+    let param_map = parse_parameter_assignments(assignments, config)?;
+    let mut breakdowns = Vec::new();
+    for seed in 1..=repeats {
+        match evaluate(paths, config, &param_map, seed) {
+            Ok((breakdown, _)) => {
+                log!("seed {}: error {}", seed, breakdown.total());
+                breakdowns.push(breakdown);
+            }
+            Err(err) => log!("seed {} failed: {}", seed, err),
+        }
+    }
+    if breakdowns.is_empty() {
+        return Err(crate::Error::Simulation(
+            "Every simulation failed".to_owned(),
+        ));
+    }
+
+    log!(
+        "Noise for {:?} over {} of {} seeds:",
+        param_map,
+        breakdowns.len(),
+        repeats
+    );
+    let totals: Vec<f64> = breakdowns.iter().map(CostBreakdown::total).collect();
+    print_spread("error", &totals);
+    for (i, (name, _)) in breakdowns[0].terms().into_iter().enumerate() {
+        let values: Vec<f64> = breakdowns.iter().map(|b| b.terms()[i].1).collect();
+        print_spread(name, &values);
+    }
+    Ok(())
+}
+
+/// Evaluates the parameter set in `assignments`, then each parameter moved `step` down and up
+/// while the others stay put, and prints the central difference estimate of the error gradient
+/// for each parameter
+pub fn sensitivity(
+    paths: &Ns3Paths,
+    config: &Config,
+    assignments: &str,
+    step: f64,
+    seed: usize,
+) -> Result<(), crate::Error> {
+    let center = parse_parameter_assignments(assignments, config)?;
+    let (breakdown, _) = evaluate(paths, config, &center, seed)?;
+    log!("Error at {:?}: {}", center, breakdown.total());
+
+    for name in center.keys() {
+        let values = sensitivity_values(center[name], step, &configured_range(config, name));
+        let mut errors = [0.0; 2];
+        for (error, value) in errors.iter_mut().zip(values) {
+            let mut params = center.clone();
+            params[name] = value;
+            let (breakdown, _) = evaluate(paths, config, &params, seed)?;
+            *error = breakdown.total();
+        }
+        log!(
+            "{}: error {} at {}, {} at {}, gradient {}",
+            name,
+            errors[0],
+            values[0],
+            errors[1],
+            values[1],
+            (errors[1] - errors[0]) / (values[1] - values[0])
+        );
     }
     Ok(())
 }
+
+/// The values `step` below and above `value` that `sensitivity` evaluates. A step past the end
+/// of `range` stops at the end, so the simulation never sees a value the search wouldn't try
+fn sensitivity_values(value: f64, step: f64, range: &Range<f64>) -> [f64; 2] {
+    [value - step, value + step].map(|value| value.clamp(range.start, range.end))
+}
+
+/// Returns how many of the last `window` samples failed and how many samples that covers
+fn count_recent_failures(failures: u64, samples: usize, window: usize) -> (u32, usize) {
+    let window = window.min(64).min(samples);
+    let mask = if window == 64 {
+        u64::MAX
+    } else {
+        (1 << window) - 1
+    };
+    ((failures & mask).count_ones(), window)
+}
+
+/// Whether `patience` evaluations have finished since the best error last improved at
+/// evaluation `last_improvement`
+fn is_stalled(simulations: usize, last_improvement: usize, patience: usize) -> bool {
+    simulations - last_improvement >= patience
+}
+
+/// The lowest error after each result, in the order they finished
+fn best_so_far(results: &[SimulationRun]) -> Vec<f64> {
+    results
+        .iter()
+        .scan(f64::INFINITY, |best, run| {
+            *best = best.min(run.error);
+            Some(*best)
+        })
+        .collect()
+}
+
+/// Roughly how many more evaluations it will take for the best error to reach `target`, from a
+/// power law `best = c * n^-k` fitted to the best-so-far series in log-log space. Returns `None`
+/// when there is too little data or the best error isn't falling
+fn evaluations_to_target(best: &[f64], target: f64) -> Option<f64> {
+    const MIN_POINTS: usize = 10;
+
+    let current = *best.last()?;
+    if current <= target {
+        return Some(0.0);
+    }
+    let points: Vec<(f64, f64)> = best
+        .iter()
+        .enumerate()
+        .filter(|(_, error)| error.is_finite() && **error > 0.0)
+        .map(|(i, error)| (((i + 1) as f64).ln(), error.ln()))
+        .collect();
+    if points.len() < MIN_POINTS {
+        return None;
+    }
+    let count = points.len() as f64;
+    let mean_x = points.iter().map(|(x, _)| x).sum::<f64>() / count;
+    let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / count;
+    let covariance: f64 = points
+        .iter()
+        .map(|(x, y)| (x - mean_x) * (y - mean_y))
+        .sum();
+    let variance: f64 = points.iter().map(|(x, _)| (x - mean_x).powi(2)).sum();
+    let slope = covariance / variance;
+    if slope.is_nan() || slope >= 0.0 {
+        return None;
+    }
+    let intercept = mean_y - slope * mean_x;
+    let needed = ((target.ln() - intercept) / slope).exp();
+    Some((needed - best.len() as f64).max(0.0))
+}
+
+/// Records the outcome of a sample and warns (or stops) when too many recent samples failed
+fn record_sample(runner: usize, success: bool) {
+    let _ = RECENT_FAILURES.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+        Some((bits << 1) | !success as u64)
+    });
+    RECENT_SAMPLES.fetch_add(1, Ordering::Relaxed);
+    if success {
+        let now = RUN_START.get().unwrap().elapsed().as_millis() as u64;
+        LAST_SUCCESS.get().unwrap()[runner].store(now, Ordering::Relaxed);
+        return;
+    }
+
+    let monitor = &CONFIG.get().unwrap().failure_monitor;
+    let (failed, samples) = count_recent_failures(
+        RECENT_FAILURES.load(Ordering::Relaxed),
+        RECENT_SAMPLES.load(Ordering::Relaxed),
+        monitor.window,
+    );
+    if samples >= monitor.window.min(64)
+        && failed as f64 / samples as f64 > monitor.max_failure_rate
+    {
+        log!(
+            "WARNING: {} of the last {} samples failed! Check the simulation binary and its output",
+            failed,
+            samples
+        );
+        if monitor.abort {
+            log!("Stopping because of the failure rate");
+            stop("too many recent simulations failed");
+        }
+    }
+}
+
+/// The message a panic was started with, if it was given one
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "<no message>"
+    }
+}
+
+/// Cleans up after scoring `simulations` panicked, so the runner can go on to the next sample.
+/// Stops the optimizer once `max_panics` samples have panicked
+fn recover_from_panic(
+    payload: &(dyn std::any::Any + Send),
+    simulations: &[SeedSimulation],
+    param_map: &IndexMap<String, f64>,
+) {
+    log!(
+        "Scoring params {:?} panicked: {}",
+        param_map,
+        panic_message(payload)
+    );
+    for simulation in simulations {
+        let _ = std::fs::remove_file(&simulation.positions_file);
+    }
+    // A panic while the state was locked leaves at most this sample half recorded
+    STATE.get().unwrap().clear_poison();
+
+    let panics = PANICKED_SAMPLES.fetch_add(1, Ordering::Relaxed) + 1;
+    let max_panics = CONFIG.get().unwrap().failure_monitor.max_panics;
+    if max_panics.is_some_and(|max_panics| panics >= max_panics) {
+        log!("Stopping after {} samples panicked", panics);
+        stop(format!("{} samples panicked while being scored", panics));
+    }
+}
+
+/// Stops the optimizer if either `working_dir` or the directory the results are exported to has
+/// less than `min_free_mb` megabytes free
+fn check_disk_space(working_dir: &Path, min_free_mb: u64) {
+    let out_dir = CONFIG.get().unwrap().out_dir.as_deref().unwrap();
+    for dir in [working_dir, out_dir] {
+        let free = match crate::util::free_disk_space(dir) {
+            Some(free) => free,
+            None => continue,
+        };
+        if free < min_free_mb.saturating_mul(1024 * 1024) && RUNNING.load(Ordering::Relaxed) {
+            log!(
+                "Only {} MB left on the disk holding {}, which is below min_free_disk_mb ({} MB). Stopping so the results can still be saved",
+                free / (1024 * 1024),
+                dir.display(),
+                min_free_mb
+            );
+            stop("the disk is almost full");
+            return;
+        }
+    }
+}
+
+/// The progress line printed after every finished sample
+fn status_line(state: &StateImpl) -> String {
+    let simulations = state.results.len();
+    let quantiles = &state.error_quantiles;
+    let (failed, samples) = count_recent_failures(
+        RECENT_FAILURES.load(Ordering::Relaxed),
+        RECENT_SAMPLES.load(Ordering::Relaxed),
+        CONFIG.get().unwrap().failure_monitor.window,
+    );
+    let now = RUN_START.get().unwrap().elapsed().as_millis() as u64;
+    let longest_without_success = LAST_SUCCESS
+        .get()
+        .unwrap()
+        .iter()
+        .map(|last| now.saturating_sub(last.load(Ordering::Relaxed)))
+        .max()
+        .unwrap_or(0);
+    let quantile = |estimator: &P2Quantile| {
+        estimator
+            .estimate()
+            .map_or_else(|| "-".to_owned(), |error| format!("{:.2}", error))
+    };
+    let mut line = format!(
+        "  {} | p50 {} p90 {} | {} runners active | {}/{} recent samples failed | longest without success: {:.1}s",
+        simulations,
+        quantile(&quantiles.median),
+        quantile(&quantiles.p90),
+        ACTIVE_RUNNERS.load(Ordering::Relaxed),
+        failed,
+        samples,
+        longest_without_success as f64 / 1000.0
+    );
+    if let Some(target) = CONFIG.get().unwrap().target_fitness {
+        let estimate = match evaluations_to_target(&best_so_far(&state.results), target) {
+            Some(0.0) => "reached".to_owned(),
+            Some(remaining) => format!("~{:.0} more evals (rough extrapolation)", remaining),
+            None => "no estimate yet".to_owned(),
+        };
+        line += &format!(" | target {}: {}", target, estimate);
+    }
+    line
+}
+
+/// The next parameter values picked by the Gaussian process, in the order of `parameters`, or
+/// None when the TPE optimizers pick them
+#[cfg(feature = "gaussian-process")]
+fn gaussian_process_ask(rng: &mut impl Rng, config: &Config) -> Option<Vec<f64>> {
+    // The pick is kept in the range and rounded here so the process is told the values it picked
+    let simulated = |values: Vec<f64>| {
+        config
+            .parameters
+            .iter()
+            .zip(values)
+            .map(|(param, value)| {
+                let range = param.search_range();
+                round_param(
+                    clamp_param(value, &range),
+                    config.positions_precision,
+                    &range,
+                )
+            })
+            .collect()
+    };
+    GAUSSIAN_PROCESS
+        .get()
+        .map(|gp| gp.lock().unwrap().ask(rng, simulated))
+}
+
+#[cfg(not(feature = "gaussian-process"))]
+fn gaussian_process_ask(_rng: &mut impl Rng, _config: &Config) -> Option<Vec<f64>> {
+    None
+}
+
+/// Tells the Gaussian process about a result, returning false when the TPE optimizers are in use
+#[cfg(feature = "gaussian-process")]
+fn gaussian_process_tell(param_map: &IndexMap<String, f64>, error: f64) -> bool {
+    match GAUSSIAN_PROCESS.get() {
+        Some(gp) => {
+            let values: Vec<f64> = param_map.values().copied().collect();
+            gp.lock().unwrap().tell(&values, error);
+            true
+        }
+        None => false,
+    }
+}
+
+#[cfg(not(feature = "gaussian-process"))]
+fn gaussian_process_tell(_param_map: &IndexMap<String, f64>, _error: f64) -> bool {
+    false
+}
+
+/// Tells the Gaussian process the errors of every result in place of the ones it was told,
+/// returning false when the TPE optimizers are in use
+#[cfg(feature = "gaussian-process")]
+fn gaussian_process_retell(results: &[SimulationRun]) -> bool {
+    match GAUSSIAN_PROCESS.get() {
+        Some(gp) => {
+            gp.lock().unwrap().retell(
+                results
+                    .iter()
+                    .map(|run| (run.parameters.values().copied().collect(), run.error)),
+            );
+            true
+        }
+        None => false,
+    }
+}
+
+#[cfg(not(feature = "gaussian-process"))]
+fn gaussian_process_retell(_results: &[SimulationRun]) -> bool {
+    false
+}
+
+fn run_thread(runner: usize) {
+    let mut rng = rand::thread_rng();
+    let mut param_map = IndexMap::new();
+    ACTIVE_RUNNERS.fetch_add(1, Ordering::Relaxed);
+
+    while RUNNING.load(Ordering::Relaxed) {
+        let paths = PATHS.get().unwrap();
+        if let Some(min_free_mb) = CONFIG.get().unwrap().min_free_disk_mb {
+            check_disk_space(paths.working_dir(), min_free_mb);
+            if !RUNNING.load(Ordering::Relaxed) {
+                break;
+            }
+        }
+
+        param_map.clear();
+        let mut planned = false;
+        let cell = match GRID.get() {
+            Some(sweep) => {
+                let cell = match sweep.queue.lock().unwrap().pop_front() {
+                    Some(cell) => cell,
+                    None => {
+                        stop("every grid cell was picked up");
+                        break;
+                    }
+                };
+                let config = CONFIG.get().unwrap();
+                for (param, value) in config.parameters.iter().zip(sweep.grid.cell_values(cell)) {
+                    param_map.insert(param.name.clone(), value);
+                }
+                Some(cell)
+            }
+            None => {
+                let config = CONFIG.get().unwrap();
+                let has_defaults = config.parameters.iter().any(|p| p.default.is_some());
+                if has_defaults && !DEFAULTS_EVALUATED.swap(true, Ordering::Relaxed) {
+                    for param in &config.parameters {
+                        param_map.insert(param.name.clone(), default_value(param));
+                    }
+                    log!("Evaluating the default parameters first: {:?}", param_map);
+                    planned = true;
+                } else if let Some(point) = SEED_POINTS
+                    .get()
+                    .and_then(|points| points.lock().unwrap().pop_front())
+                {
+                    log!("Evaluating seed point {:?}", point);
+                    param_map = point;
+                    planned = true;
+                } else if let Some(point) = WARMUP_POINTS
+                    .get()
+                    .and_then(|points| points.lock().unwrap().pop_front())
+                {
+                    param_map = point;
+                    planned = true;
+                } else if PLANNED_SAMPLES.load(Ordering::Relaxed) > 0 {
+                    // Another runner is still evaluating one of the planned samples, and asking
+                    // the optimizers before it is told would search without it
+                    std::thread::sleep(Duration::from_millis(50));
+                    continue;
+                } else if let Some(values) = gaussian_process_ask(&mut rng, config) {
+                    for (param, value) in config.parameters.iter().zip(values) {
+                        param_map.insert(param.name.clone(), value);
+                    }
+                } else {
+                    let mut state = STATE.get().unwrap().lock().unwrap();
+                    for param in state.params.iter_mut() {
+                        let range = configured_range(config, &param.name);
+                        let value = clamp_param(param.optim.ask(&mut rng).unwrap(), &range);
+                        param_map.insert(param.name.clone(), value);
+                    }
+                }
+                None
+            }
+        };
+        let config = CONFIG.get().unwrap();
+        // The simulation only sees the rounded values, so they are what gets recorded and told to
+        // the optimizers. Grid cells can sit on the end of their range
+        for (name, value) in param_map.iter_mut() {
+            let range = match cell {
+                Some(_) => f64::NEG_INFINITY..f64::INFINITY,
+                None => configured_range(config, name),
+            };
+            *value = round_param(*value, config.positions_precision, &range);
+        }
+        let sample = NEXT_SAMPLE.fetch_add(1, Ordering::Relaxed);
+        let seeds = if config.scenario_seeds.is_empty() {
+            vec![rng.gen()]
+        } else {
+            config.scenario_seeds.clone()
+        };
+
+        //Run one simulation per seed, giving up on the sample if any of them fails
+        let mut simulations = Vec::new();
+        for seed in seeds {
+            let positions_file = temp_positions_file(paths);
+            let args = simulation_args(&positions_file, seed, &param_map, config);
+            let start = Instant::now();
+            let log_path = sim_log_path(config, &positions_file);
+            let outcome = run_binary(paths, &args, config, log_path.as_deref());
+            add_time(&SIMULATION_TIME, start);
+            let partial = match outcome {
+                Ok(RunOutcome::Finished) => false,
+                Ok(RunOutcome::TimedOut) if config.score_timed_out => {
+                    log!("Simulation timed out, scoring what it wrote so far");
+                    true
+                }
+                Ok(RunOutcome::TimedOut) => {
+                    log!("Simulation timed out");
+                    let _ = std::fs::remove_file(positions_file);
+                    break;
+                }
+                Ok(RunOutcome::OutOfMemory) => {
+                    log!("Simulation was killed by the memory limit");
+                    let _ = std::fs::remove_file(positions_file);
+                    break;
+                }
+                Err(err) => {
+                    log!("Error while running waf: {}", err);
+                    let _ = std::fs::remove_file(positions_file);
+                    break;
+                }
+            };
+            simulations.push(SeedSimulation {
+                seed,
+                positions_file,
+                partial,
+            });
+        }
+        if simulations.len() < config.scenario_seeds.len().max(1) {
+            for simulation in simulations {
+                let _ = std::fs::remove_file(simulation.positions_file);
+            }
+            record_sample(runner, false);
+            if planned {
+                PLANNED_SAMPLES.fetch_sub(1, Ordering::Relaxed);
+            }
+            continue;
+        }
+        let analysis = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            run_analysis(&simulations, &param_map, sample, runner)
+        }));
+        match analysis {
+            Ok(Ok(run)) => {
+                record_sample(runner, true);
+                if let (Some(sweep), Some(cell)) = (GRID.get(), cell) {
+                    sweep.complete(cell, &run);
+                }
+            }
+            Ok(Err(err)) => {
+                log!("Error while doing analysis: {}", err);
+                record_sample(runner, false);
+            }
+            Err(payload) => {
+                recover_from_panic(payload.as_ref(), &simulations, &param_map);
+                record_sample(runner, false);
+            }
+        }
+        if planned {
+            PLANNED_SAMPLES.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+    ACTIVE_RUNNERS.fetch_sub(1, Ordering::Relaxed);
+    if CONFIG.get().unwrap().verbose {
+        log!("Runner {} exiting cleanly", runner);
+    }
+}
+
+/// The environment variables `on_new_best` is run with
+fn new_best_env(
+    error: f64,
+    param_map: &IndexMap<String, f64>,
+    positions_file: &Path,
+) -> Vec<(String, String)> {
+    let mut env = vec![
+        ("OPTIMIZER_ERROR".to_owned(), error.to_string()),
+        (
+            "OPTIMIZER_PARAMS".to_owned(),
+            serde_json::to_string(param_map).unwrap(),
+        ),
+        (
+            "OPTIMIZER_POSITIONS_FILE".to_owned(),
+            positions_file.to_string_lossy().into_owned(),
+        ),
+    ];
+    for (name, value) in param_map {
+        env.push((
+            format!("OPTIMIZER_PARAM_{}", name.to_uppercase()),
+            value.to_string(),
+        ));
+    }
+    env
+}
+
+/// Starts the `on_new_best` command without waiting for it, so a slow hook doesn't hold up the
+/// runner that found the new best
+fn run_new_best_hook(command: &str, env: Vec<(String, String)>) {
+    let child = Command::new("bash")
+        .arg("-c")
+        .arg(command)
+        .envs(env)
+        .stdout(crate::util::child_stdout())
+        .spawn();
+    match child {
+        Ok(mut child) => {
+            std::thread::spawn(move || match child.wait() {
+                Ok(status) if !status.success() => {
+                    log!("on_new_best command failed with {}", status)
+                }
+                Err(err) => log!("Error while waiting for on_new_best command: {}", err),
+                Ok(_) => {}
+            });
+        }
+        Err(err) => log!("Failed to run on_new_best command: {}", err),
+    }
+}
+
+/// One line of `--ndjson` output describing a completed sample
+#[derive(serde::Serialize)]
+struct NdjsonRecord<'a> {
+    params: &'a IndexMap<String, f64>,
+    total: f64,
+    breakdown: &'a CostBreakdown,
+    timestamp: String,
+    /// The runner thread that evaluated the sample
+    runner: usize,
+}
+
+fn ndjson_record(run: &SimulationRun, runner: usize) -> String {
+    serde_json::to_string(&NdjsonRecord {
+        params: &run.parameters,
+        total: run.error,
+        breakdown: &run.breakdown,
+        timestamp: humantime::format_rfc3339_millis(run.time).to_string(),
+        runner,
+    })
+    .unwrap()
+}
+
+/// The index of the first of `breakdowns` whose total isn't a finite number
+fn first_non_finite(breakdowns: &[CostBreakdown]) -> Option<usize> {
+    breakdowns
+        .iter()
+        .position(|breakdown| !breakdown.total().is_finite())
+}
+
+/// The errors of a sample, one per seed simulation and reduced over the seeds
+struct SampleErrors {
+    /// The error with each seed, normalized if the fitness config asks for it
+    seeds: Vec<f64>,
+    /// The reduced error that is recorded in the results
+    error: f64,
+    /// The index of the seed with the highest error
+    worst: usize,
+}
+
+/// Scores the sample that `breakdowns` came from against `term_ranges`
+fn sample_errors(
+    term_ranges: &TermRanges,
+    config: &Config,
+    breakdowns: &[CostBreakdown],
+) -> SampleErrors {
+    let seeds: Vec<f64> = match config.fitness.normalization {
+        Normalization::Raw => breakdowns.iter().map(CostBreakdown::total).collect(),
+        Normalization::PopulationRange => breakdowns
+            .iter()
+            .map(|breakdown| term_ranges.normalized_total(breakdown, &config.fitness.term_weights))
+            .collect(),
+    };
+    let worst = (0..seeds.len())
+        .max_by(|a, b| seeds[*a].total_cmp(&seeds[*b]))
+        .unwrap();
+    SampleErrors {
+        error: config.seed_reducer.reduce(&seeds),
+        seeds,
+        worst,
+    }
+}
+
+/// The breakdown of each seed simulation of `run`. Backups from before they were all recorded
+/// only have the worst seed's
+fn seed_breakdowns(run: &SimulationRun) -> &[CostBreakdown] {
+    if run.seed_breakdowns.is_empty() {
+        std::slice::from_ref(&run.breakdown)
+    } else {
+        &run.seed_breakdowns
+    }
+}
+
+/// Scores every result again against the term ranges of all of them, so results that finished
+/// before a range last grew are on the same scale as later ones. Only
+/// `Normalization::PopulationRange` errors depend on the other results
+fn rescore(state: &mut StateImpl, config: &Config) {
+    if config.fitness.normalization != Normalization::PopulationRange {
+        return;
+    }
+    let mut term_ranges = TermRanges::default();
+    for run in &state.results {
+        for breakdown in seed_breakdowns(run) {
+            term_ranges.update(breakdown);
+        }
+    }
+    let mut error_quantiles = ErrorQuantiles::default();
+    for run in &mut state.results {
+        let breakdowns = seed_breakdowns(run).to_vec();
+        let errors = sample_errors(&term_ranges, config, &breakdowns);
+        run.error = errors.error;
+        run.breakdown = breakdowns[errors.worst].clone();
+        if !run.seed_breakdowns.is_empty() {
+            for (seed_error, error) in run.seed_errors.values_mut().zip(errors.seeds) {
+                *seed_error = error;
+            }
+        }
+        error_quantiles.add(run.error);
+    }
+    state.term_ranges = term_ranges;
+    state.error_quantiles = error_quantiles;
+}
+
+/// Replaces the optimizers with ones that are told every result, after `rescore` changed the
+/// errors the old ones were told
+fn retell_optimizers(state: &mut StateImpl, config: &Config) {
+    if gaussian_process_retell(&state.results) {
+        return;
+    }
+    state.params = seeded_parameters(config);
+    for run in &state.results {
+        for param in state.params.iter_mut() {
+            if let Some(value) = run.parameters.get(&param.name) {
+                param.optim.tell(*value, run.error).unwrap();
+            }
+        }
+    }
+}
+
+/// The lowest error of the results evaluated in this session
+fn session_lowest(results: &[SimulationRun]) -> f64 {
+    results
+        .iter()
+        .filter(|run| run.session == *SESSION.get().unwrap())
+        .map(|run| run.error)
+        .fold(f64::INFINITY, f64::min)
+}
+
+/// The result with the lowest error
+fn best_run(results: &[SimulationRun]) -> Option<&SimulationRun> {
+    results.iter().min_by(|a, b| a.error.total_cmp(&b.error))
+}
+
+/// Renames the best files saved in this session after the final error of their sample, since
+/// they were named after its error when it finished. Every file is moved aside first so that one
+/// taking another's old name doesn't overwrite it
+fn rename_best_files(results: &[SimulationRun], precision: Option<usize>) {
+    let files = match BEST_FILES.get() {
+        Some(files) => std::mem::take(&mut *files.lock().unwrap()),
+        None => return,
+    };
+    let mut moved = Vec::new();
+    for (sample, path) in files {
+        let run = results
+            .iter()
+            .find(|run| run.sample == sample && run.session == *SESSION.get().unwrap());
+        let run = match run {
+            Some(run) => run,
+            None => continue,
+        };
+        let aside = path.with_extension("csv.rescored");
+        match std::fs::rename(&path, &aside) {
+            Ok(()) => moved.push((run.error, aside)),
+            Err(err) => log!("Failed to rename {}: {}", path.display(), err),
+        }
+    }
+    for (error, aside) in moved {
+        let dest = aside.with_file_name(format!("{}.csv", format_value(error, precision)));
+        if let Err(err) = std::fs::rename(&aside, &dest) {
+            log!("Failed to rename {}: {}", aside.display(), err);
+        }
+    }
+}
+
+/// One simulation of a sample, run with one of its seeds
+struct SeedSimulation {
+    seed: usize,
+    positions_file: PathBuf,
+    /// If the simulation timed out and `positions_file` only has what it wrote so far
+    partial: bool,
+}
+
+/// Handles a sample where the simulation wrote the positions file `empty` without any positions,
+/// following `Config::empty_positions`
+fn empty_positions(config: &Config, simulations: &[SeedSimulation], empty: &Path) -> crate::Error {
+    match config.empty_positions {
+        EmptyPositionsPolicy::Fail => {
+            for simulation in simulations {
+                let _ = std::fs::remove_file(&simulation.positions_file);
+            }
+        }
+        EmptyPositionsPolicy::Stop => {
+            log!("Keeping {} for inspection", empty.display());
+            stop("a simulation wrote an empty positions file");
+        }
+    }
+    crate::Error::Simulation(format!(
+        "The simulation exited successfully but wrote no positions to {}",
+        empty.display()
+    ))
+}
+
+fn run_analysis(
+    simulations: &[SeedSimulation],
+    param_map: &IndexMap<String, f64>,
+    sample: usize,
+    runner: usize,
+) -> Result<SimulationRun, crate::Error> {
+    //let start = Instant::now();
+    let config = CONFIG.get().unwrap();
+    if let Some(empty) = simulations
+        .iter()
+        .map(|simulation| &simulation.positions_file)
+        .find(|path| !crate::util::has_positions(path).unwrap_or(true))
+    {
+        return Err(empty_positions(config, simulations, empty));
+    }
+    let mut breakdowns = Vec::new();
+    for simulation in simulations {
+        let pos_path = &simulation.positions_file;
+        let start = Instant::now();
+        let mut data = if simulation.partial {
+            let positions = String::from_utf8(std::fs::read(pos_path)?)?;
+            SimulationData::parse_partial(&positions, &config.parser)?
+        } else {
+            //Temp files are deleted right after, so there is no point in caching them
+            SimulationData::load(pos_path, &config.parser, false)?
+        };
+        add_time(&PARSE_TIME, start);
+        if let Err(err) = config.fitness.check_data(&data) {
+            // The sample is never scored, so its files won't be cleaned up after it
+            for simulation in simulations {
+                let _ = remove_scored_files(config, &simulation.positions_file);
+            }
+            return Err(err.into());
+        }
+        let start = Instant::now();
+        breakdowns.push(fitness::get_error(&mut data, &config.fitness));
+        add_time(&FITNESS_TIME, start);
+    }
+    if let Some(index) = first_non_finite(&breakdowns) {
+        if config.fail_on_nan_fitness {
+            let pos_path = &simulations[index].positions_file;
+            log!(
+                "Got a fitness of {} for params {:?}, keeping {} for inspection",
+                breakdowns[index].total(),
+                param_map,
+                pos_path.display()
+            );
+            log!("  breakdown: {:?}", breakdowns[index]);
+            stop("a sample's fitness wasn't finite");
+            return Err(format!("Non-finite fitness in {}", pos_path.display()).into());
+        }
+        // The optimizers can't be told a NaN, so the sample fails like one that didn't run
+        let err = crate::Error::Simulation(format!(
+            "Got a fitness of {} for params {:?}",
+            breakdowns[index].total(),
+            param_map
+        ));
+        for simulation in simulations {
+            let _ = remove_scored_files(config, &simulation.positions_file);
+        }
+        return Err(err);
+    }
+    let (new_best, worst, run) = {
+        let mut state = STATE.get().unwrap().lock().unwrap();
+        let mut ranges_grew = false;
+        for breakdown in &breakdowns {
+            ranges_grew |= state.term_ranges.update(breakdown);
+        }
+        let SampleErrors {
+            seeds: errors,
+            error,
+            worst,
+        } = sample_errors(&state.term_ranges, config, &breakdowns);
+        let (seed_errors, seed_breakdowns) = if config.scenario_seeds.is_empty() {
+            (IndexMap::new(), Vec::new())
+        } else {
+            let seeds = simulations.iter().map(|simulation| simulation.seed);
+            (seeds.zip(errors).collect(), breakdowns.clone())
+        };
+        state.results.push(SimulationRun {
+            parameters: param_map.clone(),
+            time: SystemTime::now(),
+            error,
+            breakdown: breakdowns[worst].clone(),
+            seed_errors,
+            seed_breakdowns,
+            sample,
+            session: SESSION.get().unwrap().clone(),
+        });
+        let rescored =
+            ranges_grew && config.fitness.normalization == Normalization::PopulationRange;
+        if rescored {
+            // The earlier results were normalized against narrower ranges, so the best of them
+            // is found again on the new scale
+            rescore(&mut state, config);
+            let earlier = &state.results[..state.results.len() - 1];
+            LOWEST_ERROR.store(
+                session_lowest(earlier).min(LOWEST_ERROR_START),
+                Ordering::Relaxed,
+            );
+        } else {
+            state.error_quantiles.add(error);
+        }
+        // Grid sweeps don't use the optimizers, and the grid includes the end of the range which
+        // they reject
+        if GRID.get().is_none() {
+            if rescored {
+                retell_optimizers(&mut state, config);
+            } else if !gaussian_process_tell(param_map, error) {
+                for param in state.params.iter_mut() {
+                    let value = param_map.get(&param.name).unwrap();
+                    param.optim.tell(*value, error).unwrap();
+                }
+            }
+        }
+        if config.ndjson {
+            println!("{}", ndjson_record(state.results.last().unwrap(), runner));
+        }
+        let simulations = state.results.len();
+        let new_best = error < LOWEST_ERROR.load(Ordering::Relaxed);
+        if new_best {
+            LOWEST_ERROR.store(error, Ordering::Relaxed);
+            LAST_IMPROVEMENT.store(simulations, Ordering::Relaxed);
+        }
+        if let Some(patience) = config.patience {
+            let last_improvement = LAST_IMPROVEMENT.load(Ordering::Relaxed);
+            if is_stalled(simulations, last_improvement, patience) {
+                log!("No improvement in the last {} evaluations", patience);
+                stop(format!(
+                    "the best error hasn't improved since evaluation {}",
+                    last_improvement
+                ));
+            }
+        }
+        // Grid sweeps stop once every cell has been evaluated instead
+        if simulations == MAX_SIMULATIONS && GRID.get().is_none() {
+            log!("Exiting after {}", MAX_SIMULATIONS);
+            stop(format!("reached {} simulations", MAX_SIMULATIONS));
+        } else {
+            log!("{}", status_line(&state));
+        }
+        (new_best, worst, state.results.last().unwrap().clone())
+    };
+    if new_best {
+        let error = run.error;
+        let src = &simulations[worst].positions_file;
+        let mut dest = PathBuf::from(out_file("best"));
+        let _ = std::fs::create_dir_all(&dest);
+        dest.push(format!(
+            "{}.csv",
+            format_value(error, config.positions_precision)
+        ));
+        std::fs::copy(src, &dest).unwrap();
+        BEST_FILES
+            .get_or_init(Default::default)
+            .lock()
+            .unwrap()
+            .push((sample, dest.clone()));
+        log!("  got best error: {} for params: {:?}", error, param_map);
+        log!("  breakdown: {:?}", breakdowns[worst]);
+        if let Some(command) = &config.on_new_best {
+            run_new_best_hook(command, new_best_env(error, param_map, &dest));
+        }
+    }
+
+    for simulation in simulations {
+        let pos_path = &simulation.positions_file;
+        if let Some(err) = remove_scored_files(config, pos_path).err() {
+            log!(
+                "failed to clean up temp positions file: {} - {}",
+                pos_path.to_str().unwrap(),
+                err
+            );
+        }
+    }
+    Ok(run)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ParameterGroup;
+
+    #[test]
+    fn panic_messages() {
+        let payload = std::panic::catch_unwind(|| panic!("bad shape")).unwrap_err();
+        assert_eq!(panic_message(payload.as_ref()), "bad shape");
+        let payload = std::panic::catch_unwind(|| panic!("{} columns", 3)).unwrap_err();
+        assert_eq!(panic_message(payload.as_ref()), "3 columns");
+        let payload = std::panic::catch_unwind(|| std::panic::panic_any(7)).unwrap_err();
+        assert_eq!(panic_message(payload.as_ref()), "<no message>");
+    }
+
+    #[test]
+    fn grouped_args() {
+        let mut param_map = IndexMap::new();
+        param_map.insert("a".to_owned(), 1.5);
+        param_map.insert("b".to_owned(), 2.0);
+        param_map.insert("c".to_owned(), 3.25);
+        let mut config = Config {
+            sim_duration: 240.0,
+            parameter_groups: vec![ParameterGroup {
+                arg: "gains".to_owned(),
+                params: vec!["c".to_owned(), "a".to_owned()],
+                separator: ":".to_owned(),
+            }],
+            ..Config::default()
+        };
+        let args = simulation_args(Path::new("/tmp/p.csv"), 7, &param_map, &config);
+        let extra = &args[BASE_ARGUMENTS.len()..];
+        assert_eq!(
+            extra,
+            &[
+                "--duration=240",
+                "--positionsFile=/tmp/p.csv",
+                "--seed=7",
+                "--b=2",
+                "--gains=3.25:1.5"
+            ]
+        );
+
+        config.positions_precision = Some(1);
+        let args = simulation_args(Path::new("/tmp/p.csv"), 7, &param_map, &config);
+        assert_eq!(
+            &args[BASE_ARGUMENTS.len() + 3..],
+            &["--b=2.0", "--gains=3.2:1.5"]
+        );
+    }
+
+    #[test]
+    fn value_precision() {
+        assert_eq!(format_value(1.0 / 3.0, None), (1.0f64 / 3.0).to_string());
+        assert_eq!(format_value(1.0 / 3.0, Some(3)), "0.333");
+        assert_eq!(format_value(2.5, Some(0)), "2");
+        assert_eq!(format_value(12.0, None), "12");
+    }
+
+    #[test]
+    fn rounded_params() {
+        assert_eq!(round_param(1.0 / 3.0, None, &(0.0..18.0)), 1.0 / 3.0);
+        assert_eq!(round_param(1.0 / 3.0, Some(2), &(0.0..18.0)), 0.33);
+        //The end of the range is exclusive
+        assert_eq!(round_param(17.999, Some(2), &(0.0..18.0)), 17.99);
+        assert_eq!(round_param(0.4, Some(0), &(0.6..2.0)), 1.0);
+        //No value with one decimal place fits
+        assert_eq!(round_param(0.52, Some(1), &(0.51..0.56)), 0.52);
+    }
+
+    #[test]
+    fn quoted_command() {
+        let paths = Ns3Paths {
+            working_dir: PathBuf::from("/ns3"),
+            binary: PathBuf::from("/ns3/build/sim"),
+            lib_dir: PathBuf::from("/ns3/build/lib dir"),
+        };
+        let args = ["--a=1.5".to_owned(), "--name=it's".to_owned()];
+        assert_eq!(
+            shell_command(&paths, &[], &args),
+            "cd /ns3 && LD_LIBRARY_PATH='/ns3/build/lib dir' /ns3/build/sim --a=1.5 '--name=it'\\''s'"
+        );
+        let wrapper = ["taskset".to_owned(), "-c".to_owned(), "0-3".to_owned()];
+        assert_eq!(
+            shell_command(&paths, &wrapper, &args[..1]),
+            "cd /ns3 && LD_LIBRARY_PATH='/ns3/build/lib dir' taskset -c 0-3 /ns3/build/sim --a=1.5"
+        );
+    }
+
+    #[test]
+    fn deleted_working_dir() {
+        let dir = std::env::temp_dir().join(format!("deleted-working-dir-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let paths = Ns3Paths {
+            working_dir: dir.clone(),
+            binary: PathBuf::from("/bin/true"),
+            lib_dir: dir.clone(),
+        };
+        std::fs::remove_dir(&dir).unwrap();
+
+        let err = run_binary(&paths, &[], &Config::default(), None).unwrap_err();
+        assert!(err.to_string().contains("working directory"), "{}", err);
+    }
+
+    #[test]
+    fn simulation_logs() {
+        let dir = std::env::temp_dir().join(format!("simulation-logs-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let paths = Ns3Paths {
+            working_dir: dir.clone(),
+            binary: PathBuf::from("/bin/echo"),
+            lib_dir: dir.clone(),
+        };
+        let config = Config {
+            sim_log_dir: Some(dir.join("logs")),
+            ..Config::default()
+        };
+        let positions_file = dir.join("abc.csv");
+        let log_path = sim_log_path(&config, &positions_file).unwrap();
+        assert_eq!(log_path, dir.join("logs/abc.csv.log"));
+
+        let outcome = run_binary(&paths, &["hello".to_owned()], &config, Some(&log_path));
+        assert!(matches!(outcome, Ok(RunOutcome::Finished)));
+        assert_eq!(std::fs::read_to_string(&log_path).unwrap(), "hello\n");
+
+        std::fs::write(&positions_file, "").unwrap();
+        remove_scored_files(&config, &positions_file).unwrap();
+        assert!(!log_path.exists() && !positions_file.exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn parameter_sets() {
+        let config = Config::default();
+        let sets = parse_parameter_sets("r, a\n1.5, 2\n\n3,4\n", &config).unwrap();
+        assert_eq!(sets.len(), 2);
+        assert_eq!(sets[0].params["a"], 2.0);
+        assert_eq!(sets[0].params["r"], 1.5);
+        assert_eq!(sets[0].seed, None);
+        assert_eq!(sets[1].params["a"], 4.0);
+
+        let sets = parse_parameter_sets("a,r,seed\n1,2,42\n", &config).unwrap();
+        assert_eq!(sets[0].seed, Some(42));
+
+        assert!(parse_parameter_sets("a\n1\n", &config).is_err());
+        assert!(parse_parameter_sets("a,r,b\n1,2,3\n", &config).is_err());
+        assert!(parse_parameter_sets("a,r\n1\n", &config).is_err());
+        assert!(parse_parameter_sets("a,r\n1,x\n", &config).is_err());
+    }
+
+    #[test]
+    fn seed_points() {
+        let dir = std::env::temp_dir().join(format!("seed-points-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("points.csv");
+        let mut config = Config::default();
+        config.parameters[1].range = Some((0.0, 10.0));
+
+        std::fs::write(&path, "r,a,seed\n2,1.5,4\n10,18,5\n").unwrap();
+        let points = load_seed_points(&path, &config).unwrap();
+        assert_eq!(points.len(), 2);
+        assert_eq!(points[0].keys().collect::<Vec<_>>(), ["a", "r"]);
+        assert_eq!((points[0]["a"], points[0]["r"]), (1.5, 2.0));
+        // The end of each range is moved just inside it
+        assert!(points[1]["a"] < 18.0 && points[1]["r"] < 10.0);
+
+        std::fs::write(&path, "a,r\n1,11\n").unwrap();
+        let err = load_seed_points(&path, &config).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Seed point 1 has r = 11, outside its range 0..10"
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn warmup_samples() {
+        let mut config = Config::default();
+        config.parameters[1].range = Some((0.0, 10.0));
+        config.warmup_samples = 16;
+        let points = warmup_points(&config, &mut rand::thread_rng());
+        assert_eq!(points.len(), 16);
+        for point in &points {
+            assert_eq!(point.keys().collect::<Vec<_>>(), ["a", "r"]);
+            assert!((PARAM_MIN..PARAM_MAX).contains(&point["a"]));
+            assert!((0.0..10.0).contains(&point["r"]));
+        }
+        // A Sobol sample of 16 has one value in each sixteenth of every range
+        let mut slices: Vec<usize> = points.iter().map(|p| (p["r"] * 1.6) as usize).collect();
+        slices.sort_unstable();
+        assert_eq!(slices, (0..16).collect::<Vec<_>>());
+
+        config.parameters = (0..11)
+            .map(|i| crate::config::ParameterConfig::new(&format!("p{}", i)))
+            .collect();
+        assert!(config.validate().is_err());
+        config.warmup_sampler = crate::config::WarmupSampler::LatinHypercube;
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn past_results() {
+        let mut config = Config::default();
+        config
+            .parameters
+            .push(crate::config::ParameterConfig::new("g"));
+        let csv = "a,r,fitness,p_mad_cost,time\n1.5,2,30,1,x\n3,4,NaN,0,x\n5,6,12.5,0,x\n";
+        let past = parse_past_results(csv, &config).unwrap();
+        assert_eq!(past.len(), 2);
+        assert_eq!(
+            past[1].params,
+            parse_parameter_assignments("a=5,r=6", &Config::default()).unwrap()
+        );
+        assert_eq!(past[1].error, 12.5);
+
+        let mut state = StateImpl {
+            params: ["a", "r", "g"]
+                .iter()
+                .map(|name| Parameter {
+                    name: (*name).to_owned(),
+                    optim: optim_new(),
+                })
+                .collect(),
+            results: Vec::new(),
+            term_ranges: TermRanges::default(),
+            error_quantiles: ErrorQuantiles::default(),
+        };
+        let path = std::env::temp_dir().join(format!("past-results-{}.csv", std::process::id()));
+        std::fs::write(&path, csv).unwrap();
+        warm_start(&mut state, &config, path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert!(parse_past_results("a,b,fitness\n1,2,3\n", &config).is_err());
+        assert!(parse_past_results("a,r\n1,2\n", &config).is_err());
+        assert!(parse_past_results("a,r,fitness\n1,2\n", &config).is_err());
+    }
+
+    #[test]
+    fn warm_start_limits() {
+        let past = || -> Vec<PastResult> {
+            [30.0, 12.5, 50.0, 14.0, 20.0]
+                .iter()
+                .map(|error| PastResult {
+                    params: IndexMap::new(),
+                    error: *error,
+                })
+                .collect()
+        };
+        let errors = |limits: WarmStartConfig| -> Vec<f64> {
+            select_past_results(past(), &limits)
+                .iter()
+                .map(|result| result.error)
+                .collect()
+        };
+
+        assert_eq!(errors(WarmStartConfig::default()).len(), 5);
+        let recent = WarmStartConfig {
+            recent: Some(3),
+            ..WarmStartConfig::default()
+        };
+        assert_eq!(errors(recent), vec![50.0, 14.0, 20.0]);
+        let band = WarmStartConfig {
+            error_band: Some(5.0),
+            ..WarmStartConfig::default()
+        };
+        assert_eq!(errors(band), vec![12.5, 14.0]);
+        let both = WarmStartConfig {
+            recent: Some(3),
+            error_band: Some(10.0),
+        };
+        assert_eq!(errors(both), vec![14.0, 20.0]);
+    }
+
+    #[test]
+    fn suggested_parameters() {
+        let csv = "a,r,fitness\n1.5,2,30\n5,6,12.5\n";
+        let path = std::env::temp_dir().join(format!("suggest-{}.csv", std::process::id()));
+        std::fs::write(&path, csv).unwrap();
+        let suggested = suggestions(&Config::default(), path.to_str().unwrap(), 5).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(suggested.len(), 5);
+        for params in suggested {
+            assert_eq!(params.keys().collect::<Vec<_>>(), ["a", "r"]);
+            assert!(params
+                .values()
+                .all(|value| (PARAM_MIN..PARAM_MAX).contains(value)));
+        }
+    }
+
+    #[test]
+    fn parameter_assignments() {
+        let config = Config::default();
+        let params = parse_parameter_assignments("r=2, a=1.5", &config).unwrap();
+        assert_eq!(params["a"], 1.5);
+        assert_eq!(params["r"], 2.0);
+
+        assert!(parse_parameter_assignments("a=1.5", &config).is_err());
+        assert!(parse_parameter_assignments("a=1.5,r=2,b=3", &config).is_err());
+        for bad in ["a=1.5,r", "a=x,r=2", "a=1,r=2,q=3", "a=1"] {
+            assert!(matches!(
+                parse_parameter_assignments(bad, &config),
+                Err(crate::Error::Config(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn fitness_grid() {
+        let run = |a: f64, r: f64, error: f64| SimulationRun {
+            parameters: vec![("a".to_owned(), a), ("r".to_owned(), r)]
+                .into_iter()
+                .collect(),
+            error,
+            breakdown: CostBreakdown::default(),
+            seed_errors: IndexMap::new(),
+            seed_breakdowns: Vec::new(),
+            time: SystemTime::UNIX_EPOCH,
+            sample: 0,
+            session: String::new(),
+        };
+        let state = StateImpl {
+            params: Vec::new(),
+            results: vec![
+                run(0.0, 0.0, 4.0),
+                run(1.0, 0.5, 2.0),
+                run(4.0, 0.0, 10.0),
+                run(3.0, 4.0, 1.0),
+            ],
+            term_ranges: TermRanges::default(),
+            error_quantiles: ErrorQuantiles::default(),
+        };
+        let csv = fitness_grid_csv(&state, 2, None, None).unwrap();
+        assert_eq!(
+            csv.lines().collect::<Vec<_>>(),
+            vec![
+                "r,a,sample_count,mean_fitness,min_fitness",
+                "1,1,2,3,2",
+                "3,1,0,,",
+                "1,3,1,10,10",
+                "3,3,1,1,1",
+            ]
+        );
+
+        // Two sessions over the same configured ranges add up to the grid of all their runs
+        let mut config = Config::default();
+        config.parameters[0].range = Some((0.0, 4.0));
+        config.parameters[1].range = Some((0.0, 4.0));
+        let session = |results: &[SimulationRun]| StateImpl {
+            params: Vec::new(),
+            results: results
+                .iter()
+                .map(|result| run(result.parameters["a"], result.parameters["r"], result.error))
+                .collect(),
+            term_ranges: TermRanges::default(),
+            error_quantiles: ErrorQuantiles::default(),
+        };
+        let first =
+            fitness_grid_csv(&session(&state.results[..2]), 2, Some(&config), None).unwrap();
+        let both = fitness_grid_csv(
+            &session(&state.results[2..]),
+            2,
+            Some(&config),
+            Some(&first),
+        )
+        .unwrap();
+        assert_eq!(both, csv);
+
+        let three = fitness_grid_csv(&state, 3, Some(&config), None).unwrap();
+        assert!(fitness_grid_csv(&state, 2, Some(&config), Some(&three)).is_err());
+        config.parameters[0].range = Some((0.0, 8.0));
+        assert!(fitness_grid_csv(&state, 2, Some(&config), Some(&first)).is_err());
+    }
+
+    #[test]
+    fn saved_grid_results() {
+        let path = std::env::temp_dir().join(format!("grid-results-{}.ndjson", std::process::id()));
+        assert!(load_grid_results(&path).unwrap().is_empty());
+
+        let run = |parameters: Vec<(String, f64)>, error: f64| SimulationRun {
+            parameters: parameters.into_iter().collect(),
+            error,
+            breakdown: CostBreakdown::default(),
+            seed_errors: IndexMap::new(),
+            seed_breakdowns: Vec::new(),
+            time: SystemTime::UNIX_EPOCH,
+            sample: 0,
+            session: String::new(),
+        };
+        let runs = [
+            run(vec![("a".to_owned(), 1.0), ("r".to_owned(), 2.0)], 3.0),
+            run(Vec::new(), 4.0),
+        ];
+        let lines: Vec<String> = runs
+            .iter()
+            .map(|run| serde_json::to_string(run).unwrap())
+            .collect();
+        // A crash can leave the last line unfinished
+        let text = format!("{}\n{}\n{}", lines[0], lines[1], &lines[0][..10]);
+        std::fs::write(&path, text).unwrap();
+        let runs = load_grid_results(&path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[0].parameters["r"], 2.0);
+        assert_eq!(runs[1].error, 4.0);
+    }
+
+    #[test]
+    fn accumulated_grid_sessions() {
+        let dir = std::env::temp_dir().join(format!("grid-sessions-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut config = Config::default();
+        config.parameters[0].range = Some((0.0, 4.0));
+        config.parameters[1].range = Some((0.0, 4.0));
+        config.plot.export_grid = Some(dir.join("grid.csv"));
+        config.plot.accumulate_grid = true;
+        config.plot.grid_cells = 2;
+        let in_session = |a: f64, session: &str| SimulationRun {
+            parameters: vec![("a".to_owned(), a), ("r".to_owned(), 1.0)]
+                .into_iter()
+                .collect(),
+            error: a,
+            breakdown: CostBreakdown::default(),
+            seed_errors: IndexMap::new(),
+            seed_breakdowns: Vec::new(),
+            time: SystemTime::UNIX_EPOCH,
+            sample: 0,
+            session: session.to_owned(),
+        };
+        let total = || -> usize {
+            std::fs::read_to_string(dir.join("grid.csv"))
+                .unwrap()
+                .lines()
+                .skip(1)
+                .map(|line| line.split(',').nth(2).unwrap().parse::<usize>().unwrap())
+                .sum()
+        };
+
+        let first = StateImpl {
+            params: Vec::new(),
+            results: vec![in_session(1.0, "s1"), in_session(3.0, "s1")],
+            term_ranges: TermRanges::default(),
+            error_quantiles: ErrorQuantiles::default(),
+        };
+        write_fitness_grid(&first, &config).unwrap();
+        assert_eq!(total(), 2);
+        // Re-exporting the same session changes nothing
+        write_fitness_grid(&first, &config).unwrap();
+        assert_eq!(total(), 2);
+
+        // Only the new session of a merged backup is added
+        let mut merged = first;
+        merged.results.push(in_session(2.0, "s2"));
+        write_fitness_grid(&merged, &config).unwrap();
+        assert_eq!(total(), 3);
+        assert_eq!(
+            std::fs::read_to_string(dir.join("grid.csv.sessions")).unwrap(),
+            "s1\ns2\n"
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn results_table() {
+        let breakdown = CostBreakdown {
+            p_mad_cost: 1.0,
+            velocity_cost: 2.5,
+            recovery_stable_time: Some(3.0),
+            initial_uavs: 4,
+            surviving_uavs: 3,
+            ..CostBreakdown::default()
+        };
+        let run = |a: f64, breakdown: CostBreakdown| SimulationRun {
+            parameters: vec![("r".to_owned(), 2.0), ("a".to_owned(), a)]
+                .into_iter()
+                .collect(),
+            error: breakdown.total(),
+            breakdown,
+            seed_errors: IndexMap::new(),
+            seed_breakdowns: Vec::new(),
+            time: SystemTime::UNIX_EPOCH + Duration::from_secs(86400),
+            sample: 0,
+            session: String::new(),
+        };
+        let state = StateImpl {
+            params: ["a", "r"]
+                .iter()
+                .map(|name| Parameter {
+                    name: (*name).to_owned(),
+                    optim: optim_new(),
+                })
+                .collect(),
+            results: vec![run(1.5, breakdown), run(4.0, CostBreakdown::default())],
+            term_ranges: TermRanges::default(),
+            error_quantiles: ErrorQuantiles::default(),
+        };
+        let csv = results_csv(&state);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                "a,r,fitness,p_mad_cost,central_distance_cost,velocity_cost,recovery_cost,\
+                 dropout_cost,control_effort_cost,uav_count_cost,formation_cost,target_distance,\
+                 stable_time,recovery_stable_time,initial_uavs,surviving_uavs,\
+                 central_outlier_timesteps,time",
+                "1.5,2,3.5,1,0,2.5,0,0,0,0,0,0,0,3,4,3,0,1970-01-02T00:00:00Z",
+                "4,2,0,0,0,0,0,0,0,0,0,0,0,,0,0,0,1970-01-02T00:00:00Z",
+            ]
+        );
+    }
+
+    #[test]
+    fn trajectory_order() {
+        let run = |a: f64, sample: usize| SimulationRun {
+            parameters: vec![("a".to_owned(), a), ("r".to_owned(), 2.0 * a)]
+                .into_iter()
+                .collect(),
+            error: 1.0,
+            breakdown: CostBreakdown::default(),
+            seed_errors: IndexMap::new(),
+            seed_breakdowns: Vec::new(),
+            time: SystemTime::UNIX_EPOCH,
+            sample,
+            session: String::new(),
+        };
+        let mut state = StateImpl {
+            params: Vec::new(),
+            results: vec![run(1.0, 1), run(0.0, 0), run(3.0, 3), run(2.0, 2)],
+            term_ranges: TermRanges::default(),
+            error_quantiles: ErrorQuantiles::default(),
+        };
+        // The hot/cold graph puts r on the x axis
+        assert_eq!(
+            trajectory(&state),
+            vec![(0.0, 0.0), (2.0, 1.0), (4.0, 2.0), (6.0, 3.0)]
+        );
+
+        for run in state.results.iter_mut() {
+            run.sample = 0;
+        }
+        assert_eq!(trajectory(&state)[0], (2.0, 1.0));
+    }
+
+    #[test]
+    fn summary_table_rows() {
+        let run = |a: f64, error: f64| SimulationRun {
+            parameters: vec![("a".to_owned(), a)].into_iter().collect(),
+            error,
+            breakdown: CostBreakdown {
+                velocity_cost: error,
+                ..CostBreakdown::default()
+            },
+            seed_errors: IndexMap::new(),
+            seed_breakdowns: Vec::new(),
+            time: SystemTime::UNIX_EPOCH,
+            sample: 0,
+            session: String::new(),
+        };
+        let state = StateImpl {
+            params: vec![Parameter {
+                name: "a".to_owned(),
+                optim: optim_new(),
+            }],
+            results: vec![
+                run(1.0, 30.0),
+                run(2.0, f64::NAN),
+                run(3.0, 4.5),
+                run(4.0, 100.0),
+            ],
+            term_ranges: TermRanges::default(),
+            error_quantiles: ErrorQuantiles::default(),
+        };
+        let table = summary_table(&state, 2);
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].trim_start().starts_with("a  fitness  p_mad_cost"));
+        assert!(lines[1].trim_start().starts_with("3.000    4.500"));
+        assert!(lines[2].trim_start().starts_with("1.000   30.000"));
+        assert!(lines.iter().all(|line| line.len() == lines[0].len()));
+
+        assert_eq!(summary_table(&state, 10).lines().count(), 5);
+    }
+
+    #[test]
+    fn merged_backups() {
+        let backup = |names: &[&str], finished: &[u64]| StateImpl {
+            params: names
+                .iter()
+                .map(|name| Parameter {
+                    name: (*name).to_owned(),
+                    optim: optim_new(),
+                })
+                .collect(),
+            results: finished
+                .iter()
+                .enumerate()
+                .map(|(sample, secs)| SimulationRun {
+                    parameters: names.iter().map(|name| ((*name).to_owned(), 1.0)).collect(),
+                    error: *secs as f64,
+                    breakdown: CostBreakdown::default(),
+                    seed_errors: IndexMap::new(),
+                    seed_breakdowns: Vec::new(),
+                    time: SystemTime::UNIX_EPOCH + Duration::from_secs(*secs),
+                    sample,
+                    session: String::new(),
+                })
+                .collect(),
+            term_ranges: TermRanges::default(),
+            error_quantiles: ErrorQuantiles::default(),
+        };
+        let merged = merge_backups(vec![
+            ("a.json".to_owned(), backup(&["a", "r"], &[3, 5])),
+            ("b.json".to_owned(), backup(&["a", "r"], &[1, 4])),
+        ])
+        .unwrap();
+        let errors: Vec<f64> = merged.results.iter().map(|run| run.error).collect();
+        assert_eq!(errors, vec![1.0, 3.0, 4.0, 5.0]);
+        let samples: Vec<usize> = merged.results.iter().map(|run| run.sample).collect();
+        assert_eq!(samples, vec![2, 0, 3, 1]);
+        assert_eq!(next_sample(&merged.results), 4);
+        assert_eq!(next_sample(&[]), 0);
+
+        let err = merge_backups(vec![
+            ("a.json".to_owned(), backup(&["a", "r"], &[3])),
+            ("b.json".to_owned(), backup(&["a", "b"], &[1])),
+        ])
+        .err()
+        .unwrap();
+        assert!(err.to_string().contains("b.json"));
+        assert!(merge_backups(Vec::new()).is_err());
+    }
+
+    #[test]
+    fn append_results() {
+        let state = |names: &[&str]| StateImpl {
+            params: names
+                .iter()
+                .map(|name| Parameter {
+                    name: (*name).to_owned(),
+                    optim: optim_new(),
+                })
+                .collect(),
+            results: vec![SimulationRun {
+                parameters: names.iter().map(|name| ((*name).to_owned(), 1.0)).collect(),
+                error: 2.0,
+                breakdown: CostBreakdown::default(),
+                seed_errors: IndexMap::new(),
+                seed_breakdowns: Vec::new(),
+                time: SystemTime::UNIX_EPOCH,
+                sample: 0,
+                session: String::new(),
+            }],
+            term_ranges: TermRanges::default(),
+            error_quantiles: ErrorQuantiles::default(),
+        };
+        let dir = std::env::temp_dir().join(format!("append-results-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let stem = dir.join("results");
+        let stem = stem.to_str().unwrap();
+
+        write_results(&state(&["a", "r"]), stem, true).unwrap();
+        write_results(&state(&["a", "r"]), stem, true).unwrap();
+        let csv = std::fs::read_to_string(format!("{}.csv", stem)).unwrap();
+        assert_eq!(csv.lines().count(), 3);
+        let metadata = std::fs::read_to_string(format!("{}.meta.json", stem)).unwrap();
+        let metadata: ResultsMetadata = serde_json::from_str(&metadata).unwrap();
+        assert_eq!(metadata.runs, 2);
+
+        assert!(write_results(&state(&["a"]), stem, true).is_err());
+        assert_eq!(
+            std::fs::read_to_string(format!("{}.csv", stem)).unwrap(),
+            csv
+        );
+        write_results(&state(&["a"]), stem, false).unwrap();
+        let csv = std::fs::read_to_string(format!("{}.csv", stem)).unwrap();
+        assert_eq!(csv.lines().count(), 2);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn ndjson_line() {
+        let run = SimulationRun {
+            parameters: vec![("r".to_owned(), 2.0), ("a".to_owned(), 1.5)]
+                .into_iter()
+                .collect(),
+            error: 2.5,
+            breakdown: CostBreakdown {
+                velocity_cost: 2.5,
+                ..CostBreakdown::default()
+            },
+            seed_errors: IndexMap::new(),
+            seed_breakdowns: Vec::new(),
+            time: SystemTime::UNIX_EPOCH + Duration::from_millis(86_400_250),
+            sample: 0,
+            session: String::new(),
+        };
+        let line = ndjson_record(&run, 3);
+        assert!(!line.contains('\n'));
+        assert!(line.starts_with(r#"{"params":{"r":2.0,"a":1.5},"total":2.5,"breakdown":{"#));
+        assert!(line.ends_with(r#""timestamp":"1970-01-02T00:00:00.250Z","runner":3}"#));
+    }
+
+    #[test]
+    fn new_best_variables() {
+        let mut param_map = IndexMap::new();
+        param_map.insert("a".to_owned(), 1.5);
+        param_map.insert("r".to_owned(), 2.0);
+        let env = new_best_env(12.5, &param_map, Path::new("out/12.5.csv"));
+        let env: Vec<(&str, &str)> = env
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect();
+        assert_eq!(
+            env,
+            vec![
+                ("OPTIMIZER_ERROR", "12.5"),
+                ("OPTIMIZER_PARAMS", r#"{"a":1.5,"r":2.0}"#),
+                ("OPTIMIZER_POSITIONS_FILE", "out/12.5.csv"),
+                ("OPTIMIZER_PARAM_A", "1.5"),
+                ("OPTIMIZER_PARAM_R", "2"),
+            ]
+        );
+    }
+
+    #[test]
+    fn color_bar_ticks() {
+        assert_eq!(tick_label(0.0), "0.00");
+        assert_eq!(tick_label(1403.226), "1403.23");
+        assert_eq!(tick_label(0.000224), "2.24e-4");
+        assert_eq!(tick_label(25000.0), "2.50e4");
+        assert_eq!(scatter_color(1), RGBColor(1, 255, 50));
+        assert_eq!(scatter_color(256), scatter_color(255));
+    }
+
+    #[test]
+    fn fixed_color_range() {
+        let range = (1000.0, 2000.0);
+        assert_eq!(fixed_color_index(1000.0, range), 0);
+        assert_eq!(fixed_color_index(1500.0, range), 127);
+        assert_eq!(fixed_color_index(2000.0, range), 255);
+        assert_eq!(fixed_color_index(-5.0, range), 0);
+        assert_eq!(fixed_color_index(1e9, range), 255);
+    }
+
+    #[test]
+    fn profile_report() {
+        let times = EvaluationTimes {
+            simulation: Duration::from_secs(9),
+            parse: Duration::from_millis(750),
+            fitness: Duration::from_millis(250),
+        };
+        assert_eq!(
+            times.report(Duration::from_secs(3)),
+            "Time spent over 3.0s of wall time:\n  \
+             simulation       9.00s  90.0%\n  \
+             parsing          0.75s   7.5%\n  \
+             fitness          0.25s   2.5%"
+        );
+        let empty = EvaluationTimes {
+            simulation: Duration::ZERO,
+            parse: Duration::ZERO,
+            fitness: Duration::ZERO,
+        };
+        assert!(empty.report(Duration::ZERO).ends_with("0.00s   0.0%"));
+    }
+
+    #[test]
+    fn clamped_params() {
+        let full = PARAM_MIN..PARAM_MAX;
+        assert_eq!(clamp_param(3.5, &full), 3.5);
+        assert_eq!(clamp_param(PARAM_MIN, &full), PARAM_MIN);
+        assert_eq!(clamp_param(PARAM_MIN - 1e-12, &full), PARAM_MIN);
+        assert_eq!(clamp_param(3.5, &(9.0..18.0)), 9.0);
+        for value in [PARAM_MAX, PARAM_MAX + 1e-12, 1e9] {
+            let clamped = clamp_param(value, &full);
+            assert!(clamped < PARAM_MAX && clamped > PARAM_MAX - 1e-9);
+            optim_new().tell(clamped, 1.0).unwrap();
+        }
+    }
+
+    #[test]
+    fn default_values() {
+        let mut param = ParameterConfig::new("a");
+        assert_eq!(default_value(&param), DEFAULT_PARAM_VALUE);
+        param.default = Some(4.5);
+        assert_eq!(default_value(&param), 4.5);
+        param.range = Some((9.0, 18.0));
+        param.default = None;
+        assert_eq!(default_value(&param), 9.0);
+        param.default = Some(18.0);
+        assert!(default_value(&param) < 18.0);
+    }
+
+    #[test]
+    fn non_finite_fitness() {
+        let finite = CostBreakdown {
+            velocity_cost: 3.0,
+            ..CostBreakdown::default()
+        };
+        let nan = CostBreakdown {
+            p_mad_cost: f64::NAN,
+            ..CostBreakdown::default()
+        };
+        let infinite = CostBreakdown {
+            recovery_cost: f64::INFINITY,
+            ..CostBreakdown::default()
+        };
+        assert_eq!(first_non_finite(&[finite.clone(), finite.clone()]), None);
+        assert_eq!(first_non_finite(&[finite.clone(), nan.clone()]), Some(1));
+        assert_eq!(first_non_finite(&[infinite, nan]), Some(0));
+        assert_eq!(first_non_finite(&[]), None);
+    }
+
+    #[test]
+    fn target_estimate() {
+        // Exactly 100 / n, which reaches 1 at the 100th evaluation
+        let best: Vec<f64> = (1..=20).map(|n| 100.0 / n as f64).collect();
+        let remaining = evaluations_to_target(&best, 1.0).unwrap();
+        assert!((remaining - 80.0).abs() < 1e-6, "{}", remaining);
+
+        assert_eq!(evaluations_to_target(&best, 10.0), Some(0.0));
+        assert_eq!(evaluations_to_target(&best[..5], 1.0), None);
+        // A best error that never falls can't be extrapolated
+        assert_eq!(evaluations_to_target(&[3.0; 20], 1.0), None);
+    }
+
+    #[test]
+    fn best_errors_so_far() {
+        let run = |error| SimulationRun {
+            parameters: IndexMap::new(),
+            time: SystemTime::UNIX_EPOCH,
+            error,
+            breakdown: CostBreakdown::default(),
+            seed_errors: IndexMap::new(),
+            seed_breakdowns: Vec::new(),
+            sample: 0,
+            session: String::new(),
+        };
+        let results = [run(5.0), run(7.0), run(2.0), run(3.0)];
+        assert_eq!(best_so_far(&results), vec![5.0, 5.0, 2.0, 2.0]);
+    }
+
+    #[test]
+    fn baseline_improvement() {
+        assert_eq!(
+            baseline_comparison(15.0, 20.0),
+            "Best error 15 vs baseline 20, a 25.0% improvement"
+        );
+        assert_eq!(
+            baseline_comparison(30.0, 20.0),
+            "Best error 30 vs baseline 20, 50.0% worse"
+        );
+        assert_eq!(baseline_comparison(0.0, 0.0), "Best error 0 vs baseline 0");
+    }
+
+    #[test]
+    fn report_display() {
+        let mut report = OptimizationReport {
+            evaluations: 12,
+            best_error: 4.5,
+            best_parameters: vec![("a".to_owned(), 1.5)].into_iter().collect(),
+            stop_reason: "interrupted".to_owned(),
+            out_dir: PathBuf::from("run-1"),
+            failed_exports: Vec::new(),
+        };
+        assert_eq!(
+            report.to_string(),
+            r#"Best error 4.5 for {"a": 1.5} after 12 evaluations (interrupted), written to run-1"#
+        );
+        report.failed_exports = vec!["hot_cold.png".to_owned(), "error_time.png".to_owned()];
+        assert!(report
+            .to_string()
+            .ends_with("run-1. Failed to export hot_cold.png, error_time.png"));
+    }
+
+    #[test]
+    fn best_file_consistency() {
+        let dir = std::env::temp_dir().join(format!("best-files-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // Nothing beat the starting error, so nothing was saved
+        assert_eq!(
+            best_file_mismatch(&dir, LOWEST_ERROR_START, 20000.0, None),
+            None
+        );
+
+        for name in ["12.5.csv", "3.25.csv", "notes.txt", "bad.csv"] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+        assert_eq!(best_file_mismatch(&dir, 3.25, 3.25, None), None);
+        let mismatch = best_file_mismatch(&dir, 3.25, 1.5, None).unwrap();
+        assert!(mismatch.contains("has error 3.25"), "{}", mismatch);
+        assert!(mismatch.contains("summary has error 1.5"), "{}", mismatch);
+        assert!(best_file_mismatch(&dir, 1.5, 1.5, None).is_some());
+        // Files named with a fixed precision only have to match to that precision
+        assert_eq!(best_file_mismatch(&dir, 3.2504, 3.2504, Some(2)), None);
+
+        // The best file is missing
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(best_file_mismatch(&dir, 3.25, 3.25, None).is_some());
+    }
+
+    #[test]
+    fn range_usage_warnings() {
+        let config = Config {
+            parameters: vec![
+                ParameterConfig::new("a"),
+                ParameterConfig::new("b"),
+                ParameterConfig::new("c"),
+            ],
+            ..Config::default()
+        };
+        let run = |i: usize| SimulationRun {
+            parameters: vec![
+                ("a".to_owned(), (i % 10) as f64 * 2.0),
+                ("b".to_owned(), 4.0 + (i % 10) as f64 * 0.2),
+            ]
+            .into_iter()
+            .collect(),
+            time: SystemTime::UNIX_EPOCH,
+            error: 1.0,
+            breakdown: CostBreakdown::default(),
+            seed_errors: IndexMap::new(),
+            seed_breakdowns: Vec::new(),
+            sample: i,
+            session: String::new(),
+        };
+        let results: Vec<SimulationRun> = (0..RANGE_USAGE_MIN_RESULTS).map(run).collect();
+        let lines = range_usage(&results, &config);
+        assert_eq!(
+            lines[0],
+            "a: sampled 0.000..18.000 of 0..18 (100% of the range)"
+        );
+        assert_eq!(
+            lines[1],
+            "b: sampled 4.000..5.800 of 0..18 (10% of the range) - the range may be too wide, consider narrowing it to about 2.200..7.600"
+        );
+        assert_eq!(lines[2], "c: never sampled");
+
+        // Too few results to judge
+        assert!(!range_usage(&results[..10], &config)[1].contains("too wide"));
+    }
+
+    #[test]
+    fn outliers_in_view() {
+        let mut xs = vec![5.0; 20];
+        xs.push(18.0);
+        let bounds = plot_bounds(xs.iter().copied(), 0.02);
+        assert!(bounds.contains(&18.0) && bounds.contains(&5.0));
+        assert!((bounds.end - 18.26).abs() < 1e-9);
+        assert_eq!(plot_bounds([3.0, 3.0].iter().copied(), 0.02), 2.0..4.0);
+    }
+
+    #[test]
+    fn scatter_color_ends() {
+        assert_eq!(scatter_color(0), RGBColor(1, 255, 50));
+        assert_eq!(scatter_color(255), RGBColor(255, 1, 50));
+        assert_eq!(scatter_color(1000), RGBColor(255, 1, 50));
+        assert_eq!(
+            scatter_color(fixed_color_index(-1.0, (0.0, 10.0))),
+            RGBColor(1, 255, 50)
+        );
+    }
+
+    #[test]
+    fn stalled() {
+        assert!(!is_stalled(10, 10, 5));
+        assert!(!is_stalled(14, 10, 5));
+        assert!(is_stalled(15, 10, 5));
+        assert!(is_stalled(5, 0, 5));
+    }
+
+    #[test]
+    fn recent_failures() {
+        assert_eq!(count_recent_failures(0, 0, 20), (0, 0));
+        assert_eq!(count_recent_failures(0b101, 3, 20), (2, 3));
+        //Only the newest samples in the window count
+        assert_eq!(count_recent_failures(0b1100, 40, 2), (0, 2));
+        assert_eq!(count_recent_failures(0b1100, 40, 4), (2, 4));
+        assert_eq!(count_recent_failures(u64::MAX, 1000, 100), (64, 64));
+    }
+
+    #[test]
+    fn sensitivity_steps() {
+        assert_eq!(sensitivity_values(5.0, 0.5, &(0.0..10.0)), [4.5, 5.5]);
+        assert_eq!(sensitivity_values(0.1, 0.5, &(0.0..10.0)), [0.0, 0.6]);
+        assert_eq!(sensitivity_values(9.8, 0.5, &(0.0..10.0)), [9.3, 10.0]);
+    }
+
+    #[test]
+    fn nan_seed_errors() {
+        let breakdown = |velocity_cost| CostBreakdown {
+            velocity_cost,
+            ..CostBreakdown::default()
+        };
+        let errors = sample_errors(
+            &TermRanges::default(),
+            &Config::default(),
+            &[breakdown(1.0), breakdown(f64::NAN)],
+        );
+        assert!(errors.error.is_nan());
+    }
+
+    #[test]
+    fn normalized_best() {
+        let mut config = Config::default();
+        config.fitness.normalization = Normalization::PopulationRange;
+        let run = |sample, central_distance_cost, velocity_cost| SimulationRun {
+            parameters: IndexMap::new(),
+            time: SystemTime::UNIX_EPOCH,
+            //Scored against the ranges of the runs before it, so the first was 0
+            error: 0.0,
+            breakdown: CostBreakdown {
+                central_distance_cost,
+                velocity_cost,
+                ..CostBreakdown::default()
+            },
+            seed_errors: IndexMap::new(),
+            seed_breakdowns: Vec::new(),
+            sample,
+            session: String::new(),
+        };
+        let mut state = StateImpl {
+            params: Vec::new(),
+            results: vec![
+                run(0, 1000.0, 10.0),
+                run(1, 100.0, 12.0),
+                run(2, 500.0, 15.0),
+            ],
+            term_ranges: TermRanges::default(),
+            error_quantiles: ErrorQuantiles::default(),
+        };
+        rescore(&mut state, &config);
+        let errors: Vec<f64> = state.results.iter().map(|run| run.error).collect();
+        assert_eq!(errors[..2], [1.0, 0.4]);
+        assert!((errors[2] - 13.0 / 9.0).abs() < 1e-9);
+        assert_eq!(best_run(&state.results).unwrap().sample, 1);
+
+        config
+            .fitness
+            .term_weights
+            .insert("central_distance_cost".to_owned(), 0.1);
+        rescore(&mut state, &config);
+        assert_eq!(best_run(&state.results).unwrap().sample, 0);
+    }
+}
@@ -1,12 +1,18 @@
+use crate::log;
 use std::collections::{HashMap, HashSet};
+use std::io::BufRead;
 use std::net::IpAddr;
+use std::path::{Path, PathBuf};
 
 use crate::util;
 
 pub use glam::Vec3A as Vec3;
 pub type UavId = IpAddr;
 
-#[derive(Debug, PartialEq, PartialOrd, Clone, Copy)]
+/// Bumped whenever the layout of `SimulationData` changes so that old `.simdata` files are ignored
+const CACHE_VERSION: u32 = 2;
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq, PartialOrd, Clone, Copy)]
 pub struct TimePoint(pub f32);
 
 impl TimePoint {
@@ -19,19 +25,12 @@ impl TimePoint {
     }
 }
 
-/// Data that is recorded at a point in time during the simulation
-#[derive(Debug, PartialEq)]
-pub struct UavKeyFrame {
-    ip: UavId,
-    pos: Vec3,
-}
-
-#[derive(Debug, PartialEq)]
+#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
 pub enum Event {
     ColorChange((UavId, Vec3)),
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq, Eq)]
 enum InterpolationState {
     /// Indicates that we are before the first data point. Value is the index into frames
     Before(usize),
@@ -44,7 +43,7 @@ enum InterpolationState {
     After(usize),
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
 struct TimedObject<T> {
     time: TimePoint,
     inner: T,
@@ -59,9 +58,193 @@ impl<T> TimedObject<T> {
     }
 }
 
+/// What to do with position lines whose coordinates are NaN or infinite, which NS3 writes when a
+/// simulation blows up numerically
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NonFinitePolicy {
+    /// Fail to parse the whole file
+    Reject,
+    /// Skip the offending lines and keep the rest of the data
+    Drop,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ParseOptions {
+    pub non_finite: NonFinitePolicy,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            non_finite: NonFinitePolicy::Reject,
+        }
+    }
+}
+
+/// A value on a line of a positions file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Time,
+    UavId,
+    X,
+    Y,
+    Z,
+    Red,
+    Green,
+    Blue,
+}
+
+impl std::fmt::Display for Field {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Field::Time => "time",
+            Field::UavId => "UAV IP address",
+            Field::X => "x",
+            Field::Y => "y",
+            Field::Z => "z",
+            Field::Red => "red",
+            Field::Green => "green",
+            Field::Blue => "blue",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Why a positions file couldn't be parsed. Line numbers start at 1 for the header
 #[derive(Debug, PartialEq)]
+pub enum ParseError {
+    /// A value is missing from a line or isn't valid. `column` counts the comma separated values
+    /// from 1
+    InvalidField {
+        line_number: usize,
+        line: String,
+        field: Field,
+        column: usize,
+    },
+    /// A position is NaN or infinite while `NonFinitePolicy::Reject` is set
+    NonFinite { line_number: usize, line: String },
+    /// The file has no position lines
+    NoPositions,
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::InvalidField {
+                line_number,
+                line,
+                field,
+                column,
+            } => write!(
+                f,
+                "Missing or invalid {} in column {} on line {}: {}",
+                field, column, line_number, line
+            ),
+            ParseError::NonFinite { line_number, line } => {
+                write!(f, "Non-finite position on line {}: {}", line_number, line)
+            }
+            ParseError::NoPositions => f.write_str("No positions in file"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// The comma separated values of one line, parsed on demand so errors can say which one failed
+struct LineFields<'a> {
+    line_number: usize,
+    line: &'a str,
+    values: Vec<&'a str>,
+}
+
+impl<'a> LineFields<'a> {
+    fn new(line_number: usize, line: &'a str) -> Self {
+        Self {
+            line_number,
+            line,
+            values: line.split(',').map(str::trim).collect(),
+        }
+    }
+
+    /// Parses the value in `column`, counting from 0, as `field`
+    fn get<T: std::str::FromStr>(&self, column: usize, field: Field) -> Result<T, ParseError> {
+        self.values
+            .get(column)
+            .and_then(|value| value.parse().ok())
+            .ok_or_else(|| ParseError::InvalidField {
+                line_number: self.line_number,
+                line: self.line.to_owned(),
+                field,
+                column: column + 1,
+            })
+    }
+
+    /// Parses the time in `column`. Unlike positions, a time that isn't finite can't be placed in
+    /// the file, so it is always invalid
+    fn time(&self, column: usize) -> Result<f32, ParseError> {
+        let time: f32 = self.get(column, Field::Time)?;
+        if time.is_finite() {
+            Ok(time)
+        } else {
+            Err(ParseError::InvalidField {
+                line_number: self.line_number,
+                line: self.line.to_owned(),
+                field: Field::Time,
+                column: column + 1,
+            })
+        }
+    }
+}
+
+/// The structure of a positions file as found by `SimulationData::validate`
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileReport {
+    pub uavs: usize,
+    pub timesteps: usize,
+    /// The times of the first and last timestep
+    pub start: f32,
+    pub end: f32,
+    /// The usual time between timesteps. `None` with a single timestep
+    pub interval: Option<f32>,
+    /// Times between consecutive timesteps of more than twice `interval`
+    pub gaps: usize,
+    /// Timesteps that are earlier than the one before them
+    pub out_of_order: usize,
+    /// Lines that repeat a UAV at the time it was already logged at
+    pub duplicates: usize,
+    /// Lines with a NaN or infinite position, which were left out
+    pub non_finite: usize,
+}
+
+impl FileReport {
+    /// If nothing suspicious was found
+    pub fn passed(&self) -> bool {
+        self.gaps == 0 && self.out_of_order == 0 && self.duplicates == 0 && self.non_finite == 0
+    }
+}
+
+impl std::fmt::Display for FileReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} UAVs, {} timesteps from {}s to {}s",
+            self.uavs, self.timesteps, self.start, self.end
+        )?;
+        if let Some(interval) = self.interval {
+            write!(f, " every {}s", interval)?;
+        }
+        write!(
+            f,
+            ", {} gaps, {} out of order, {} duplicates, {} non-finite",
+            self.gaps, self.out_of_order, self.duplicates, self.non_finite
+        )
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
 pub struct SimulationData {
-    frames: Vec<TimedObject<HashMap<UavId, UavKeyFrame>>>,
+    frames: Vec<TimedObject<HashMap<UavId, Vec3>>>,
     state: HashMap<UavId, InterpolationState>,
     events: Vec<TimedObject<Event>>,
     last_time: Option<TimePoint>,
@@ -70,61 +253,115 @@ pub struct SimulationData {
     pub simulation_length: f32,
 }
 
-impl SimulationData {
-    pub fn parse(data: &str) -> Result<Self, Box<dyn std::error::Error>> {
-        let mut frames = Vec::new();
-        let mut events = Vec::new();
-        let mut last_time = None;
-        let mut inner_map = HashMap::new();
-        let mut unique_ids = HashSet::new();
-        for line in data.lines().skip(1) {
-            if line.starts_with("color") {
-                // Color directive
-                let (time, ip, r, g, b) = scan_fmt::scan_fmt!(
-                    line,
-                    "color,{f},{},{f},{f},{f}",
-                    f32,
-                    IpAddr,
-                    f32,
-                    f32,
-                    f32
-                )?;
-                events.push(TimedObject::new(
-                    time,
-                    Event::ColorChange((ip, Vec3::new(r, g, b))),
-                ));
-            } else {
-                //Normal pos line
-                let (time, ip, x, y, z) =
-                    scan_fmt::scan_fmt!(line, "{f},{},{f},{f},{f}", f32, IpAddr, f32, f32, f32)?;
-                unique_ids.insert(ip);
-                //Finish last part of frame
-                if let Some(last_time) = last_time {
-                    if last_time != time {
-                        //Finish inner map
-                        frames.push(TimedObject::new(last_time, std::mem::take(&mut inner_map)));
+/// Builds `SimulationData` one line at a time, so the same logic serves both parsing a string and
+/// streaming from a reader
+struct Parser<'a> {
+    options: &'a ParseOptions,
+    frames: Vec<TimedObject<HashMap<UavId, Vec3>>>,
+    events: Vec<TimedObject<Event>>,
+    last_time: Option<f32>,
+    inner_map: HashMap<UavId, Vec3>,
+    unique_ids: HashSet<UavId>,
+    dropped: usize,
+    first_dropped: Option<(usize, String)>,
+    /// Position lines for a UAV that already has one at the same time. The last one wins
+    duplicates: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(options: &'a ParseOptions) -> Self {
+        Self {
+            options,
+            frames: Vec::new(),
+            events: Vec::new(),
+            last_time: None,
+            inner_map: HashMap::new(),
+            unique_ids: HashSet::new(),
+            dropped: 0,
+            first_dropped: None,
+            duplicates: 0,
+        }
+    }
+
+    /// Parses the line at index `line_number` (0 is the header) without its line ending
+    fn line(&mut self, line_number: usize, line: &str) -> Result<(), ParseError> {
+        let fields = LineFields::new(line_number + 1, line);
+        if line.starts_with("color") {
+            // Color directive
+            let time = fields.time(1)?;
+            let ip: IpAddr = fields.get(2, Field::UavId)?;
+            let r = fields.get(3, Field::Red)?;
+            let g = fields.get(4, Field::Green)?;
+            let b = fields.get(5, Field::Blue)?;
+            self.events.push(TimedObject::new(
+                time,
+                Event::ColorChange((ip, Vec3::new(r, g, b))),
+            ));
+        } else {
+            //Normal pos line. `nan` and `inf` parse as floats and are handled below
+            let time = fields.time(0)?;
+            let ip: IpAddr = fields.get(1, Field::UavId)?;
+            let x: f32 = fields.get(2, Field::X)?;
+            let y: f32 = fields.get(3, Field::Y)?;
+            let z: f32 = fields.get(4, Field::Z)?;
+            if ![x, y, z].iter().all(|v| v.is_finite()) {
+                match self.options.non_finite {
+                    NonFinitePolicy::Reject => {
+                        return Err(ParseError::NonFinite {
+                            line_number: line_number + 1,
+                            line: line.to_owned(),
+                        });
+                    }
+                    NonFinitePolicy::Drop => {
+                        self.dropped += 1;
+                        self.first_dropped
+                            .get_or_insert_with(|| (line_number + 1, line.to_owned()));
+                        return Ok(());
                     }
                 }
-                last_time = Some(time);
-                inner_map.insert(
-                    ip,
-                    UavKeyFrame {
-                        ip,
-                        pos: Vec3::new(x, y, z),
-                    },
-                );
+            }
+            self.unique_ids.insert(ip);
+            //Finish last part of frame
+            if let Some(last_time) = self.last_time {
+                if last_time != time {
+                    //Finish inner map
+                    self.frames.push(TimedObject::new(
+                        last_time,
+                        std::mem::take(&mut self.inner_map),
+                    ));
+                }
+            }
+            self.last_time = Some(time);
+            if self.inner_map.insert(ip, Vec3::new(x, y, z)).is_some() {
+                self.duplicates += 1;
             }
         }
-        if let Some(last_time) = last_time {
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<SimulationData, ParseError> {
+        if let Some((line_number, line)) = self.first_dropped {
+            log!(
+                "Dropped {} non-finite positions, first on line {}: {}",
+                self.dropped,
+                line_number,
+                line
+            );
+        }
+        let mut frames = self.frames;
+        if let Some(last_time) = self.last_time {
             //Finish the last inner map
-            frames.push(TimedObject::new(last_time, std::mem::take(&mut inner_map)));
+            frames.push(TimedObject::new(
+                last_time,
+                std::mem::take(&mut self.inner_map),
+            ));
         }
 
         //Set the initial state. Because we assume the user starts the simulation at t=0, all the UAV's
         //will be in the before state because we only know their position in the future
         let mut state = HashMap::new();
         if !frames.is_empty() {
-            for uav in &unique_ids {
+            for uav in &self.unique_ids {
                 for (i, _) in frames.iter().enumerate() {
                     let entry = &frames[i];
                     if entry.inner.contains_key(uav) {
@@ -134,17 +371,227 @@ impl SimulationData {
                 }
             }
         }
-        let simulation_length = frames[frames.len() - 1].time.0;
-        Ok(Self {
+        let simulation_length = match frames.last() {
+            Some(frame) => frame.time.0,
+            None => return Err(ParseError::NoPositions),
+        };
+        Ok(SimulationData {
             frames,
             state,
-            events,
+            events: self.events,
             last_time: None,
             simulation_length,
-            uavs: unique_ids,
+            uavs: self.unique_ids,
+        })
+    }
+}
+
+impl SimulationData {
+    pub fn parse(data: &str, options: &ParseOptions) -> Result<Self, ParseError> {
+        let mut parser = Parser::new(options);
+        for (line_number, line) in data.lines().enumerate().skip(1) {
+            parser.line(line_number, line)?;
+        }
+        parser.finish()
+    }
+
+    /// Parses a positions file as it is read, without holding all of its text in memory
+    pub fn parse_reader(
+        reader: impl BufRead,
+        options: &ParseOptions,
+    ) -> Result<Self, crate::Error> {
+        let mut parser = Parser::new(options);
+        Self::read_lines(reader, &mut parser)?;
+        Ok(parser.finish()?)
+    }
+
+    /// Feeds every line after the header in `reader` to `parser`
+    fn read_lines(mut reader: impl BufRead, parser: &mut Parser) -> Result<(), crate::Error> {
+        let mut line = String::new();
+        let mut line_number = 0;
+        while reader.read_line(&mut line)? != 0 {
+            if line_number > 0 {
+                let trimmed = line.strip_suffix('\n').unwrap_or(&line);
+                let trimmed = trimmed.strip_suffix('\r').unwrap_or(trimmed);
+                parser.line(line_number, trimmed)?;
+            }
+            line.clear();
+            line_number += 1;
+        }
+        Ok(())
+    }
+
+    /// Parses a positions file leniently, dropping non-finite positions instead of failing, and
+    /// describes its structure and anything suspicious about it without scoring it
+    pub fn validate(reader: impl BufRead) -> Result<FileReport, crate::Error> {
+        let options = ParseOptions {
+            non_finite: NonFinitePolicy::Drop,
+        };
+        let mut parser = Parser::new(&options);
+        Self::read_lines(reader, &mut parser)?;
+        let (non_finite, duplicates) = (parser.dropped, parser.duplicates);
+        let data = parser.finish()?;
+
+        let interval = data.logging_interval();
+        let steps: Vec<f32> = data
+            .frames
+            .windows(2)
+            .map(|pair| pair[1].time.0 - pair[0].time.0)
+            .collect();
+        Ok(FileReport {
+            uavs: data.uavs.len(),
+            timesteps: data.frames.len(),
+            start: data.frames.first().unwrap().time.0,
+            end: data.simulation_length,
+            interval,
+            gaps: interval.map_or(0, |interval| {
+                steps.iter().filter(|step| **step > 2.0 * interval).count()
+            }),
+            out_of_order: steps.iter().filter(|step| **step < 0.0).count(),
+            duplicates,
+            non_finite,
         })
     }
 
+    /// Parses `data` with both `parse` and `parse_reader` and describes the first difference
+    /// between the results, if any
+    pub fn verify_parsers(data: &str, options: &ParseOptions) -> Option<String> {
+        let parsed = Self::parse(data, options).map_err(|err| err.to_string());
+        let streamed = Self::parse_reader(data.as_bytes(), options).map_err(|err| err.to_string());
+        match (parsed, streamed) {
+            (Ok(parsed), Ok(streamed)) => parsed.difference(&streamed),
+            (Err(a), Err(b)) if a == b => None,
+            (a, b) => Some(format!(
+                "parse returned {:?} but parse_reader returned {:?}",
+                a.map(|_| "data"),
+                b.map(|_| "data")
+            )),
+        }
+    }
+
+    /// Describes the first difference between `self` and `other`
+    fn difference(&self, other: &Self) -> Option<String> {
+        if self.frames.len() != other.frames.len() {
+            return Some(format!(
+                "{} frames vs {} frames",
+                self.frames.len(),
+                other.frames.len()
+            ));
+        }
+        if let Some((i, (a, b))) = self
+            .frames
+            .iter()
+            .zip(&other.frames)
+            .enumerate()
+            .find(|(_, (a, b))| a != b)
+        {
+            return Some(format!("frame {} differs: {:?} vs {:?}", i, a, b));
+        }
+        if self != other {
+            return Some(format!(
+                "events, UAVs or length differ: {:?} {:?} {} vs {:?} {:?} {}",
+                self.events,
+                self.uavs,
+                self.simulation_length,
+                other.events,
+                other.uavs,
+                other.simulation_length
+            ));
+        }
+        None
+    }
+
+    /// Parses the positions file at `path`.
+    ///
+    /// When `use_cache` is set, the parsed data is kept next to the csv as a `.simdata` file, which
+    /// is read instead of the csv as long as it is newer and was made with the same options
+    pub fn load(
+        path: impl AsRef<Path>,
+        options: &ParseOptions,
+        use_cache: bool,
+    ) -> Result<Self, crate::Error> {
+        let path = path.as_ref();
+        let cache_path = Self::cache_path(path);
+        if use_cache {
+            if let Some(data) = Self::read_cache(path, &cache_path, options) {
+                return Ok(data);
+            }
+        }
+        let data = Self::parse_reader(crate::util::open_positions(path)?, options)?;
+        if use_cache {
+            if let Err(err) = data.write_cache(&cache_path, options) {
+                log!(
+                    "Failed to write parse cache {}: {}",
+                    cache_path.to_str().unwrap(),
+                    err
+                );
+            }
+        }
+        Ok(data)
+    }
+
+    fn cache_path(path: &Path) -> PathBuf {
+        path.with_extension("simdata")
+    }
+
+    /// Returns the cached data for `csv_path` if there is an up to date cache for it
+    fn read_cache(csv_path: &Path, cache_path: &Path, options: &ParseOptions) -> Option<Self> {
+        let csv_modified = std::fs::metadata(csv_path).ok()?.modified().ok()?;
+        let cache_modified = std::fs::metadata(cache_path).ok()?.modified().ok()?;
+        if cache_modified < csv_modified {
+            return None;
+        }
+        let mut reader = std::io::BufReader::new(std::fs::File::open(cache_path).ok()?);
+        let version: u32 = bincode::deserialize_from(&mut reader).ok()?;
+        if version != CACHE_VERSION {
+            return None;
+        }
+        let cached_options: NonFinitePolicy = bincode::deserialize_from(&mut reader).ok()?;
+        if cached_options != options.non_finite {
+            return None;
+        }
+        bincode::deserialize_from(&mut reader).ok()
+    }
+
+    fn write_cache(&self, cache_path: &Path, options: &ParseOptions) -> Result<(), crate::Error> {
+        let mut writer = std::io::BufWriter::new(std::fs::File::create(cache_path)?);
+        bincode::serialize_into(&mut writer, &CACHE_VERSION)?;
+        bincode::serialize_into(&mut writer, &options.non_finite)?;
+        bincode::serialize_into(&mut writer, self)?;
+        Ok(())
+    }
+
+    /// Parses a positions file that the simulation may have been killed while writing, ignoring
+    /// the last line if it wasn't finished
+    pub fn parse_partial(data: &str, options: &ParseOptions) -> Result<Self, ParseError> {
+        let complete = match data.rfind('\n') {
+            Some(end) => &data[..=end],
+            None => "",
+        };
+        Self::parse(complete, options)
+    }
+
+    /// The recorded positions in time order. Each timestep only contains the UAVs that were logged
+    /// at that time
+    pub fn timesteps(&self) -> impl Iterator<Item = (TimePoint, &HashMap<UavId, Vec3>)> + '_ {
+        self.frames.iter().map(|frame| (frame.time, &frame.inner))
+    }
+
+    /// The most common time between consecutive timesteps, which is the interval the simulation
+    /// logged positions at. `None` when there is only one timestep
+    pub fn logging_interval(&self) -> Option<f32> {
+        let mut intervals: Vec<f32> = self
+            .frames
+            .windows(2)
+            .map(|pair| pair[1].time.0 - pair[0].time.0)
+            .collect();
+        if intervals.is_empty() {
+            return None;
+        }
+        intervals.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        Some(intervals[intervals.len() / 2])
+    }
+
     /// Returns the position of the specified UAV at the given point in time
     ///
     /// time must never decrease from one call of this function to the next
@@ -162,7 +609,7 @@ impl SimulationData {
                     let old_entry = &self.frames[*index];
                     if now.is_before(&old_entry.time) {
                         //We are still before the old value
-                        return Some(old_entry.inner.get(&uav).unwrap().pos);
+                        return Some(old_entry.inner[&uav]);
                     } else {
                         *index
                     }
@@ -176,7 +623,7 @@ impl SimulationData {
                 }
                 InterpolationState::After(index) => {
                     //Easy - we know there are no more values after this one
-                    return Some(self.frames[*index].inner.get(&uav).unwrap().pos);
+                    return Some(self.frames[*index].inner[&uav]);
                 }
             },
             None => return None,
@@ -186,25 +633,25 @@ impl SimulationData {
         for i in (start_index + 1)..self.frames.len() {
             let entry = &self.frames[i];
             if let Some(new_entry) = entry.inner.get(&uav) {
-                last_index = i;
                 if entry.time.is_after(&now) {
-                    //We found an entry which is after this event
-                    //Make sure the last entry is before now
-                    let last_frame = &self.frames[start_index];
+                    //We found an entry which is after this event. Interpolate from the newest
+                    //entry before now, which may be past where we started looking
+                    let last_frame = &self.frames[last_index];
                     assert!(last_frame.time.is_before(&now));
                     assert!(last_frame.inner.contains_key(&uav));
-                    let a_pos = last_frame.inner.get(&uav).unwrap().pos;
+                    let a_pos = last_frame.inner[&uav];
                     let a_time = last_frame.time;
-                    let b_pos = new_entry.pos;
+                    let b_pos = *new_entry;
                     let b_time = entry.time;
 
                     let pos = util::map(a_time.0, b_time.0, now.0, a_pos, b_pos);
 
                     self.state
-                        .insert(uav, InterpolationState::Interpolate(start_index, i));
+                        .insert(uav, InterpolationState::Interpolate(last_index, i));
 
                     return Some(pos);
                 }
+                last_index = i;
             }
         }
         //If we got to the end without finding an upper bound, that means we are at the end of the
@@ -212,7 +659,7 @@ impl SimulationData {
         self.state
             .insert(uav, InterpolationState::After(last_index));
 
-        Some(self.frames[last_index].inner.get(&uav).unwrap().pos)
+        Some(self.frames[last_index].inner[&uav])
     }
 }
 
@@ -234,6 +681,7 @@ mod tests {
 0,10.1.1.8,1.03635,1.8033,3.10858,
 color,0,10.1.1.1,0.3,0.7,1,
 0.05,10.1.1.1,0,0,0,"#,
+            &ParseOptions::default(),
         )
         .unwrap();
 
@@ -253,6 +701,209 @@ color,0,10.1.1.1,0.3,0.7,1,
         );
     }
 
+    const NAN_POSITIONS: &str = r#"Time (s),IP Address, X (m), Y (m), Z (m)
+0,10.1.1.1,0,0,0,
+0,10.1.1.2,1,1,1,
+0.1,10.1.1.1,0,0,0,
+0.1,10.1.1.2,nan,1,1,
+0.2,10.1.1.1,0,0,0,
+0.2,10.1.1.2,inf,1,1,"#;
+
+    #[test]
+    fn non_finite_reject() {
+        let err = SimulationData::parse(NAN_POSITIONS, &ParseOptions::default()).unwrap_err();
+        assert_eq!(
+            err,
+            ParseError::NonFinite {
+                line_number: 5,
+                line: "0.1,10.1.1.2,nan,1,1,".to_owned()
+            }
+        );
+        assert!(err.to_string().contains("line 5"));
+    }
+
+    #[test]
+    fn invalid_fields() {
+        let error = |line: &str| {
+            let data = format!(
+                "Time (s),IP Address, X (m), Y (m), Z (m)\n0,10.1.1.1,0,0,0,\n{}\n",
+                line
+            );
+            match SimulationData::parse(&data, &ParseOptions::default()).unwrap_err() {
+                ParseError::InvalidField {
+                    line_number,
+                    field,
+                    column,
+                    ..
+                } => (line_number, field, column),
+                err => panic!("unexpected error {}", err),
+            }
+        };
+        assert_eq!(error("abc,10.1.1.1,0,0,0,"), (3, Field::Time, 1));
+        assert_eq!(error("nan,10.1.1.1,0,0,0,"), (3, Field::Time, 1));
+        assert_eq!(error("color,inf,10.1.1.1,1,0,0"), (3, Field::Time, 2));
+        assert_eq!(error("0.1,10.1.1,0,0,0,"), (3, Field::UavId, 2));
+        assert_eq!(error("0.1,10.1.1.1,0,0.5.1,0,"), (3, Field::Y, 4));
+        assert_eq!(error("0.1,10.1.1.1,0,0"), (3, Field::Z, 5));
+        assert_eq!(error("color,0.1,10.1.1.1,1,0"), (3, Field::Blue, 6));
+
+        let data = "Time (s),IP Address, X (m), Y (m), Z (m)\n0,10.1.1.1,0,x,0,\n";
+        let message = SimulationData::parse(data, &ParseOptions::default())
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            message,
+            "Missing or invalid y in column 4 on line 2: 0,10.1.1.1,0,x,0,"
+        );
+    }
+
+    #[test]
+    fn validate_report() {
+        let report = SimulationData::validate(NAN_POSITIONS.as_bytes()).unwrap();
+        assert_eq!(
+            (report.uavs, report.timesteps, report.non_finite),
+            (2, 3, 2)
+        );
+        assert!(!report.passed());
+
+        let clean = "Time (s),IP Address, X (m), Y (m), Z (m)\n\
+                     0,10.1.1.1,0,0,0,\n\
+                     0.1,10.1.1.1,0,0,0,\n\
+                     0.2,10.1.1.1,0,0,0,\n";
+        let report = SimulationData::validate(clean.as_bytes()).unwrap();
+        assert!(report.passed(), "{}", report);
+        assert_eq!((report.start, report.end), (0.0, 0.2));
+
+        let broken = "Time (s),IP Address, X (m), Y (m), Z (m)\n\
+                      0,10.1.1.1,0,0,0,\n\
+                      0.1,10.1.1.1,0,0,0,\n\
+                      0.1,10.1.1.1,1,0,0,\n\
+                      0.2,10.1.1.1,0,0,0,\n\
+                      0.3,10.1.1.1,0,0,0,\n\
+                      1.0,10.1.1.1,0,0,0,\n\
+                      0.9,10.1.1.1,0,0,0,\n";
+        let report = SimulationData::validate(broken.as_bytes()).unwrap();
+        assert_eq!(
+            (report.gaps, report.out_of_order, report.duplicates),
+            (1, 1, 1)
+        );
+        assert!(SimulationData::validate("Time (s)\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn non_finite_drop() {
+        let options = ParseOptions {
+            non_finite: NonFinitePolicy::Drop,
+        };
+        let data = SimulationData::parse(NAN_POSITIONS, &options).unwrap();
+        assert_eq!(data.frames.len(), 3);
+        assert_eq!(data.frames[0].inner.len(), 2);
+        assert_eq!(data.frames[1].inner.len(), 1);
+        assert_eq!(data.frames[2].inner.len(), 1);
+    }
+
+    #[test]
+    fn timesteps() {
+        let data = SimulationData::parse(
+            r#"Time (s),IP Address, X (m), Y (m), Z (m)
+0,10.1.1.1,0,0,0,
+0,10.1.1.2,1,1,1,
+0.5,10.1.1.2,2,2,2,"#,
+            &ParseOptions::default(),
+        )
+        .unwrap();
+        let timesteps: Vec<_> = data.timesteps().collect();
+        assert_eq!(timesteps.len(), 2);
+        assert_eq!(timesteps[0].0, TimePoint(0.0));
+        assert_eq!(timesteps[0].1.len(), 2);
+        assert_eq!(timesteps[1].0, TimePoint(0.5));
+        assert_eq!(
+            timesteps[1].1[&"10.1.1.2".parse().unwrap()],
+            Vec3::new(2.0, 2.0, 2.0)
+        );
+    }
+
+    #[test]
+    fn logging_interval() {
+        let mut csv = String::from("Time (s),IP Address, X (m), Y (m), Z (m)\n");
+        for time in [0.0, 0.05, 0.1, 0.15, 0.2, 0.5] {
+            csv.push_str(&format!("{},10.1.1.1,0,0,0,\n", time));
+        }
+        let data = SimulationData::parse(&csv, &ParseOptions::default()).unwrap();
+        assert!((data.logging_interval().unwrap() - 0.05).abs() < 1e-6);
+
+        let data = SimulationData::parse(
+            "Time (s)\n0,10.1.1.1,0,0,0,\n0,10.1.1.2,0,0,0,\n",
+            &ParseOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(data.logging_interval(), None);
+    }
+
+    #[test]
+    fn partial() {
+        let data = r#"Time (s),IP Address, X (m), Y (m), Z (m)
+0,10.1.1.1,0,0,0,
+0.1,10.1.1.1,1,1,1,
+0.2,10.1.1.1,2,2"#;
+        assert!(SimulationData::parse(data, &ParseOptions::default()).is_err());
+        let partial = SimulationData::parse_partial(data, &ParseOptions::default()).unwrap();
+        assert_eq!(partial.frames.len(), 2);
+        assert_eq!(partial.simulation_length, 0.1);
+
+        assert!(SimulationData::parse_partial("Time (s),IP", &ParseOptions::default()).is_err());
+    }
+
+    #[test]
+    fn streaming_matches() {
+        let options = ParseOptions {
+            non_finite: NonFinitePolicy::Drop,
+        };
+        let crlf = NAN_POSITIONS.replace('\n', "\r\n");
+        let no_trailing_newline = format!("{}\ncolor,0.2,10.1.1.1,1,0,0,", NAN_POSITIONS);
+        for data in [
+            NAN_POSITIONS,
+            &crlf,
+            &no_trailing_newline,
+            "Time (s),IP Address, X (m), Y (m), Z (m)\n",
+            "Time (s)\n0,10.1.1.1,0,0,0,\n\n",
+            "",
+        ] {
+            assert_eq!(SimulationData::verify_parsers(data, &options), None);
+            assert_eq!(
+                SimulationData::verify_parsers(data, &ParseOptions::default()),
+                None
+            );
+        }
+    }
+
+    #[test]
+    fn cache_round_trip() {
+        let dir = std::env::temp_dir().join(format!("simdata-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let csv_path = dir.join("positions.csv");
+        std::fs::write(&csv_path, NAN_POSITIONS).unwrap();
+        let options = ParseOptions {
+            non_finite: NonFinitePolicy::Drop,
+        };
+
+        let parsed = SimulationData::load(&csv_path, &options, true).unwrap();
+        let cache_path = SimulationData::cache_path(&csv_path);
+        assert!(cache_path.exists());
+        assert_eq!(
+            SimulationData::read_cache(&csv_path, &cache_path, &options),
+            Some(parsed)
+        );
+        //A cache made with other options is not used
+        assert_eq!(
+            SimulationData::read_cache(&csv_path, &cache_path, &ParseOptions::default()),
+            None
+        );
+        assert!(SimulationData::load(&csv_path, &ParseOptions::default(), true).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
     macro_rules! assert_approx_eq {
         ($a:expr, $b:expr) => {{
             let eps = 1.0e-5;
@@ -293,6 +944,7 @@ color,0,10.1.1.1,0.3,0.7,1,
 0.3,10.1.1.1,3,3,3,
 0.4,10.1.1.1,-50,-8,2,
 0.5,10.1.1.1,0,0,0,"#,
+            &ParseOptions::default(),
         )
         .unwrap();
 
@@ -403,4 +1055,27 @@ color,0,10.1.1.1,0.3,0.7,1,
             Vec3::new(0.0, 0.0, 0.0)
         );
     }
+
+    #[test]
+    fn skipped_frame() {
+        let uav = "10.1.1.1".parse().unwrap();
+        let mut data = SimulationData::parse(
+            r#"Time (s),IP Address, X (m), Y (m), Z (m)
+0,10.1.1.1,0,0,0,
+1,10.1.1.1,10,0,0,
+2,10.1.1.1,10,10,0,
+3,10.1.1.1,0,10,0,"#,
+            &ParseOptions::default(),
+        )
+        .unwrap();
+        assert_approx_eq!(
+            data.pos_at_time(TimePoint(0.5), uav).unwrap(),
+            Vec3::new(5.0, 0.0, 0.0)
+        );
+        //Jumping past the frame at 2 seconds interpolates from it, not from the frame at 1
+        assert_approx_eq!(
+            data.pos_at_time(TimePoint(2.5), uav).unwrap(),
+            Vec3::new(5.0, 10.0, 0.0)
+        );
+    }
 }
@@ -0,0 +1,156 @@
+use crate::config::RemoteConfig;
+use crate::optimization::shell_quote;
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// The simulation argument that names the positions file it writes
+const POSITIONS_ARG: &str = "--positionsFile=";
+
+/// An ssh command that runs `script` in the login shell of the remote host. It fails instead of
+/// prompting for a password
+pub fn ssh(remote: &RemoteConfig, script: &str) -> Command {
+    let mut command = Command::new("ssh");
+    command
+        .args(&remote.ssh_args)
+        .args(["-o", "BatchMode=yes"])
+        .arg(&remote.host)
+        .arg(script);
+    command
+}
+
+/// `args` with the positions file moved into the remote working directory, along with the local
+/// positions file and the remote one that replaced it
+pub fn remote_args(
+    remote: &RemoteConfig,
+    args: &[String],
+) -> (Vec<String>, Option<(PathBuf, PathBuf)>) {
+    let mut positions = None;
+    let args = args
+        .iter()
+        .map(|arg| match arg.strip_prefix(POSITIONS_ARG) {
+            Some(local) => {
+                let local = PathBuf::from(local);
+                let remote_path = remote.working_dir.join(local.file_name().unwrap());
+                let arg = format!("{}{}", POSITIONS_ARG, remote_path.to_str().unwrap());
+                positions = Some((local, remote_path));
+                arg
+            }
+            None => arg.clone(),
+        })
+        .collect();
+    (args, positions)
+}
+
+/// Copies `remote_path` on the host to `local_path`
+pub fn fetch(
+    remote: &RemoteConfig,
+    remote_path: &Path,
+    local_path: &Path,
+) -> Result<(), crate::Error> {
+    let status = Command::new("scp")
+        .args(&remote.ssh_args)
+        .args(["-q", "-o", "BatchMode=yes"])
+        .arg(format!(
+            "{}:{}",
+            remote.host,
+            shell_quote(remote_path.to_str().unwrap())
+        ))
+        .arg(local_path)
+        .stdin(Stdio::null())
+        .status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(crate::Error::Simulation(format!(
+            "Copying {} back from {} failed with {}",
+            remote_path.display(),
+            remote.host,
+            status
+        )))
+    }
+}
+
+/// Deletes `path` on the host. A file that is left behind only takes up space, so failures are
+/// ignored
+pub fn remove(remote: &RemoteConfig, path: &Path) {
+    let _ = ssh(
+        remote,
+        &format!("rm -f {}", shell_quote(path.to_str().unwrap())),
+    )
+    .stdin(Stdio::null())
+    .stdout(Stdio::null())
+    .stderr(Stdio::null())
+    .status();
+}
+
+/// Checks that the host can be logged into and has the simulation, so a wrong path fails once
+/// instead of on every sample, and creates the local directory positions files are copied to
+pub fn check(remote: &RemoteConfig) -> Result<(), crate::Error> {
+    std::fs::create_dir_all(crate::util::resolve_path(&remote.local_dir))?;
+    let script = format!(
+        "test -d {} && test -x {}",
+        shell_quote(remote.working_dir.to_str().unwrap()),
+        shell_quote(remote.binary.to_str().unwrap())
+    );
+    let output = ssh(remote, &script).stdin(Stdio::null()).output()?;
+    match output.status.code() {
+        Some(0) => Ok(()),
+        // ssh itself exits with 255 when it can't connect
+        Some(255) => Err(crate::Error::Simulation(format!(
+            "Can't connect to {}: {}",
+            remote.host,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))),
+        _ => Err(crate::Error::Simulation(format!(
+            "{} has no working directory {} or simulation binary {}",
+            remote.host,
+            remote.working_dir.display(),
+            remote.binary.display()
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positions_on_host() {
+        let remote = RemoteConfig {
+            host: "ns3-box".to_owned(),
+            ssh_args: vec!["-o".to_owned(), "Port=2222".to_owned()],
+            working_dir: PathBuf::from("/srv/ns3"),
+            binary: PathBuf::from("/srv/ns3/build/non-ideal"),
+            lib_dir: PathBuf::from("/srv/ns3/build/lib"),
+            local_dir: PathBuf::from("."),
+        };
+        let args = vec![
+            "--duration=180".to_owned(),
+            "--positionsFile=/home/me/positions/abc.csv".to_owned(),
+            "--seed=4".to_owned(),
+        ];
+        let (args, positions) = remote_args(&remote, &args);
+        assert_eq!(
+            args,
+            [
+                "--duration=180",
+                "--positionsFile=/srv/ns3/abc.csv",
+                "--seed=4"
+            ]
+        );
+        assert_eq!(
+            positions,
+            Some((
+                PathBuf::from("/home/me/positions/abc.csv"),
+                PathBuf::from("/srv/ns3/abc.csv")
+            ))
+        );
+
+        let command = ssh(&remote, "true");
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            ["-o", "Port=2222", "-o", "BatchMode=yes", "ns3-box", "true"]
+        );
+    }
+}
@@ -1,27 +1,59 @@
-use std::process::Command;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Set when stdout is reserved for machine readable output, such as with `--ndjson`. Progress
+/// messages and the output of child processes then go to stderr instead
+pub static LOG_TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// Prints a progress message to stdout, or to stderr when [`LOG_TO_STDERR`] is set
+#[macro_export]
+macro_rules! log {
+    ($($arg:tt)*) => {
+        if $crate::util::LOG_TO_STDERR.load(std::sync::atomic::Ordering::Relaxed) {
+            eprintln!($($arg)*);
+        } else {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// Where child processes should write their stdout so it doesn't mix with machine readable output
+pub fn child_stdout() -> Stdio {
+    if LOG_TO_STDERR.load(Ordering::Relaxed) {
+        Stdio::from(std::io::stderr())
+    } else {
+        Stdio::inherit()
+    }
+}
 
 pub fn run_waf_command(
     path: &str,
     command: &str,
     env: std::collections::HashMap<&str, &str>,
 ) -> Result<(), crate::Error> {
-    let mut waf_path = std::fs::canonicalize(path).unwrap();
-    waf_path.push("waf");
+    let path = resolve_path(path);
+    let waf_path = path.join("waf");
     let arg = format!("{} {}", waf_path.to_str().unwrap(), command);
-    println!("Running: {}", arg);
+    log!("Running: {}", arg);
 
     if Command::new("bash")
-        .current_dir(path)
+        .current_dir(&path)
         .arg("-c")
         .arg(arg)
         .envs(env)
+        .stdout(child_stdout())
         .spawn()?
         .wait()?
         .success()
     {
         Ok(())
     } else {
-        Err(format!("Failed to run command: waf {}", command).into())
+        Err(crate::Error::Build(format!(
+            "Failed to run command: waf {}",
+            command
+        )))
     }
 }
 
@@ -31,7 +63,7 @@ pub fn run_waf_command(
 macro_rules! map {
     // map-like
     ($($k:expr => $v:expr),* $(,)?) => {
-        std::iter::Iterator::collect(std::array::IntoIter::new([$(($k, $v),)*]))
+        std::iter::Iterator::collect(std::iter::IntoIterator::into_iter([$(($k, $v),)*]))
     };
 }
 
@@ -71,6 +103,144 @@ where
     lerp(right_min, right_max, f)
 }
 
+/// Widens `range` on both sides by `margin` times its span
+pub fn pad_range(range: std::ops::Range<f64>, margin: f64) -> std::ops::Range<f64> {
+    let pad = (range.end - range.start) * margin;
+    (range.start - pad)..(range.end + pad)
+}
+
+/// Turns a user supplied path into an absolute one. A leading `~` is replaced with the home
+/// directory and relative paths are taken relative to the directory the optimizer was started
+/// from. Paths that exist are also canonicalized
+pub fn resolve_path(path: impl AsRef<Path>) -> PathBuf {
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    let cwd = std::env::current_dir().expect("Failed to get the current directory");
+    resolve_path_from(path.as_ref(), home.as_deref(), &cwd)
+}
+
+fn resolve_path_from(path: &Path, home: Option<&Path>, cwd: &Path) -> PathBuf {
+    let expanded = match (path.strip_prefix("~"), home) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path.to_path_buf(),
+    };
+    let absolute = cwd.join(expanded);
+    std::fs::canonicalize(&absolute).unwrap_or(absolute)
+}
+
+/// If `name` matches `pattern`, where `*` matches any run of characters and `?` any single one
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let (pattern, name): (Vec<char>, Vec<char>) =
+        (pattern.chars().collect(), name.chars().collect());
+    // The pattern position just after the last `*` and the name position it was tried at
+    let (mut p, mut n, mut star) = (0, 0, None);
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p + 1, n));
+            p += 1;
+        } else if let Some((after_star, tried)) = star {
+            // Let the `*` take one more character
+            p = after_star;
+            n = tried + 1;
+            star = Some((after_star, n));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// The files matching `pattern`, sorted. Only the file name may contain wildcards, for patterns
+/// that the shell didn't expand because they were quoted. Patterns without wildcards are returned
+/// as they are
+pub fn expand_glob(pattern: &str) -> Vec<PathBuf> {
+    let path = Path::new(pattern);
+    let file_pattern = match path.file_name().and_then(|name| name.to_str()) {
+        Some(name) if name.contains(['*', '?']) => name,
+        _ => return vec![path.to_path_buf()],
+    };
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut matches: Vec<PathBuf> = std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_str()
+                .is_some_and(|name| wildcard_match(file_pattern, name))
+        })
+        .map(|entry| dir.join(entry.file_name()))
+        .collect();
+    matches.sort();
+    matches
+}
+
+/// Opens a positions file for reading, decompressing it if its name ends in `.gz`
+pub fn open_positions(path: &Path) -> std::io::Result<Box<dyn BufRead>> {
+    let file = std::fs::File::open(path)?;
+    if path.extension().is_some_and(|extension| extension == "gz") {
+        Ok(Box::new(BufReader::new(flate2::read::GzDecoder::new(file))))
+    } else {
+        Ok(Box::new(BufReader::new(file)))
+    }
+}
+
+/// Whether the positions file at `path` has any non-blank lines after its header. A simulation
+/// that exits early can still exit successfully and leave an empty or header-only file behind
+pub fn has_positions(path: &Path) -> std::io::Result<bool> {
+    for line in open_positions(path)?.lines().skip(1) {
+        if !line?.trim().is_empty() {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Replaces the file at `path` with a gzipped copy named `<path>.gz`, returning the new path
+pub fn gzip_file(path: &Path) -> std::io::Result<PathBuf> {
+    let mut compressed_path = path.as_os_str().to_owned();
+    compressed_path.push(".gz");
+    let compressed_path = PathBuf::from(compressed_path);
+    let mut encoder = flate2::write::GzEncoder::new(
+        std::fs::File::create(&compressed_path)?,
+        flate2::Compression::default(),
+    );
+    std::io::copy(&mut std::fs::File::open(path)?, &mut encoder)?;
+    encoder.finish()?;
+    std::fs::remove_file(path)?;
+    Ok(compressed_path)
+}
+
+/// The bytes available to unprivileged users on the filesystem holding `path`. `None` if it can't
+/// be determined, which is always the case outside Unix
+#[cfg(unix)]
+pub fn free_disk_space(path: &Path) -> Option<u64> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stats = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // Safety: `path` is a valid C string and statvfs only writes to `stats`, which is read only
+    // after it reports success
+    let stats = unsafe {
+        if libc::statvfs(path.as_ptr(), stats.as_mut_ptr()) != 0 {
+            return None;
+        }
+        stats.assume_init()
+    };
+    Some((stats.f_bavail as u64).saturating_mul(stats.f_frsize as u64))
+}
+
+#[cfg(not(unix))]
+pub fn free_disk_space(_path: &Path) -> Option<u64> {
+    None
+}
+
 pub struct RangeSmoother<T>
 where
     T: Copy + From<i32>,
@@ -107,10 +277,143 @@ where
     }
 }
 
+/// Estimates a quantile of a stream of values without storing them, using the P² algorithm by
+/// Jain and Chlamtac. Five markers track the minimum, the maximum, the quantile and the points
+/// halfway to it on either side, and are moved towards their desired positions as values arrive
+pub struct P2Quantile {
+    quantile: f64,
+    count: usize,
+    /// The height of each marker. Holds the values themselves until there are five of them
+    heights: [f64; 5],
+    positions: [f64; 5],
+    desired: [f64; 5],
+}
+
+impl P2Quantile {
+    /// Tracks the `quantile` (0 to 1) of the values added
+    pub fn new(quantile: f64) -> Self {
+        Self {
+            quantile,
+            count: 0,
+            heights: [0.0; 5],
+            positions: [1.0, 2.0, 3.0, 4.0, 5.0],
+            desired: [
+                1.0,
+                1.0 + 2.0 * quantile,
+                1.0 + 4.0 * quantile,
+                3.0 + 2.0 * quantile,
+                5.0,
+            ],
+        }
+    }
+
+    /// Adds `value` to the stream. Values that aren't finite are ignored
+    pub fn add(&mut self, value: f64) {
+        if !value.is_finite() {
+            return;
+        }
+        if self.count < 5 {
+            self.heights[self.count] = value;
+            self.count += 1;
+            if self.count == 5 {
+                self.heights.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            }
+            return;
+        }
+        self.count += 1;
+
+        let h = &mut self.heights;
+        let cell = if value < h[0] {
+            h[0] = value;
+            0
+        } else if value >= h[4] {
+            h[4] = value;
+            3
+        } else {
+            (0..4).find(|i| value < h[i + 1]).unwrap()
+        };
+        for position in &mut self.positions[cell + 1..] {
+            *position += 1.0;
+        }
+        let q = self.quantile;
+        for (desired, increment) in
+            self.desired
+                .iter_mut()
+                .zip([0.0, q / 2.0, q, (1.0 + q) / 2.0, 1.0])
+        {
+            *desired += increment;
+        }
+
+        let n = &mut self.positions;
+        for i in 1..4 {
+            let offset = self.desired[i] - n[i];
+            if (offset >= 1.0 && n[i + 1] - n[i] > 1.0)
+                || (offset <= -1.0 && n[i - 1] - n[i] < -1.0)
+            {
+                let d = offset.signum();
+                let parabolic = h[i]
+                    + d / (n[i + 1] - n[i - 1])
+                        * ((n[i] - n[i - 1] + d) * (h[i + 1] - h[i]) / (n[i + 1] - n[i])
+                            + (n[i + 1] - n[i] - d) * (h[i] - h[i - 1]) / (n[i] - n[i - 1]));
+                h[i] = if h[i - 1] < parabolic && parabolic < h[i + 1] {
+                    parabolic
+                } else {
+                    let j = if d > 0.0 { i + 1 } else { i - 1 };
+                    h[i] + d * (h[j] - h[i]) / (n[j] - n[i])
+                };
+                n[i] += d;
+            }
+        }
+    }
+
+    /// The current estimate, or `None` before any values were added. Exact until there are more
+    /// than five values, and settles in over the next few dozen
+    pub fn estimate(&self) -> Option<f64> {
+        match self.count {
+            0 => None,
+            1..=5 => {
+                let mut values = self.heights[..self.count].to_vec();
+                values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                let rank = (self.quantile * (self.count - 1) as f64).round() as usize;
+                Some(values[rank])
+            }
+            _ => Some(self.heights[2]),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn streaming_quantiles() {
+        let mut median = P2Quantile::new(0.5);
+        let mut p90 = P2Quantile::new(0.9);
+        assert_eq!(median.estimate(), None);
+        for value in [3.0, 1.0, 2.0] {
+            median.add(value);
+        }
+        assert_eq!(median.estimate(), Some(2.0));
+        for value in [5.0, 4.0] {
+            median.add(value);
+            p90.add(value);
+        }
+        assert_eq!(median.estimate(), Some(3.0));
+        assert_eq!(p90.estimate(), Some(5.0));
+
+        let mut median = P2Quantile::new(0.5);
+        // Every value from 0 to 9999 once, in a scrambled order
+        for i in 0..10000 {
+            let value = ((i * 7919) % 10000) as f64;
+            median.add(value);
+            p90.add(value);
+        }
+        median.add(f64::NAN);
+        assert!((median.estimate().unwrap() - 5000.0).abs() < 100.0);
+        assert!((p90.estimate().unwrap() - 9000.0).abs() < 100.0);
+    }
+
     #[test]
     fn basic_smooth() {
         let smoother = RangeSmoother::new(4, &[0, 5, 6, 7]);
@@ -136,4 +439,133 @@ mod tests {
         let ranges: Vec<i32> = smoother.ranges().collect();
         assert_eq!(ranges.as_slice(), &[10, 10, 10, 15, 15, 15, 25, 25]);
     }
+
+    #[test]
+    fn pad() {
+        assert_eq!(pad_range(0.0..18.0, 0.0), 0.0..18.0);
+        assert_eq!(pad_range(0.0..10.0, 0.1), -1.0..11.0);
+        assert_eq!(pad_range(2.0..4.0, 0.5), 1.0..5.0);
+    }
+
+    #[test]
+    fn resolve_tilde() {
+        let home = Path::new("/nonexistent/home");
+        let cwd = Path::new("/nonexistent/cwd");
+        assert_eq!(
+            resolve_path_from(Path::new("~/ns3"), Some(home), cwd),
+            PathBuf::from("/nonexistent/home/ns3")
+        );
+        assert_eq!(
+            resolve_path_from(Path::new("~"), Some(home), cwd),
+            PathBuf::from("/nonexistent/home")
+        );
+        // Only a whole leading component is expanded
+        assert_eq!(
+            resolve_path_from(Path::new("~ns3"), Some(home), cwd),
+            PathBuf::from("/nonexistent/cwd/~ns3")
+        );
+        assert_eq!(
+            resolve_path_from(Path::new("~/ns3"), None, cwd),
+            PathBuf::from("/nonexistent/cwd/~/ns3")
+        );
+    }
+
+    #[test]
+    fn resolve_relative() {
+        let cwd = Path::new("/nonexistent/cwd");
+        assert_eq!(
+            resolve_path_from(Path::new("NS3/build"), None, cwd),
+            PathBuf::from("/nonexistent/cwd/NS3/build")
+        );
+        assert_eq!(
+            resolve_path_from(Path::new("/opt/ns3"), None, cwd),
+            PathBuf::from("/opt/ns3")
+        );
+
+        let dir = std::env::temp_dir();
+        assert_eq!(
+            resolve_path_from(Path::new("."), None, &dir),
+            std::fs::canonicalize(&dir).unwrap()
+        );
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(wildcard_match("*.csv", "output-1.csv"));
+        assert!(wildcard_match("output-?.csv", "output-1.csv"));
+        assert!(wildcard_match("out*1*", "output-1.csv"));
+        assert!(wildcard_match("*", ""));
+        assert!(!wildcard_match("*.csv", "output-1.json"));
+        assert!(!wildcard_match("output-?.csv", "output-10.csv"));
+        assert!(!wildcard_match("a", "ab"));
+    }
+
+    #[test]
+    fn glob_expansion() {
+        let dir = std::env::temp_dir().join(format!("glob-expansion-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["b.csv", "a.csv", "c.json"] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+        let pattern = dir.join("*.csv");
+        assert_eq!(
+            expand_glob(pattern.to_str().unwrap()),
+            vec![dir.join("a.csv"), dir.join("b.csv")]
+        );
+        assert_eq!(expand_glob("plain.csv"), vec![PathBuf::from("plain.csv")]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn gzipped_positions() {
+        let dir = std::env::temp_dir().join(format!("gzipped-positions-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let csv = "Time (s),IP Address, X (m), Y (m), Z (m)\n0,10.1.1.1,1,2,3,\n";
+        let path = dir.join("positions.csv");
+        std::fs::write(&path, csv).unwrap();
+
+        let compressed = gzip_file(&path).unwrap();
+        assert_eq!(compressed, dir.join("positions.csv.gz"));
+        assert!(!path.exists());
+        assert!(std::fs::read(&compressed).unwrap() != csv.as_bytes());
+        std::fs::write(dir.join("plain.csv"), "plain\n").unwrap();
+        for (path, contents) in [(&compressed, csv), (&dir.join("plain.csv"), "plain\n")] {
+            let mut read = String::new();
+            std::io::Read::read_to_string(&mut open_positions(path).unwrap(), &mut read).unwrap();
+            assert_eq!(read, contents);
+        }
+        let data =
+            crate::position_parser::SimulationData::load(&compressed, &Default::default(), false)
+                .unwrap();
+        assert_eq!(data.uavs.len(), 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn empty_positions() {
+        let dir = std::env::temp_dir().join(format!("empty-positions-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("positions.csv");
+        for (contents, expected) in [
+            ("", false),
+            ("Time (s),IP Address, X (m), Y (m), Z (m)\n", false),
+            ("Time (s),IP Address, X (m), Y (m), Z (m)\n\n  \n", false),
+            (
+                "Time (s),IP Address, X (m), Y (m), Z (m)\n0,10.1.1.1,1,2,3,\n",
+                true,
+            ),
+        ] {
+            std::fs::write(&path, contents).unwrap();
+            assert_eq!(has_positions(&path).unwrap(), expected, "{:?}", contents);
+        }
+        assert!(has_positions(&gzip_file(&path).unwrap()).unwrap());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn disk_space() {
+        assert!(free_disk_space(&std::env::temp_dir()).is_some());
+        assert_eq!(free_disk_space(Path::new("/nonexistent/dir")), None);
+    }
 }
@@ -0,0 +1,186 @@
+use crate::config::WarmupSampler;
+
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::ops::Range;
+
+/// The Sobol direction numbers from Joe and Kuo's new-joe-kuo-6.21201 table for every dimension
+/// after the first, as (degree, coefficients, initial numbers). The first dimension is the van der
+/// Corput sequence
+const SOBOL_DIRECTIONS: [(u32, u32, &[u32]); 9] = [
+    (1, 0, &[1]),
+    (2, 1, &[1, 3]),
+    (3, 1, &[1, 3, 1]),
+    (3, 2, &[1, 1, 1]),
+    (4, 1, &[1, 1, 3, 3]),
+    (4, 4, &[1, 3, 5, 13]),
+    (5, 2, &[1, 1, 5, 5, 17]),
+    (5, 4, &[1, 1, 5, 5, 5]),
+    (5, 7, &[1, 1, 7, 11, 19]),
+];
+
+/// How many parameters the Sobol sampler supports
+pub const SOBOL_MAX_DIMENSIONS: usize = SOBOL_DIRECTIONS.len() + 1;
+
+const BITS: usize = 32;
+
+/// `count` parameter sets spread over `ranges` by `sampler`
+pub fn sample(
+    sampler: WarmupSampler,
+    count: usize,
+    ranges: &[Range<f64>],
+    rng: &mut impl Rng,
+) -> Vec<Vec<f64>> {
+    let dimensions = ranges.len();
+    let points = match sampler {
+        WarmupSampler::Random => (0..count)
+            .map(|_| (0..dimensions).map(|_| rng.gen::<f64>()).collect())
+            .collect(),
+        WarmupSampler::Sobol => {
+            // A random digital shift keeps the spread of the sequence but gives each run
+            // different points, and moves the first one off the corner of the space
+            let shifts: Vec<u32> = (0..dimensions).map(|_| rng.gen()).collect();
+            sobol(count, &shifts)
+        }
+        WarmupSampler::LatinHypercube => latin_hypercube(count, dimensions, rng),
+    };
+    points
+        .into_iter()
+        .map(|point| {
+            point
+                .iter()
+                .zip(ranges)
+                .map(|(x, range)| range.start + x * (range.end - range.start))
+                .collect()
+        })
+        .collect()
+}
+
+/// The first `count` points of the Sobol sequence in the unit cube, with one dimension per
+/// shift. Each coordinate is XORed with its dimension's shift
+fn sobol(count: usize, shifts: &[u32]) -> Vec<Vec<f64>> {
+    assert!(
+        shifts.len() <= SOBOL_MAX_DIMENSIONS,
+        "The Sobol sampler supports at most {} dimensions",
+        SOBOL_MAX_DIMENSIONS
+    );
+    let directions: Vec<[u32; BITS]> = (0..shifts.len()).map(direction_numbers).collect();
+    let mut current = vec![0u32; shifts.len()];
+    let mut points = Vec::with_capacity(count);
+    for i in 0..count {
+        if i > 0 {
+            // Gray code order: each point flips the direction number of the lowest zero bit of
+            // the previous index
+            let bit = (!(i - 1)).trailing_zeros() as usize;
+            for (x, v) in current.iter_mut().zip(&directions) {
+                *x ^= v[bit];
+            }
+        }
+        points.push(
+            current
+                .iter()
+                .zip(shifts)
+                .map(|(x, shift)| f64::from(x ^ shift) / 2f64.powi(BITS as i32))
+                .collect(),
+        );
+    }
+    points
+}
+
+/// The direction numbers of Sobol dimension `dimension`, counting from 0, scaled to 32 bits
+fn direction_numbers(dimension: usize) -> [u32; BITS] {
+    let mut v = [0u32; BITS];
+    if dimension == 0 {
+        for (k, v) in v.iter_mut().enumerate() {
+            *v = 1 << (BITS - 1 - k);
+        }
+        return v;
+    }
+    let (degree, coefficients, initial) = SOBOL_DIRECTIONS[dimension - 1];
+    let s = degree as usize;
+    for k in 0..BITS {
+        v[k] = if k < s {
+            initial[k] << (BITS - 1 - k)
+        } else {
+            let mut next = v[k - s] ^ (v[k - s] >> s);
+            for i in 1..s {
+                if (coefficients >> (s - 1 - i)) & 1 == 1 {
+                    next ^= v[k - i];
+                }
+            }
+            next
+        };
+    }
+    v
+}
+
+/// `count` points in the unit cube where each dimension has exactly one point in each of `count`
+/// equal slices, at a random place inside it
+fn latin_hypercube(count: usize, dimensions: usize, rng: &mut impl Rng) -> Vec<Vec<f64>> {
+    let mut points = vec![Vec::with_capacity(dimensions); count];
+    for _ in 0..dimensions {
+        let mut slices: Vec<usize> = (0..count).collect();
+        slices.shuffle(rng);
+        for (point, slice) in points.iter_mut().zip(slices) {
+            point.push((slice as f64 + rng.gen::<f64>()) / count as f64);
+        }
+    }
+    points
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    #[test]
+    fn sobol_sequence() {
+        let points = sobol(8, &[0, 0, 0]);
+        let column = |d: usize| points.iter().map(|p| p[d]).collect::<Vec<_>>();
+        assert_eq!(
+            column(0),
+            vec![0.0, 0.5, 0.75, 0.25, 0.375, 0.875, 0.625, 0.125]
+        );
+        assert_eq!(
+            column(1),
+            vec![0.0, 0.5, 0.25, 0.75, 0.375, 0.875, 0.125, 0.625]
+        );
+
+        // The first 2^k points put one point in each of the 2^k slices of every dimension, with
+        // or without a shift
+        let mut rng = rand::rngs::StdRng::seed_from_u64(3);
+        let shifts: Vec<u32> = (0..SOBOL_MAX_DIMENSIONS).map(|_| rng.gen()).collect();
+        let points = sobol(64, &shifts);
+        for d in 0..SOBOL_MAX_DIMENSIONS {
+            let mut slices: Vec<usize> = points.iter().map(|p| (p[d] * 64.0) as usize).collect();
+            slices.sort_unstable();
+            assert_eq!(slices, (0..64).collect::<Vec<_>>(), "dimension {}", d);
+        }
+    }
+
+    #[test]
+    fn latin_hypercube_slices() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(5);
+        let points = sample(
+            WarmupSampler::LatinHypercube,
+            10,
+            &[0.0..10.0, 5.0..6.0],
+            &mut rng,
+        );
+        assert_eq!(points.len(), 10);
+        let mut first: Vec<usize> = points.iter().map(|p| p[0] as usize).collect();
+        first.sort_unstable();
+        assert_eq!(first, (0..10).collect::<Vec<_>>());
+        let mut second: Vec<usize> = points
+            .iter()
+            .map(|p| ((p[1] - 5.0) * 10.0) as usize)
+            .collect();
+        second.sort_unstable();
+        assert_eq!(second, (0..10).collect::<Vec<_>>());
+
+        let points = sample(WarmupSampler::Random, 4, &[2.0..3.0], &mut rng);
+        assert!(points
+            .iter()
+            .all(|p| p.len() == 1 && (2.0..3.0).contains(&p[0])));
+    }
+}
@@ -0,0 +1,154 @@
+//! Runs the whole optimizer against a fake simulation that writes a positions file straight from
+//! its parameters, so the orchestration, scoring and exports can be checked without NS3
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Four UAVs around a central node at a radius that is exactly the 7.5m target when `a` and `r`
+/// are both 9 and grows as either moves away from it
+const FAKE_SIMULATION: &str = r#"#!/bin/bash
+for arg in "$@"; do
+    case $arg in
+        --positionsFile=*) out="${arg#*=}";;
+        --a=*) a="${arg#*=}";;
+        --r=*) r="${arg#*=}";;
+    esac
+done
+awk -v a="$a" -v r="$r" 'BEGIN {
+    da = a - 9; if (da < 0) da = -da
+    dr = r - 9; if (dr < 0) dr = -dr
+    radius = 7.5 + 0.5 * da + 0.5 * dr
+    print "Time (s),IP Address, X (m), Y (m), Z (m)"
+    for (step = 0; step <= 30; step++) {
+        t = step / 10
+        printf "%g,10.1.1.1,0,0,0,\n", t
+        printf "%g,10.1.1.2,%g,0,0,\n", t, radius
+        printf "%g,10.1.1.3,0,%g,0,\n", t, radius
+        printf "%g,10.1.1.4,%g,0,0,\n", t, -radius
+        printf "%g,10.1.1.5,0,%g,0,\n", t, -radius
+    }
+}' > "$out"
+"#;
+
+/// A fresh directory for one test run
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("work")).unwrap();
+    dir
+}
+
+fn write_fake_simulation(dir: &Path) -> PathBuf {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = dir.join("fake-simulation.sh");
+    std::fs::write(&path, FAKE_SIMULATION).unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    path
+}
+
+#[test]
+fn grid_sweep_against_fake_simulation() {
+    let dir = scratch_dir("parameter-optimizer-pipeline");
+    let binary = write_fake_simulation(&dir);
+    let config = serde_json::json!({
+        "install": {
+            "working_dir": dir.join("work"),
+            "binary": binary,
+            "lib_dir": dir,
+        },
+        "search": { "Grid": { "steps": 3 } },
+    });
+    std::fs::write(dir.join("config.json"), config.to_string()).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_parameter_optimizer"))
+        .current_dir(&dir)
+        .args(["--config", "config.json", "--out-dir", "out"])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "optimizer failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let out = dir.join("out");
+    let files: Vec<String> = std::fs::read_dir(&out)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    for file in ["manifest.json", "hot_cold.png", "error_time.png", "best"] {
+        assert!(
+            files.iter().any(|name| name == file),
+            "{} was not written",
+            file
+        );
+    }
+    let results = files
+        .iter()
+        .find(|name| name.starts_with("results-") && name.ends_with(".csv"))
+        .expect("results csv was not written");
+    assert!(files
+        .iter()
+        .any(|name| name.starts_with("output-") && name.ends_with(".json")));
+
+    // A 3 step grid over 0..18 tries 0, 9 and 18 for each parameter
+    let csv = std::fs::read_to_string(out.join(results)).unwrap();
+    let mut lines = csv.lines();
+    let header: Vec<&str> = lines.next().unwrap().split(',').collect();
+    let column = |name: &str| header.iter().position(|column| *column == name).unwrap();
+    let (a, r, fitness) = (column("a"), column("r"), column("fitness"));
+    let rows: Vec<Vec<f64>> = lines
+        .map(|line| {
+            line.split(',')
+                .take(column("time"))
+                .map(|value| value.parse().unwrap_or(f64::NAN))
+                .collect()
+        })
+        .collect();
+    assert_eq!(rows.len(), 9);
+    let best = rows
+        .iter()
+        .min_by(|x, y| x[fitness].partial_cmp(&y[fitness]).unwrap())
+        .unwrap();
+    assert_eq!((best[a], best[r]), (9.0, 9.0));
+    assert!(best[fitness] < 1.0);
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn every_simulation_failing() {
+    let dir = scratch_dir("parameter-optimizer-failing");
+    let binary = dir.join("failing-simulation.sh");
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::write(&binary, "#!/bin/bash\necho 'segfault' >&2\nexit 1\n").unwrap();
+        std::fs::set_permissions(&binary, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+    let config = serde_json::json!({
+        "install": {
+            "working_dir": dir.join("work"),
+            "binary": binary,
+            "lib_dir": dir,
+        },
+        "search": { "Grid": { "steps": 2 } },
+    });
+    std::fs::write(dir.join("config.json"), config.to_string()).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_parameter_optimizer"))
+        .current_dir(&dir)
+        .args(["--config", "config.json", "--out-dir", "out"])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(
+        stderr.contains("0 successful evaluations"),
+        "unexpected output: {}",
+        stderr
+    );
+    assert!(!dir.join("out/hot_cold.png").exists());
+
+    std::fs::remove_dir_all(dir).unwrap();
+}